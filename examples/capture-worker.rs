@@ -0,0 +1,53 @@
+//! Captures frames on a background thread and receives them on the main thread.
+//!
+//! Uses [`linuxvideo::stream::CaptureWorker`] with a small channel that drops the oldest frame when
+//! the (deliberately slow) main thread cannot keep up.
+
+use std::{env, path::Path, thread, time::Duration};
+
+use anyhow::anyhow;
+use linuxvideo::{format::Format, stream::OverflowPolicy, BufType, Device};
+
+/// Time it takes to "process" each frame.
+const PROCESSING_TIME: Duration = Duration::from_millis(50);
+
+/// Number of frames to receive before stopping.
+const FRAMES: usize = 100;
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args = env::args_os().skip(1);
+
+    let path = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: capture-worker <device>"))?;
+
+    let device = Device::open(Path::new(&path))?;
+
+    let Format::VideoCapture(fmt) = device.format(BufType::VIDEO_CAPTURE)? else {
+        unreachable!()
+    };
+    let capture = device.video_capture(fmt)?;
+    println!("negotiated format: {:?}", capture.format());
+
+    let worker = capture
+        .into_stream()?
+        .into_worker(2, OverflowPolicy::DropOldest)?;
+
+    for _ in 0..FRAMES {
+        let frame = worker.recv()?;
+        println!(
+            "frame #{} at {:?}: {} bytes",
+            frame.sequence(),
+            frame.timestamp(),
+            frame.data().len(),
+        );
+        thread::sleep(PROCESSING_TIME);
+    }
+
+    println!("{} frames dropped by the worker", worker.dropped_frames());
+    worker.stop()?;
+
+    Ok(())
+}
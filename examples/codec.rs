@@ -0,0 +1,74 @@
+//! Encodes test frames with a stateful encoder, then decodes the result with a stateful decoder.
+//!
+//! Works with the encoder and decoder devices of the `vicodec` test driver (`modprobe vicodec`).
+
+use std::{env, time::Duration};
+
+use anyhow::anyhow;
+use linuxvideo::{
+    format::{PixFormat, PixelFormat},
+    stream::ReadBufferView,
+    Device,
+};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+const FRAMES: u32 = 10;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args_os().skip(1);
+
+    let usage = || anyhow!("usage: codec <encoder device> <decoder device>");
+    let encoder_path = args.next().ok_or_else(usage)?;
+    let decoder_path = args.next().ok_or_else(usage)?;
+
+    let encoder = Device::open(encoder_path)?;
+    for format in encoder.coded_formats() {
+        println!("encoder: {:?}", format?);
+    }
+    let mut encoder = encoder.video_encoder(
+        PixFormat::new(WIDTH, HEIGHT, PixelFormat::YUYV),
+        PixFormat::new(WIDTH, HEIGHT, PixelFormat::FWHT),
+    )?;
+    println!("frame format: {:?}", encoder.frame_format());
+    println!("coded format: {:?}", encoder.coded_format());
+
+    let mut chunks = Vec::new();
+    let mut on_chunk = |chunk: ReadBufferView<'_>| {
+        chunks.push(chunk.to_vec());
+        Ok(())
+    };
+    for i in 0..FRAMES {
+        encoder.encode(
+            |mut buf| {
+                buf.fill(i as u8 * 16);
+                buf.set_timestamp(Duration::from_millis(i.into()));
+                Ok(())
+            },
+            &mut on_chunk,
+        )?;
+    }
+    encoder.drain(&mut on_chunk)?;
+    println!("encoded {} chunks", chunks.len());
+
+    let mut decoder = Device::open(decoder_path)?.video_decoder(PixFormat::new(
+        WIDTH,
+        HEIGHT,
+        PixelFormat::FWHT,
+    ))?;
+    let mut on_frame = |frame: ReadBufferView<'_>| {
+        println!(
+            "decoded frame {:?}: {} bytes",
+            frame.timestamp(),
+            frame.len()
+        );
+        Ok(())
+    };
+    for (i, chunk) in chunks.iter().enumerate() {
+        decoder.decode(chunk, Duration::from_millis(i as u64), &mut on_frame)?;
+    }
+    decoder.drain(&mut on_frame)?;
+    println!("frame format: {:?}", decoder.frame_format());
+
+    Ok(())
+}
@@ -42,7 +42,8 @@ fn main() -> anyhow::Result<()> {
     let mut frames = 0;
     let mut time = Instant::now();
     loop {
-        capture.read(&mut buf)?;
+        // Each `read` returns (at most) one frame.
+        let _len = capture.read(&mut buf)?;
 
         frames += 1;
         print!(".");
@@ -0,0 +1,75 @@
+//! Captures video while printing device events, like control changes made by other applications.
+//!
+//! Control values can be changed while this is running, for example with the `control` example.
+
+use std::{env, path::Path, time::Duration};
+
+use anyhow::anyhow;
+use linuxvideo::{
+    event::{EventKind, EventSubFlags, EventType, Subscription},
+    format::Format,
+    stream::Readiness,
+    BufType, Device,
+};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args = env::args_os().skip(1);
+
+    let path = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: events <device>"))?;
+
+    let device = Device::open(Path::new(&path))?;
+
+    let events = device.events();
+    for desc in device.controls() {
+        let desc = desc?;
+        events.subscribe(Subscription::control(desc.id()).flags(EventSubFlags::SEND_INITIAL))?;
+    }
+    for ty in [
+        EventType::SOURCE_CHANGE,
+        EventType::EOS,
+        EventType::FRAME_SYNC,
+    ] {
+        // Most drivers only support some of these.
+        if let Err(e) = events.subscribe(Subscription::new(ty)) {
+            println!("cannot subscribe to {ty:?} events: {e}");
+        }
+    }
+
+    let Format::VideoCapture(fmt) = device.format(BufType::VIDEO_CAPTURE)? else {
+        unreachable!()
+    };
+    let mut stream = device.video_capture(fmt)?.into_stream()?;
+
+    loop {
+        let ready = stream.wait_timeout(Duration::from_secs(1))?;
+        if ready.is_empty() {
+            println!("no frames or events within 1 second");
+        }
+        if ready.contains(Readiness::EVENT) {
+            while let Some(event) = stream.events().try_dequeue()? {
+                match event.kind() {
+                    EventKind::Ctrl(ctrl) => println!(
+                        "{:?} = {} ({:?}, range {}-{})",
+                        ctrl.id(),
+                        ctrl.value(),
+                        ctrl.changes(),
+                        ctrl.minimum(),
+                        ctrl.maximum(),
+                    ),
+                    EventKind::FrameSync(seq) => println!("frame #{seq} started"),
+                    kind => println!("{kind:?}"),
+                }
+            }
+        }
+        if ready.contains(Readiness::BUFFER) {
+            stream.dequeue(|buf| {
+                println!("frame #{} captured", buf.sequence());
+                Ok(())
+            })?;
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Prints V4L2 devices as they are plugged in and removed.
+//!
+//! Uses [`linuxvideo::hotplug::DeviceMonitor`].
+
+use linuxvideo::hotplug::{DeviceMonitor, HotplugEvent};
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut monitor = DeviceMonitor::new()?;
+    for info in monitor.devices() {
+        println!(
+            "present: {} ({}, {})",
+            info.path().display(),
+            info.capabilities().card(),
+            info.capabilities().bus_info(),
+        );
+    }
+
+    loop {
+        match monitor.next_event()? {
+            HotplugEvent::Added(info) => println!(
+                "added:   {} ({}, {})",
+                info.path().display(),
+                info.capabilities().card(),
+                info.capabilities().bus_info(),
+            ),
+            HotplugEvent::Removed(info) => println!("removed: {}", info.path().display()),
+            _ => {}
+        }
+    }
+}
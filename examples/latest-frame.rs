@@ -0,0 +1,41 @@
+//! Simulates a slow consumer that always processes the most recent frame.
+//!
+//! Uses [`linuxvideo::stream::ReadStream::dequeue_latest`] to skip frames that were captured while
+//! the previous frame was being processed, and prints how many frames were skipped.
+
+use std::{env, path::Path, thread, time::Duration};
+
+use anyhow::anyhow;
+use linuxvideo::{format::Format, stream::StreamBuilder, BufType, Device};
+
+/// Time it takes to "process" each frame.
+const PROCESSING_TIME: Duration = Duration::from_millis(100);
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args = env::args_os().skip(1);
+
+    let path = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: latest-frame <device>"))?;
+
+    let device = Device::open(Path::new(&path))?;
+
+    let Format::VideoCapture(fmt) = device.format(BufType::VIDEO_CAPTURE)? else {
+        unreachable!()
+    };
+    let capture = device.video_capture(fmt)?;
+    println!("negotiated format: {:?}", capture.format());
+
+    // More buffers allow the driver to keep capturing while we are busy.
+    let mut stream = capture.into_stream_with(StreamBuilder::new().mmap(4))?;
+
+    loop {
+        let (sequence, skipped) = stream.dequeue_latest(|buf| {
+            thread::sleep(PROCESSING_TIME);
+            Ok(buf.sequence())
+        })?;
+        println!("processed frame #{sequence}, skipped {skipped} stale frames");
+    }
+}
@@ -4,7 +4,7 @@ use linuxvideo::Device;
 
 fn main() -> io::Result<()> {
     for res in linuxvideo::list()? {
-        match res.and_then(|device| list_device(device)) {
+        match res.and_then(list_device) {
             Ok(()) => {}
             Err(e) => {
                 eprintln!("skipping device due to error: {}", e);
@@ -0,0 +1,55 @@
+//! Passes frames through a memory-to-memory device and prints some info about the results.
+//!
+//! Works with the `vim2m` test driver (`modprobe vim2m`).
+
+use std::{env, time::Instant};
+
+use anyhow::{anyhow, bail};
+use linuxvideo::{
+    format::{PixFormat, PixelFormat},
+    CapabilityFlags, Device,
+};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+const INPUT_FORMAT: PixelFormat = PixelFormat::RGB3;
+const OUTPUT_FORMAT: PixelFormat = PixelFormat::YUYV;
+const FRAMES: u32 = 30;
+
+fn main() -> anyhow::Result<()> {
+    let mut args = env::args_os().skip(1);
+
+    let path = args.next().ok_or_else(|| anyhow!("usage: m2m <device>"))?;
+
+    let device = Device::open(path)?;
+    let caps = &device.capabilities()?.device_capabilities();
+    println!("device capabilities: {caps:?}");
+    if !caps.contains(CapabilityFlags::VIDEO_M2M) {
+        bail!("selected device does not support `VIDEO_M2M` capability");
+    }
+
+    let m2m = device.video_m2m(
+        PixFormat::new(WIDTH, HEIGHT, INPUT_FORMAT),
+        PixFormat::new(WIDTH, HEIGHT, OUTPUT_FORMAT),
+    )?;
+    println!("output format: {:?}", m2m.output_format());
+    println!("capture format: {:?}", m2m.capture_format());
+
+    let size = m2m.output_format().size_image() as usize;
+    let mut stream = m2m.into_stream()?;
+
+    for i in 0..FRAMES {
+        let start = Instant::now();
+        let (len, sequence) = stream.process(
+            |mut buf| {
+                buf[..size].fill(i as u8);
+                buf.set_bytesused(size);
+                Ok(())
+            },
+            |view| Ok((view.len(), view.sequence())),
+        )?;
+        println!("frame #{sequence}: {len} bytes, took {:?}", start.elapsed());
+    }
+
+    Ok(())
+}
@@ -31,7 +31,7 @@ const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
 
 enum Output {
     Write(VideoOutputDevice),
-    Stream(WriteStream),
+    Stream(WriteStream<'static>),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -93,11 +93,15 @@ fn main() -> anyhow::Result<()> {
     loop {
         match &mut output {
             Output::Write(device) => {
-                device.write(&image)?;
+                // Each `write` outputs one frame, so it must not be split up like `write_all` would.
+                let _len = device.write(&image)?;
             }
             Output::Stream(stream) => {
                 stream.enqueue(|mut buf| {
-                    buf.copy_from_slice(&image);
+                    // Buffers may be larger than a frame, since their size is rounded up to whole
+                    // pages.
+                    buf[..image.len()].copy_from_slice(&image);
+                    buf.set_bytesused(image.len());
                     Ok(())
                 })?;
             }
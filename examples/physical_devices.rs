@@ -0,0 +1,24 @@
+//! Lists physical devices and the roles of their device nodes.
+//!
+//! Uses [`linuxvideo::sysfs::physical_devices`].
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    for device in linuxvideo::sysfs::physical_devices()? {
+        print!("{}", device.card());
+        if let Some(bus_info) = device.bus_info() {
+            print!(" ({})", bus_info);
+        }
+        if let Some(usb) = device.usb_device() {
+            print!(" [USB {}]", usb);
+        }
+        println!();
+
+        for (node, role) in device.nodes() {
+            println!("  {}: {:?}", node.dev_path().display(), role);
+        }
+    }
+
+    Ok(())
+}
@@ -83,7 +83,7 @@ fn main() -> anyhow::Result<()> {
             if buf.is_error() {
                 eprintln!("WARNING: error flag is set on buffer");
             }
-            file.write_all(&*buf)?;
+            file.write_all(&buf)?;
             println!(
                 "wrote {} bytes to {} (raw buffer size: {} bytes)",
                 buf.len(),
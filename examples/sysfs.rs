@@ -0,0 +1,22 @@
+//! Lists V4L2 device nodes through sysfs, without opening them.
+//!
+//! Uses [`linuxvideo::sysfs::list`].
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    for node in linuxvideo::sysfs::list()? {
+        print!(
+            "{}: {} (driver: {})",
+            node.dev_path().display(),
+            node.card(),
+            node.driver().unwrap_or("unknown"),
+        );
+        if let Some(usb) = node.usb_device() {
+            print!(" [USB {}]", usb);
+        }
+        println!();
+    }
+
+    Ok(())
+}
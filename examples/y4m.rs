@@ -0,0 +1,99 @@
+//! Records frames from a capture device to a Y4M file, or plays a Y4M file back to an output
+//! device (like a `v4l2loopback` device).
+//!
+//! Uses [`linuxvideo::y4m::Y4mWriter`] and [`linuxvideo::y4m::Y4mReader`].
+
+use std::{
+    env,
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use linuxvideo::{
+    format::{PixFormat, PixelFormat},
+    y4m::{Y4mReader, Y4mWriter},
+    Device, Fract,
+};
+
+const USAGE: &str = "usage: y4m record <device> <file> [<frames>] | y4m play <device> <file>";
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args = env::args_os().skip(1);
+    let mut next = || args.next().ok_or_else(|| anyhow!("{USAGE}"));
+    let mode = next()?;
+    let device = next()?;
+    let file = next()?;
+
+    match mode.to_str() {
+        Some("record") => {
+            let frames = match args.next() {
+                Some(frames) => frames.to_str().ok_or_else(|| anyhow!("{USAGE}"))?.parse()?,
+                None => 100,
+            };
+            record(&device, &file, frames)
+        }
+        Some("play") => play(&device, &file),
+        _ => bail!("{USAGE}"),
+    }
+}
+
+fn record(device: &OsString, file: &OsString, frames: usize) -> anyhow::Result<()> {
+    let device = Device::open(Path::new(device))?;
+    let capture = device.video_capture(PixFormat::new(u32::MAX, u32::MAX, PixelFormat::YUYV))?;
+    let format = capture.format().clone();
+    println!("negotiated format: {:?}", format);
+
+    let mut stream = capture.into_stream()?;
+    let interval = stream.frame_interval().unwrap_or(Fract::new(1, 30));
+    let mut writer = Y4mWriter::new(BufWriter::new(File::create(file)?), &format, interval)?;
+
+    for _ in 0..frames {
+        stream.dequeue(|buf| writer.write_frame(&buf))?;
+    }
+    writer.into_inner().flush()?;
+
+    println!("recorded {frames} frames");
+    Ok(())
+}
+
+fn play(device: &OsString, file: &OsString) -> anyhow::Result<()> {
+    let mut reader = Y4mReader::new(BufReader::new(File::open(file)?))?;
+    let interval = reader.frame_interval().unwrap_or(Fract::new(1, 30));
+
+    let device = Device::open(Path::new(device))?;
+    let output = device.video_output(reader.pix_format())?;
+    let format = output.format().clone();
+    println!("negotiated format: {:?}", format);
+
+    let mut stream = output.into_stream()?;
+    let mut frames = 0;
+    loop {
+        let more = stream.enqueue(|mut buf| match reader.read_frame(&format, &mut buf)? {
+            Some(len) => {
+                buf.set_bytesused(len);
+                Ok(true)
+            }
+            None => {
+                // The buffer is still enqueued, so make sure it doesn't contain a stale frame.
+                buf.set_bytesused(0);
+                Ok(false)
+            }
+        })?;
+        if !more {
+            break;
+        }
+
+        frames += 1;
+        thread::sleep(Duration::from_secs_f32(interval.as_f32()));
+    }
+
+    println!("played {frames} frames");
+    Ok(())
+}
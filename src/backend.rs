@@ -0,0 +1,154 @@
+//! The ioctl layer underneath [`Device`][crate::Device].
+//!
+//! [`Device`][crate::Device] and the iterators it returns don't call the `raw` ioctls directly, but
+//! go through the [`Backend`] of their [`DeviceHandle`]. This is either the device file, or a
+//! [`MockDevice`] that answers the ioctls from a table, which allows testing the enumeration logic
+//! without hardware.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::prelude::*;
+
+#[cfg(any(test, feature = "mock"))]
+use nix::errno::Errno;
+
+#[cfg(any(test, feature = "mock"))]
+use crate::mock::MockDevice;
+use crate::raw;
+
+/// The ioctls used by [`Device`][crate::Device] and its iterators.
+///
+/// The methods mirror the `raw` ioctls of the same name: the argument is passed in and out, and
+/// errors are reported as the `errno` the kernel would return.
+pub(crate) trait Backend {
+    fn querycap(&self, caps: &mut raw::Capabilities) -> nix::Result<()>;
+    fn enum_fmt(&self, desc: &mut raw::FmtDesc) -> nix::Result<()>;
+    fn enum_framesizes(&self, desc: &mut raw::FrmSizeEnum) -> nix::Result<()>;
+    fn enum_frameintervals(&self, desc: &mut raw::FrmIvalEnum) -> nix::Result<()>;
+    fn enuminput(&self, input: &mut raw::Input) -> nix::Result<()>;
+    fn enumoutput(&self, output: &mut raw::Output) -> nix::Result<()>;
+    fn queryctrl(&self, ctrl: &mut raw::QueryCtrl) -> nix::Result<()>;
+    fn querymenu(&self, menu: &mut raw::QueryMenu) -> nix::Result<()>;
+    fn g_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()>;
+    fn s_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()>;
+    fn g_fmt(&self, format: &mut raw::Format) -> nix::Result<()>;
+    fn s_fmt(&self, format: &mut raw::Format) -> nix::Result<()>;
+}
+
+/// Any file descriptor issues the ioctls directly. This covers the device [`File`] as well as the
+/// raw descriptors held by streams and codecs.
+impl<T: AsRawFd> Backend for T {
+    fn querycap(&self, caps: &mut raw::Capabilities) -> nix::Result<()> {
+        unsafe { raw::querycap(self.as_raw_fd(), caps).map(drop) }
+    }
+
+    fn enum_fmt(&self, desc: &mut raw::FmtDesc) -> nix::Result<()> {
+        unsafe { raw::enum_fmt(self.as_raw_fd(), desc).map(drop) }
+    }
+
+    fn enum_framesizes(&self, desc: &mut raw::FrmSizeEnum) -> nix::Result<()> {
+        unsafe { raw::enum_framesizes(self.as_raw_fd(), desc).map(drop) }
+    }
+
+    fn enum_frameintervals(&self, desc: &mut raw::FrmIvalEnum) -> nix::Result<()> {
+        unsafe { raw::enum_frameintervals(self.as_raw_fd(), desc).map(drop) }
+    }
+
+    fn enuminput(&self, input: &mut raw::Input) -> nix::Result<()> {
+        unsafe { raw::enuminput(self.as_raw_fd(), input).map(drop) }
+    }
+
+    fn enumoutput(&self, output: &mut raw::Output) -> nix::Result<()> {
+        unsafe { raw::enumoutput(self.as_raw_fd(), output).map(drop) }
+    }
+
+    fn queryctrl(&self, ctrl: &mut raw::QueryCtrl) -> nix::Result<()> {
+        unsafe { raw::queryctrl(self.as_raw_fd(), ctrl).map(drop) }
+    }
+
+    fn querymenu(&self, menu: &mut raw::QueryMenu) -> nix::Result<()> {
+        unsafe { raw::querymenu(self.as_raw_fd(), menu).map(drop) }
+    }
+
+    fn g_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()> {
+        unsafe { raw::g_ctrl(self.as_raw_fd(), ctrl).map(drop) }
+    }
+
+    fn s_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()> {
+        unsafe { raw::s_ctrl(self.as_raw_fd(), ctrl).map(drop) }
+    }
+
+    fn g_fmt(&self, format: &mut raw::Format) -> nix::Result<()> {
+        unsafe { raw::g_fmt(self.as_raw_fd(), format).map(drop) }
+    }
+
+    fn s_fmt(&self, format: &mut raw::Format) -> nix::Result<()> {
+        unsafe { raw::s_fmt(self.as_raw_fd(), format).map(drop) }
+    }
+}
+
+/// What a [`Device`][crate::Device] is backed by.
+#[derive(Debug)]
+pub(crate) enum DeviceHandle {
+    File(File),
+    #[cfg(any(test, feature = "mock"))]
+    Mock {
+        device: Box<MockDevice>,
+        /// An eventfd standing in for the device file, so that the device has something to poll
+        /// and pass to `Events`. Any V4L2 ioctl on it fails with `ENOTTY`.
+        fd: OwnedFd,
+    },
+}
+
+impl DeviceHandle {
+    #[cfg(any(test, feature = "mock"))]
+    pub(crate) fn mock(device: MockDevice) -> io::Result<Self> {
+        use nix::sys::eventfd::{eventfd, EfdFlags};
+
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC)?;
+        Ok(Self::Mock {
+            device: Box::new(device),
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    pub(crate) fn backend(&self) -> &dyn Backend {
+        match self {
+            Self::File(file) => file,
+            #[cfg(any(test, feature = "mock"))]
+            Self::Mock { device, .. } => &**device,
+        }
+    }
+
+    /// Returns the device file.
+    ///
+    /// Mock devices have no device file, so this returns an `ENOTTY` error for them.
+    pub(crate) fn file(&self) -> io::Result<&File> {
+        match self {
+            Self::File(file) => Ok(file),
+            #[cfg(any(test, feature = "mock"))]
+            Self::Mock { .. } => Err(Errno::ENOTTY.into()),
+        }
+    }
+
+    /// Turns the handle into the device file, for creating streams and other device types.
+    ///
+    /// Mock devices have no device file, so this returns an `ENOTTY` error for them.
+    pub(crate) fn into_file(self) -> io::Result<File> {
+        match self {
+            Self::File(file) => Ok(file),
+            #[cfg(any(test, feature = "mock"))]
+            Self::Mock { .. } => Err(Errno::ENOTTY.into()),
+        }
+    }
+}
+
+impl AsFd for DeviceHandle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Self::File(file) => file.as_fd(),
+            #[cfg(any(test, feature = "mock"))]
+            Self::Mock { fd, .. } => fd.as_fd(),
+        }
+    }
+}
@@ -55,6 +55,27 @@ buf_types! {
     META_OUTPUT = 14,
 }
 
+impl BufType {
+    /// Returns whether this buffer type uses the multi-planar API.
+    pub(crate) fn is_multiplanar(self) -> bool {
+        self == Self::VIDEO_CAPTURE_MPLANE || self == Self::VIDEO_OUTPUT_MPLANE
+    }
+
+    /// Returns whether this is an output buffer type (as opposed to a capture buffer type).
+    pub(crate) fn is_output(self) -> bool {
+        matches!(
+            self,
+            Self::VIDEO_OUTPUT
+                | Self::VBI_OUTPUT
+                | Self::SLICED_VBI_OUTPUT
+                | Self::VIDEO_OUTPUT_OVERLAY
+                | Self::VIDEO_OUTPUT_MPLANE
+                | Self::SDR_OUTPUT
+                | Self::META_OUTPUT
+        )
+    }
+}
+
 impl BufTypes {
     pub(crate) fn from_capabilities(caps: CapabilityFlags) -> Self {
         let mut buf_types = BufTypes::empty();
@@ -0,0 +1,456 @@
+//! Stateful video decoders and encoders.
+//!
+//! Stateful codecs are memory-to-memory devices that parse and produce the bitstream themselves, so
+//! the application only has to pass bitstream chunks and raw frames back and forth. They implement
+//! the protocol described in the kernel's [decoder] and [encoder] interface documentation.
+//!
+//! The in-kernel `vicodec` test driver implements both interfaces.
+//!
+//! [decoder]: https://docs.kernel.org/userspace-api/media/v4l/dev-decoder.html
+//! [encoder]: https://docs.kernel.org/userspace-api/media/v4l/dev-encoder.html
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::prelude::{AsFd, AsRawFd, RawFd};
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::poll::PollFlags;
+
+use crate::event::{EventKind, EventType, Events, Subscription};
+use crate::format::{Format, PixFormat, PixFormatMplane};
+use crate::stream::{
+    poll_fd, ReadBufferView, ReadStream, StreamBuilder, WriteBufferView, WriteStream,
+};
+use crate::{get_format_raw, raw, set_format_raw, BufType};
+
+/// A stateful video decoder.
+///
+/// Takes chunks of a compressed bitstream and produces raw frames. Created by
+/// [`Device::video_decoder`][crate::Device::video_decoder].
+///
+/// The format of the decoded frames is determined by the decoder once it has parsed the stream
+/// headers, and the decoder can change it when the stream changes resolution. Both cases are
+/// handled by reallocating the frame buffers internally.
+pub struct Decoder {
+    file: File,
+    output: WriteStream<'static>,
+    /// Receives the decoded frames. Created once the decoder has determined the frame format.
+    capture: Option<ReadStream<'static>>,
+    capture_type: BufType,
+    coded_format: Format,
+    frame_format: Option<Format>,
+    /// Set when the driver signaled a source change that has not been handled yet.
+    source_change: bool,
+    /// Whether the decoder has finished a drain sequence and has to be restarted.
+    stopped: bool,
+}
+
+impl Decoder {
+    pub(crate) fn new(
+        file: File,
+        output_type: BufType,
+        capture_type: BufType,
+        coded: PixFormat,
+    ) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+        let coded_format = set_format_raw(&fd, codec_format(output_type, coded))?;
+        Events::new(file.as_fd()).subscribe(Subscription::new(EventType::SOURCE_CHANGE))?;
+        let output = WriteStream::new(file.try_clone()?, output_type, StreamBuilder::new())?;
+
+        Ok(Self {
+            file,
+            output,
+            capture: None,
+            capture_type,
+            coded_format,
+            frame_format: None,
+            source_change: false,
+            stopped: false,
+        })
+    }
+
+    /// Returns the format of the bitstream, as chosen by the driver.
+    pub fn coded_format(&self) -> &Format {
+        &self.coded_format
+    }
+
+    /// Returns the format of the decoded frames.
+    ///
+    /// Returns `None` until the decoder has parsed the stream headers. The format can change
+    /// during decoding if the stream changes resolution.
+    pub fn frame_format(&self) -> Option<&Format> {
+        self.frame_format.as_ref()
+    }
+
+    /// Passes a chunk of the bitstream to the decoder.
+    ///
+    /// Decoded frames that are ready are passed to `on_frame` before the chunk is enqueued. If all
+    /// bitstream buffers are in use, this blocks until the decoder is done with one, passing frames
+    /// to `on_frame` as they are decoded. Frames carry the `timestamp` of the chunk they were
+    /// decoded from, which can be used to associate them with their source.
+    ///
+    /// Unless the coded format has the
+    /// [`CONTINUOUS_BYTESTREAM`][crate::format::FormatFlags::CONTINUOUS_BYTESTREAM] flag, each
+    /// chunk must contain exactly one frame.
+    ///
+    /// If the decoder was stopped by [`Decoder::drain`], it is restarted first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EINVAL` error if `chunk` is larger than a bitstream buffer. The buffer size can
+    /// be configured when creating the decoder.
+    pub fn decode(
+        &mut self,
+        chunk: &[u8],
+        timestamp: Duration,
+        mut on_frame: impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.stopped {
+            decoder_command(self.file.as_raw_fd(), raw::DEC_CMD_START)?;
+            self.stopped = false;
+        }
+
+        let mut timeout = Some(Duration::ZERO);
+        loop {
+            // Handle events and frames first, so that the decoder doesn't stall on a full capture
+            // queue while we wait for a bitstream buffer.
+            let ready = self.poll(PollFlags::POLLOUT, timeout)?;
+            if ready.intersects(PollFlags::POLLIN | PollFlags::POLLPRI) {
+                self.process(ready, &mut on_frame)?;
+                timeout = Some(Duration::ZERO);
+                continue;
+            }
+
+            let enqueued = self.output.try_enqueue(|mut buf| {
+                if chunk.len() > buf.len() {
+                    return Err(Errno::EINVAL.into());
+                }
+                buf[..chunk.len()].copy_from_slice(chunk);
+                buf.set_bytesused(chunk.len());
+                buf.set_timestamp(timestamp);
+                Ok(())
+            })?;
+            if enqueued.is_some() {
+                return Ok(());
+            }
+
+            // All bitstream buffers are in use.
+            timeout = None;
+        }
+    }
+
+    /// Decodes all bitstream data passed to the decoder and passes the remaining frames to
+    /// `on_frame`.
+    ///
+    /// This should be called at the end of the stream. It stops the decoder and waits until it has
+    /// returned the last frame. Calling [`Decoder::decode`] afterwards restarts it, for example to
+    /// decode a new stream.
+    ///
+    /// If the decoder has not yet parsed the stream headers, no frames can be decoded and this
+    /// returns immediately.
+    pub fn drain(
+        &mut self,
+        mut on_frame: impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.stopped {
+            return Ok(());
+        }
+
+        // Pick up a pending source change, in case the headers were parsed just now.
+        let ready = self.poll(PollFlags::empty(), Some(Duration::ZERO))?;
+        self.process(ready, &mut on_frame)?;
+        if self.capture.is_none() {
+            return Ok(());
+        }
+
+        decoder_command(self.file.as_raw_fd(), raw::DEC_CMD_STOP)?;
+        while !self.stopped {
+            let ready = self.poll(PollFlags::empty(), None)?;
+            self.process(ready, &mut on_frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits up to `timeout` (or forever, if `None`) for `events`, pending device events, or
+    /// decoded frames.
+    fn poll(&self, events: PollFlags, timeout: Option<Duration>) -> io::Result<PollFlags> {
+        let mut events = events | PollFlags::POLLPRI;
+        if self.capture.is_some() && !self.stopped {
+            events |= PollFlags::POLLIN;
+        }
+        poll_device(self.file.as_raw_fd(), events, timeout)
+    }
+
+    /// Handles pending events and dequeues a decoded frame, as signaled by `ready`.
+    fn process(
+        &mut self,
+        ready: PollFlags,
+        on_frame: &mut impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if ready.contains(PollFlags::POLLPRI) {
+            self.dequeue_events()?;
+        }
+
+        if ready.contains(PollFlags::POLLIN) {
+            if let Some(capture) = &mut self.capture {
+                if dequeue_capture(capture, on_frame)? {
+                    if self.source_change {
+                        // All frames using the old format were returned.
+                        self.setup_capture()?;
+                    } else {
+                        self.stopped = true;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn dequeue_events(&mut self) -> io::Result<()> {
+        loop {
+            let event = Events::new(self.file.as_fd()).dequeue()?;
+            if let EventKind::SourceChange(_) = event.kind() {
+                self.source_change = true;
+            }
+            if event.pending() == 0 {
+                break;
+            }
+        }
+
+        if self.source_change && self.capture.is_none() {
+            // The initial source change. No frames have been decoded yet, so there are no buffers
+            // that have to be returned first.
+            self.setup_capture()?;
+        }
+
+        Ok(())
+    }
+
+    /// (Re)allocates the capture buffers for the current frame format and starts streaming.
+    fn setup_capture(&mut self) -> io::Result<()> {
+        // The old buffers have to be released before new ones can be allocated.
+        self.capture = None;
+
+        let format = get_format_raw(&self.file, self.capture_type)?;
+        let capture = ReadStream::new(
+            self.file.try_clone()?,
+            self.capture_type,
+            StreamBuilder::new(),
+        )?;
+        self.frame_format = Some(format);
+        self.capture = Some(capture);
+        self.source_change = false;
+
+        Ok(())
+    }
+}
+
+/// A stateful video encoder.
+///
+/// Takes raw frames and produces chunks of a compressed bitstream. Created by
+/// [`Device::video_encoder`][crate::Device::video_encoder].
+pub struct Encoder {
+    file: File,
+    output: WriteStream<'static>,
+    capture: ReadStream<'static>,
+    frame_format: Format,
+    coded_format: Format,
+    /// Whether the encoder has finished a drain sequence and has to be restarted.
+    stopped: bool,
+}
+
+impl Encoder {
+    pub(crate) fn new(
+        file: File,
+        output_type: BufType,
+        capture_type: BufType,
+        frames: PixFormat,
+        coded: PixFormat,
+    ) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+        // The coded format has to be set first, since it determines the supported raw formats.
+        let coded_format = set_format_raw(&fd, codec_format(capture_type, coded))?;
+        let frame_format = set_format_raw(&fd, codec_format(output_type, frames))?;
+        let output = WriteStream::new(file.try_clone()?, output_type, StreamBuilder::new())?;
+        let capture = ReadStream::new(file.try_clone()?, capture_type, StreamBuilder::new())?;
+
+        Ok(Self {
+            file,
+            output,
+            capture,
+            frame_format,
+            coded_format,
+            stopped: false,
+        })
+    }
+
+    /// Returns the format of the raw frames, as chosen by the driver.
+    pub fn frame_format(&self) -> &Format {
+        &self.frame_format
+    }
+
+    /// Returns the format of the bitstream, as chosen by the driver.
+    pub fn coded_format(&self) -> &Format {
+        &self.coded_format
+    }
+
+    /// Passes a free frame buffer to `fill` to fill it with a raw frame, then enqueues it for
+    /// encoding.
+    ///
+    /// Bitstream chunks that are ready are passed to `on_chunk` first. If all frame buffers are in
+    /// use, this blocks until the encoder is done with one, passing chunks to `on_chunk` as they
+    /// are produced. Chunks carry the timestamp of the frame they were encoded from, if one was
+    /// set with [`WriteBufferView::set_timestamp`].
+    ///
+    /// If the encoder was stopped by [`Encoder::drain`], it is restarted first.
+    pub fn encode<T>(
+        &mut self,
+        fill: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+        mut on_chunk: impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<T> {
+        if self.stopped {
+            encoder_command(self.file.as_raw_fd(), raw::ENC_CMD_START)?;
+            self.stopped = false;
+        }
+
+        let mut fill = Some(fill);
+        let mut timeout = Some(Duration::ZERO);
+        loop {
+            let ready = self.poll(PollFlags::POLLOUT, timeout)?;
+            if ready.contains(PollFlags::POLLIN) {
+                self.process(&mut on_chunk)?;
+                timeout = Some(Duration::ZERO);
+                continue;
+            }
+
+            // `fill` is only taken if a buffer is available.
+            if let Some(res) = self.output.try_enqueue(|buf| (fill.take().unwrap())(buf))? {
+                return Ok(res);
+            }
+
+            // All frame buffers are in use.
+            timeout = None;
+        }
+    }
+
+    /// Encodes all frames passed to the encoder and passes the remaining bitstream chunks to
+    /// `on_chunk`.
+    ///
+    /// This should be called at the end of the stream. It stops the encoder and waits until it has
+    /// returned the last chunk. Calling [`Encoder::encode`] afterwards restarts it.
+    pub fn drain(
+        &mut self,
+        mut on_chunk: impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.stopped {
+            return Ok(());
+        }
+
+        encoder_command(self.file.as_raw_fd(), raw::ENC_CMD_STOP)?;
+        while !self.stopped {
+            let ready = self.poll(PollFlags::empty(), None)?;
+            if ready.contains(PollFlags::POLLIN) {
+                self.process(&mut on_chunk)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits up to `timeout` (or forever, if `None`) for `events` or encoded chunks.
+    fn poll(&self, events: PollFlags, timeout: Option<Duration>) -> io::Result<PollFlags> {
+        let mut events = events;
+        if !self.stopped {
+            events |= PollFlags::POLLIN;
+        }
+        poll_device(self.file.as_raw_fd(), events, timeout)
+    }
+
+    fn process(
+        &mut self,
+        on_chunk: &mut impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if dequeue_capture(&mut self.capture, on_chunk)? {
+            self.stopped = true;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `format` in the [`Format`] variant matching `buf_type`, converting it to a multi-planar
+/// format if necessary.
+fn codec_format(buf_type: BufType, format: PixFormat) -> Format {
+    match buf_type {
+        BufType::VIDEO_OUTPUT => Format::VideoOutput(format),
+        BufType::VIDEO_CAPTURE => Format::VideoCapture(format),
+        BufType::VIDEO_OUTPUT_MPLANE => {
+            Format::VideoOutputMplane(PixFormatMplane::from_single_planar(format))
+        }
+        BufType::VIDEO_CAPTURE_MPLANE => {
+            Format::VideoCaptureMplane(PixFormatMplane::from_single_planar(format))
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Waits up to `timeout` (or forever, if `None`) for `fd` to signal `events`.
+///
+/// Memory-to-memory devices signal `POLLERR` when neither queue has any buffers to work on, which
+/// is expected while the codec is starting up. A blocking wait would never finish in that state,
+/// so it results in an `EIO` error instead.
+fn poll_device(fd: RawFd, events: PollFlags, timeout: Option<Duration>) -> io::Result<PollFlags> {
+    let ready = poll_fd(fd, events, timeout)?;
+    if timeout.is_none() && ready == PollFlags::POLLERR {
+        return Err(Errno::EIO.into());
+    }
+    Ok(ready)
+}
+
+/// Dequeues a capture buffer and passes it to `cb`, unless it is empty.
+///
+/// Returns whether the buffer was the last one before the driver stopped.
+fn dequeue_capture(
+    capture: &mut ReadStream<'_>,
+    cb: &mut impl FnMut(ReadBufferView<'_>) -> io::Result<()>,
+) -> io::Result<bool> {
+    let res = capture.dequeue(|view| {
+        let last = view.is_last();
+        // The last buffer is often empty and only marks the end of the sequence.
+        if !view.is_empty() {
+            cb(view)?;
+        }
+        Ok(last)
+    });
+    match res {
+        // The last buffer was already dequeued.
+        Err(e) if e.raw_os_error() == Some(Errno::EPIPE as i32) => Ok(true),
+        res => res,
+    }
+}
+
+fn decoder_command(fd: RawFd, cmd: u32) -> io::Result<()> {
+    let mut cmd = raw::DecoderCmd {
+        cmd,
+        ..unsafe { mem::zeroed() }
+    };
+    unsafe {
+        raw::decoder_cmd(fd, &mut cmd)?;
+    }
+    Ok(())
+}
+
+fn encoder_command(fd: RawFd, cmd: u32) -> io::Result<()> {
+    let mut cmd = raw::EncoderCmd {
+        cmd,
+        flags: 0,
+        raw: [0; 8],
+    };
+    unsafe {
+        raw::encoder_cmd(fd, &mut cmd)?;
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 //! Device control enumeration and access.
 
-use std::{fmt, io, mem};
+use std::{ffi::c_void, fmt, io, marker::PhantomData, mem};
 
 use nix::errno::Errno;
 
@@ -22,7 +22,9 @@ impl<'a> ControlIter<'a> {
     pub(crate) fn new(device: &'a Device) -> Self {
         Self {
             device,
-            next_cid: Cid::BASE,
+            // `V4L2_CTRL_FLAG_NEXT_CTRL` returns the first control with an ID *higher* than this
+            // one, so start at 0 to include `Cid::BASE` itself.
+            next_cid: Cid(0),
             finished: false,
             use_ctrl_flag_next_ctrl: true,
         }
@@ -51,7 +53,7 @@ impl Iterator for ControlIter<'_> {
                     id,
                     ..mem::zeroed()
                 };
-                match raw::queryctrl(self.device.fd(), &mut raw) {
+                match self.device.backend().queryctrl(&mut raw) {
                     Ok(_) => {
                         if self.use_ctrl_flag_next_ctrl {
                             self.next_cid.0 = raw.id;
@@ -63,7 +65,9 @@ impl Iterator for ControlIter<'_> {
                         match e {
                             Errno::EINVAL => {
                                 self.use_ctrl_flag_next_ctrl = false;
-                                self.next_cid.0 += 1;
+                                // If the driver doesn't support `V4L2_CTRL_FLAG_NEXT_CTRL` at
+                                // all, probe every control ID starting at `Cid::BASE`.
+                                self.next_cid.0 = (self.next_cid.0 + 1).max(Cid::BASE.0);
                                 continue; // continue, because there might be gaps
                             }
                             e => {
@@ -187,7 +191,7 @@ impl Iterator for TextMenuIter<'_> {
                     };
 
                     self.next_index += 1;
-                    match raw::querymenu(self.device.fd(), &mut raw) {
+                    match self.device.backend().querymenu(&mut raw) {
                         Ok(_) => return Some(Ok(TextMenuItem { raw })),
                         Err(Errno::EINVAL) => continue,
                         Err(other) => return Some(Err(other.into())),
@@ -215,3 +219,172 @@ impl TextMenuItem {
         byte_array_to_str(unsafe { &self.raw.name_or_value.name })
     }
 }
+
+/// A control value that can be set as part of a [`Request`][crate::media::Request].
+#[derive(Clone, Copy)]
+pub struct ExtControl<'a> {
+    id: Cid,
+    value: ExtValue,
+    _p: PhantomData<&'a ()>,
+}
+
+#[derive(Clone, Copy)]
+enum ExtValue {
+    Int(i32),
+    Int64(i64),
+    Compound { ptr: *const c_void, size: u32 },
+}
+
+impl<'a> ExtControl<'a> {
+    /// Creates a value for a control of type `INTEGER`, `BOOLEAN`, `MENU`, `INTEGER_MENU`,
+    /// `BITMASK` or `BUTTON`.
+    pub fn new(id: Cid, value: i32) -> Self {
+        Self {
+            id,
+            value: ExtValue::Int(value),
+            _p: PhantomData,
+        }
+    }
+
+    /// Creates a value for a control of type `INTEGER64`.
+    pub fn new_64(id: Cid, value: i64) -> Self {
+        Self {
+            id,
+            value: ExtValue::Int64(value),
+            _p: PhantomData,
+        }
+    }
+
+    /// Creates a value for a compound or array control, like the parameters of stateless codecs.
+    ///
+    /// `value` is passed to the driver as-is, so it must have the memory layout the driver
+    /// expects for the control (as defined in the kernel's uAPI headers).
+    pub fn compound<T: ?Sized>(id: Cid, value: &'a T) -> Self {
+        Self {
+            id,
+            value: ExtValue::Compound {
+                ptr: value as *const T as *const c_void,
+                size: mem::size_of_val(value) as u32,
+            },
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns the identifier of the control.
+    #[inline]
+    pub fn id(&self) -> Cid {
+        self.id
+    }
+
+    pub(crate) fn to_raw(self) -> raw::controls::ExtControl {
+        let (size, value) = match self.value {
+            ExtValue::Int(value) => (0, raw::controls::ExtControlUnion { value }),
+            ExtValue::Int64(value64) => (0, raw::controls::ExtControlUnion { value64 }),
+            // The driver only reads from the pointer when setting controls.
+            ExtValue::Compound { ptr, size } => (
+                size,
+                raw::controls::ExtControlUnion {
+                    ptr: ptr as *mut c_void,
+                },
+            ),
+        };
+        raw::controls::ExtControl {
+            id: self.id,
+            size,
+            reserved2: [0],
+            value,
+        }
+    }
+}
+
+impl fmt::Debug for ExtControl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("ExtControl");
+        s.field("id", &self.id);
+        match self.value {
+            ExtValue::Int(value) => s.field("value", &value),
+            ExtValue::Int64(value) => s.field("value", &value),
+            ExtValue::Compound { size, .. } => s.field("size", &size),
+        };
+        s.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockControl, MockDevice};
+    use crate::CapabilityFlags;
+
+    fn mock() -> MockDevice {
+        MockDevice::new("mock", "Mock Camera", CapabilityFlags::VIDEO_CAPTURE)
+            .with_control(
+                MockControl::new(Cid::BRIGHTNESS, CtrlType::INTEGER, "Brightness")
+                    .range(0, 255, 1)
+                    .default_value(128),
+            )
+            .with_control(
+                MockControl::new(
+                    Cid::POWER_LINE_FREQUENCY,
+                    CtrlType::MENU,
+                    "Power Line Frequency",
+                )
+                .range(0, 3, 1)
+                .menu_item(0, "Disabled")
+                .menu_item(1, "50 Hz")
+                .menu_item(3, "Auto"),
+            )
+            .with_control(
+                MockControl::new(Cid::HUE, CtrlType::INTEGER, "Hue")
+                    .range(-180, 180, 1)
+                    .flags(ControlFlags::DISABLED),
+            )
+    }
+
+    fn control_ids(device: &Device) -> io::Result<Vec<Cid>> {
+        device
+            .controls()
+            .map(|res| res.map(|desc| desc.id()))
+            .collect()
+    }
+
+    #[test]
+    fn control_iter() -> io::Result<()> {
+        let expected = [Cid::BRIGHTNESS, Cid::POWER_LINE_FREQUENCY];
+        assert_eq!(control_ids(&mock().into_device()?)?, expected);
+        // Drivers without `V4L2_CTRL_FLAG_NEXT_CTRL` support have to be probed.
+        assert_eq!(
+            control_ids(&mock().without_next_ctrl().into_device()?)?,
+            expected
+        );
+
+        let mut device = mock().into_device()?;
+        assert_eq!(device.read_control_raw(Cid::BRIGHTNESS)?, 128);
+        device.write_control_raw(Cid::BRIGHTNESS, 10)?;
+        assert_eq!(device.read_control_raw(Cid::BRIGHTNESS)?, 10);
+        assert!(device.write_control_raw(Cid::BRIGHTNESS, 256).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn text_menu_iter() -> io::Result<()> {
+        let device = mock().into_device()?;
+        let ctrl = device
+            .controls()
+            .find(|res| matches!(res, Ok(desc) if desc.control_type() == CtrlType::MENU))
+            .unwrap()?;
+        let items = device
+            .enumerate_menu(&ctrl)
+            .map(|res| res.map(|item| (item.index(), item.name().to_string())))
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(
+            items,
+            [
+                (0, "Disabled".to_string()),
+                (1, "50 Hz".to_string()),
+                (3, "Auto".to_string()),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,407 @@
+//! Device event subscription and dequeueing.
+//!
+//! Devices can notify applications of things like control changes, resolution changes of the
+//! video source, or the end of a stream. Events have to be subscribed to with
+//! [`Events::subscribe`] before they are delivered.
+//!
+//! Pending events are signaled by `POLLPRI` on the device's file descriptor, so they can be waited
+//! for together with buffers, for example with
+//! [`ReadStream::wait_timeout`][crate::stream::ReadStream::wait_timeout].
+
+use std::{
+    fmt, io,
+    mem::MaybeUninit,
+    os::unix::prelude::{AsFd, AsRawFd, BorrowedFd},
+    time::Duration,
+};
+
+use nix::errno::Errno;
+use nix::libc::timespec;
+use nix::poll::PollFlags;
+
+use crate::controls::Cid;
+use crate::shared::{ControlFlags, CtrlType, Field};
+use crate::{raw, stream};
+
+pub use crate::shared::{CtrlChanges, EventSubFlags, EventType, MotionDetFlags, SrcChanges};
+
+/// Describes a set of events to subscribe to.
+#[derive(Debug, Clone, Copy)]
+pub struct Subscription {
+    event_type: EventType,
+    id: u32,
+    flags: EventSubFlags,
+}
+
+impl Subscription {
+    /// Creates a subscription to events of type `event_type`, with an ID of 0.
+    pub fn new(event_type: EventType) -> Self {
+        Self {
+            event_type,
+            id: 0,
+            flags: EventSubFlags::empty(),
+        }
+    }
+
+    /// Creates a subscription to changes of the control `cid`.
+    pub fn control(cid: Cid) -> Self {
+        Self::new(EventType::CTRL).id(cid.0)
+    }
+
+    /// Sets the ID of the events to subscribe to.
+    ///
+    /// What the ID refers to depends on the [`EventType`].
+    pub fn id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the flags of the subscription.
+    pub fn flags(mut self, flags: EventSubFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    fn to_raw(self) -> raw::EventSubscription {
+        raw::EventSubscription {
+            type_: self.event_type,
+            id: self.id,
+            flags: self.flags,
+            reserved: [0; 5],
+        }
+    }
+}
+
+/// Gives access to the event queue of a device.
+///
+/// Subscriptions and pending events belong to the open device file, so they are shared by a
+/// [`Device`][crate::Device] and the streams created from it.
+pub struct Events<'a> {
+    fd: BorrowedFd<'a>,
+}
+
+impl<'a> Events<'a> {
+    pub(crate) fn new(fd: BorrowedFd<'a>) -> Self {
+        Self { fd }
+    }
+
+    /// Subscribes to the events described by `sub`.
+    pub fn subscribe(&self, sub: Subscription) -> io::Result<()> {
+        unsafe {
+            raw::subscribe_event(self.fd.as_raw_fd(), &sub.to_raw())?;
+        }
+        Ok(())
+    }
+
+    /// Cancels a subscription made with [`Events::subscribe`].
+    ///
+    /// Only the event type and ID of `sub` are used.
+    pub fn unsubscribe(&self, sub: Subscription) -> io::Result<()> {
+        unsafe {
+            raw::unsubscribe_event(self.fd.as_raw_fd(), &sub.to_raw())?;
+        }
+        Ok(())
+    }
+
+    /// Cancels all event subscriptions.
+    pub fn unsubscribe_all(&self) -> io::Result<()> {
+        self.unsubscribe(Subscription::new(EventType::ALL))
+    }
+
+    /// Dequeues the oldest pending event, blocking until one is available.
+    ///
+    /// If the device is in non-blocking mode (for example, because the stream was turned into an
+    /// [`AsyncReadStream`][crate::stream::AsyncReadStream]), this returns an `ENOENT` error
+    /// instead of blocking.
+    pub fn dequeue(&self) -> io::Result<Event> {
+        unsafe {
+            let mut event = MaybeUninit::uninit();
+            raw::dqevent(self.fd.as_raw_fd(), event.as_mut_ptr())?;
+            Ok(Event::from_raw(&event.assume_init()))
+        }
+    }
+
+    /// Dequeues the oldest pending event, if there is one.
+    pub fn try_dequeue(&self) -> io::Result<Option<Event>> {
+        self.dequeue_timeout(Duration::ZERO)
+    }
+
+    /// Waits up to `timeout` for an event and dequeues it.
+    ///
+    /// Returns `Ok(None)` if no event arrived before the timeout expired.
+    pub fn dequeue_timeout(&self, timeout: Duration) -> io::Result<Option<Event>> {
+        let ready = stream::poll_fd(self.fd.as_raw_fd(), PollFlags::POLLPRI, Some(timeout))?;
+        if ready.contains(PollFlags::POLLPRI) {
+            self.dequeue().map(Some)
+        } else if ready.is_empty() {
+            Ok(None)
+        } else {
+            // `POLLERR` or `POLLNVAL`, which wouldn't go away by waiting.
+            Err(Errno::EIO.into())
+        }
+    }
+}
+
+impl AsFd for Events<'_> {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd
+    }
+}
+
+/// An event dequeued from a device.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    kind: EventKind,
+    id: u32,
+    sequence: u32,
+    pending: u32,
+    timestamp: Duration,
+}
+
+impl Event {
+    fn from_raw(raw: &raw::Event) -> Self {
+        let kind = unsafe {
+            match raw.type_ {
+                EventType::VSYNC => EventKind::Vsync(Field(raw.u.vsync.field.into())),
+                EventType::EOS => EventKind::Eos,
+                EventType::CTRL => EventKind::Ctrl(CtrlEvent {
+                    id: Cid(raw.id),
+                    raw: raw.u.ctrl,
+                }),
+                EventType::FRAME_SYNC => EventKind::FrameSync(raw.u.frame_sync.frame_sequence),
+                EventType::SOURCE_CHANGE => EventKind::SourceChange(raw.u.src_change.changes),
+                EventType::MOTION_DET => EventKind::MotionDet(MotionDetEvent(raw.u.motion_det)),
+                ty => EventKind::Other(ty, raw.u.data),
+            }
+        };
+
+        Self {
+            kind,
+            id: raw.id,
+            sequence: raw.sequence,
+            pending: raw.pending,
+            timestamp: timespec_to_duration(raw.timestamp),
+        }
+    }
+
+    /// Returns the type of the event, along with its payload.
+    #[inline]
+    pub fn kind(&self) -> &EventKind {
+        &self.kind
+    }
+
+    /// Returns the event ID, whose meaning depends on the [`EventType`].
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the sequence number of the event.
+    ///
+    /// The sequence number is incremented for every subscribed event that occurs, so gaps indicate
+    /// that the event queue overflowed and events were lost.
+    #[inline]
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the number of events that are still pending after this one.
+    #[inline]
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    /// Returns the time at which the event occurred, on the `CLOCK_MONOTONIC` clock.
+    #[inline]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+}
+
+/// The type and payload of an [`Event`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// An [`EventType::VSYNC`] event, carrying the field that is being received.
+    Vsync(Field),
+    /// An [`EventType::EOS`] event.
+    Eos,
+    /// An [`EventType::CTRL`] event.
+    Ctrl(CtrlEvent),
+    /// An [`EventType::FRAME_SYNC`] event, carrying the sequence number of the frame that is
+    /// being received.
+    FrameSync(u32),
+    /// An [`EventType::SOURCE_CHANGE`] event, carrying the properties that have changed.
+    ///
+    /// The source has to be queried again to find out the new values.
+    SourceChange(SrcChanges),
+    /// An [`EventType::MOTION_DET`] event.
+    MotionDet(MotionDetEvent),
+    /// An event of a type that isn't known to this library, like a driver-specific event, along
+    /// with its raw payload.
+    Other(EventType, [u8; 64]),
+}
+
+/// Describes a changed control.
+#[derive(Clone, Copy)]
+pub struct CtrlEvent {
+    id: Cid,
+    raw: raw::EventCtrl,
+}
+
+impl CtrlEvent {
+    /// The identifier of the control that has changed.
+    #[inline]
+    pub fn id(&self) -> Cid {
+        self.id
+    }
+
+    /// Returns which properties of the control have changed.
+    #[inline]
+    pub fn changes(&self) -> CtrlChanges {
+        self.raw.changes
+    }
+
+    /// Returns the type of the control.
+    #[inline]
+    pub fn control_type(&self) -> CtrlType {
+        self.raw.type_
+    }
+
+    /// Returns the current value of the control.
+    ///
+    /// Controls whose values don't fit in 64 bits (like string or compound controls) always
+    /// report a value of 0.
+    pub fn value(&self) -> i64 {
+        unsafe {
+            if self.raw.type_ == CtrlType::INTEGER64 {
+                self.raw.value.value64
+            } else {
+                self.raw.value.value.into()
+            }
+        }
+    }
+
+    /// Returns the control's current flags.
+    #[inline]
+    pub fn flags(&self) -> ControlFlags {
+        self.raw.flags
+    }
+
+    /// Returns the minimum value of the control.
+    #[inline]
+    pub fn minimum(&self) -> i32 {
+        self.raw.minimum
+    }
+
+    /// Returns the maximum value of the control.
+    #[inline]
+    pub fn maximum(&self) -> i32 {
+        self.raw.maximum
+    }
+
+    /// Returns the step size between valid values of the control.
+    #[inline]
+    pub fn step(&self) -> i32 {
+        self.raw.step
+    }
+
+    /// Returns the default value of the control.
+    #[inline]
+    pub fn default_value(&self) -> i32 {
+        self.raw.default_value
+    }
+}
+
+impl fmt::Debug for CtrlEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CtrlEvent")
+            .field("id", &self.id())
+            .field("changes", &self.changes())
+            .field("control_type", &self.control_type())
+            .field("value", &self.value())
+            .field("flags", &self.flags())
+            .field("minimum", &self.minimum())
+            .field("maximum", &self.maximum())
+            .field("step", &self.step())
+            .field("default_value", &self.default_value())
+            .finish()
+    }
+}
+
+/// Describes a motion detection event.
+#[derive(Clone, Copy)]
+pub struct MotionDetEvent(raw::EventMotionDet);
+
+impl MotionDetEvent {
+    /// Returns the sequence number of the frame in which motion was detected, if the driver
+    /// reports it.
+    pub fn frame_sequence(&self) -> Option<u32> {
+        self.0
+            .flags
+            .contains(MotionDetFlags::HAVE_FRAME_SEQ)
+            .then_some(self.0.frame_sequence)
+    }
+
+    /// Returns a bitmask of the regions in which motion was detected.
+    #[inline]
+    pub fn region_mask(&self) -> u32 {
+        self.0.region_mask
+    }
+}
+
+impl fmt::Debug for MotionDetEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MotionDetEvent")
+            .field("frame_sequence", &self.frame_sequence())
+            .field("region_mask", &self.region_mask())
+            .finish()
+    }
+}
+
+fn timespec_to_duration(ts: timespec) -> Duration {
+    // Negative values are not valid timestamps, and are mapped to zero.
+    let secs = ts.tv_sec.try_into().unwrap_or(0);
+    let nanos: u32 = ts.tv_nsec.try_into().unwrap_or(0);
+    Duration::new(secs, 0) + Duration::from_nanos(nanos.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+
+    #[test]
+    fn decode_ctrl_event() {
+        let mut raw: raw::Event = unsafe { mem::zeroed() };
+        raw.type_ = EventType::CTRL;
+        raw.id = Cid::BRIGHTNESS.0;
+        raw.sequence = 3;
+        raw.timestamp.tv_sec = 2;
+        raw.timestamp.tv_nsec = 500;
+        raw.u.ctrl = raw::EventCtrl {
+            changes: CtrlChanges::VALUE,
+            type_: CtrlType::INTEGER,
+            value: raw::EventCtrlValue { value64: 0 },
+            flags: ControlFlags::SLIDER,
+            minimum: -10,
+            maximum: 10,
+            step: 1,
+            default_value: 0,
+        };
+        raw.u.ctrl.value.value = -5;
+
+        let event = Event::from_raw(&raw);
+        assert_eq!(event.sequence(), 3);
+        assert_eq!(event.timestamp(), Duration::new(2, 500));
+        let EventKind::Ctrl(ctrl) = event.kind() else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!(ctrl.id(), Cid::BRIGHTNESS);
+        assert_eq!(ctrl.value(), -5);
+        assert_eq!(ctrl.minimum(), -10);
+        assert_eq!(ctrl.flags(), ControlFlags::SLIDER);
+    }
+}
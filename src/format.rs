@@ -5,10 +5,10 @@ use std::{fmt, io, mem};
 use nix::errno::Errno;
 
 use crate::shared::{FrmIvalType, FrmSizeType};
-use crate::{byte_array_to_str, raw, BufType, Device, Fract};
+use crate::{byte_array_to_str, raw, BufType, Device, Field, Fract};
 
 pub use crate::pixel_format::PixelFormat;
-pub use crate::shared::FormatFlags;
+pub use crate::shared::{FormatFlags, Quantization};
 
 /// Formats of all possible buffer types.
 #[derive(Debug)]
@@ -26,8 +26,11 @@ pub enum Format {
 
 /// Pixel format of a [`VIDEO_OUTPUT`][BufType::VIDEO_OUTPUT] or
 /// [`VIDEO_CAPTURE`][BufType::VIDEO_CAPTURE] buffer.
+#[derive(Clone)]
 pub struct PixFormat(raw::PixFormat);
 
+/// Pixel format of a [`VIDEO_OUTPUT_MPLANE`][BufType::VIDEO_OUTPUT_MPLANE] or
+/// [`VIDEO_CAPTURE_MPLANE`][BufType::VIDEO_CAPTURE_MPLANE] buffer.
 pub struct PixFormatMplane(raw::PixFormatMplane);
 
 pub struct Window(raw::Window);
@@ -51,9 +54,45 @@ impl Format {
             }
             BufType::VIDEO_OVERLAY => Self::VideoOverlay(Window(raw.fmt.win)),
             BufType::META_CAPTURE => Self::MetaCapture(MetaFormat(raw.fmt.meta)),
+            BufType::META_OUTPUT => Self::MetaOutput(MetaFormat(raw.fmt.meta)),
             _ => return None,
         })
     }
+
+    pub(crate) fn into_raw(self) -> raw::Format {
+        let mut raw_format: raw::Format = unsafe { mem::zeroed() };
+        match self {
+            Format::VideoCapture(f) => {
+                raw_format.type_ = BufType::VIDEO_CAPTURE;
+                raw_format.fmt.pix = f.into_raw();
+            }
+            Format::VideoOutput(f) => {
+                raw_format.type_ = BufType::VIDEO_OUTPUT;
+                raw_format.fmt.pix = f.into_raw();
+            }
+            Format::VideoCaptureMplane(f) => {
+                raw_format.type_ = BufType::VIDEO_CAPTURE_MPLANE;
+                raw_format.fmt.pix_mp = f.into_raw();
+            }
+            Format::VideoOutputMplane(f) => {
+                raw_format.type_ = BufType::VIDEO_OUTPUT_MPLANE;
+                raw_format.fmt.pix_mp = f.into_raw();
+            }
+            Format::VideoOverlay(f) => {
+                raw_format.type_ = BufType::VIDEO_OVERLAY;
+                raw_format.fmt.win = f.into_raw();
+            }
+            Format::MetaCapture(f) => {
+                raw_format.type_ = BufType::META_CAPTURE;
+                raw_format.fmt.meta = f.into_raw();
+            }
+            Format::MetaOutput(f) => {
+                raw_format.type_ = BufType::META_OUTPUT;
+                raw_format.fmt.meta = f.into_raw();
+            }
+        }
+        raw_format
+    }
 }
 
 impl PixFormat {
@@ -66,7 +105,7 @@ impl PixFormat {
         })
     }
 
-    pub(crate) fn to_raw(self) -> raw::PixFormat {
+    pub(crate) fn into_raw(self) -> raw::PixFormat {
         self.0
     }
 
@@ -86,16 +125,104 @@ impl PixFormat {
         self.0.bytesperline
     }
 
+    /// Sets the number of bytes between the start of two consecutive lines of the image.
+    ///
+    /// This can be used to request padding at the end of each line. Drivers adjust the value
+    /// during format negotiation, and 0 makes them choose the minimum for the pixel format.
+    pub fn set_bytes_per_line(&mut self, bytes_per_line: u32) {
+        self.0.bytesperline = bytes_per_line;
+    }
+
+    pub fn field(&self) -> Field {
+        self.0.field
+    }
+
+    pub fn set_field(&mut self, field: Field) {
+        self.0.field = field;
+    }
+
+    /// Returns the quantization range of the image data.
+    ///
+    /// [`Quantization::DEFAULT`] means that the range is implied by the colorspace, which is full
+    /// range for RGB formats and limited range for most YUV formats.
+    pub fn quantization(&self) -> Quantization {
+        if self.0.priv_ == raw::PIX_FMT_PRIV_MAGIC {
+            self.0.quantization
+        } else {
+            Quantization::DEFAULT
+        }
+    }
+
+    pub fn set_quantization(&mut self, quantization: Quantization) {
+        self.0.priv_ = raw::PIX_FMT_PRIV_MAGIC;
+        self.0.quantization = quantization;
+    }
+
     pub fn size_image(&self) -> u32 {
         self.0.sizeimage
     }
+
+    /// Sets the size of the buffers holding an image, in bytes.
+    ///
+    /// For most formats, this is computed by the driver during format negotiation. For
+    /// [`COMPRESSED`][FormatFlags::COMPRESSED] formats, the application may request a larger size
+    /// to make room for big bitstream chunks.
+    pub fn set_size_image(&mut self, size_image: u32) {
+        self.0.sizeimage = size_image;
+    }
 }
 
 impl PixFormatMplane {
-    pub(crate) fn to_raw(self) -> raw::PixFormatMplane {
+    /// Creates a multi-planar pixel format.
+    ///
+    /// The number of planes and their layout is determined by the driver during format
+    /// negotiation, depending on `pixel_format`.
+    pub fn new(width: u32, height: u32, pixel_format: PixelFormat) -> Self {
+        Self(raw::PixFormatMplane {
+            width,
+            height,
+            pixel_format,
+            ..unsafe { mem::zeroed() }
+        })
+    }
+
+    /// Converts a single-planar format into the equivalent multi-planar format with one plane.
+    pub(crate) fn from_single_planar(format: PixFormat) -> Self {
+        let mut plane_fmt = [raw::PlanePixFormat {
+            sizeimage: 0,
+            bytesperline: 0,
+            reserved: [0; 6],
+        }; raw::VIDEO_MAX_PLANES];
+        plane_fmt[0].sizeimage = format.0.sizeimage;
+        plane_fmt[0].bytesperline = format.0.bytesperline;
+
+        Self(raw::PixFormatMplane {
+            width: format.0.width,
+            height: format.0.height,
+            pixel_format: format.0.pixel_format,
+            field: format.0.field.0,
+            plane_fmt,
+            num_planes: 1,
+            ..unsafe { mem::zeroed() }
+        })
+    }
+
+    pub(crate) fn into_raw(self) -> raw::PixFormatMplane {
         self.0
     }
 
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.0.pixel_format
+    }
+
     pub fn num_planes(&self) -> usize {
         self.0.num_planes.into()
     }
@@ -117,7 +244,7 @@ impl PlanePixFormat {
 }
 
 impl Window {
-    pub(crate) fn to_raw(self) -> raw::Window {
+    pub(crate) fn into_raw(self) -> raw::Window {
         self.0
     }
 }
@@ -134,7 +261,7 @@ impl MetaFormat {
         self.0.buffersize
     }
 
-    pub(crate) fn to_raw(self) -> raw::MetaFormat {
+    pub(crate) fn into_raw(self) -> raw::MetaFormat {
         self.0
     }
 }
@@ -237,7 +364,7 @@ impl Iterator for FormatDescIter<'_> {
                 mbus_code: 0,
                 ..mem::zeroed()
             };
-            match raw::enum_fmt(self.device.fd(), &mut desc) {
+            match self.device.backend().enum_fmt(&mut desc) {
                 Ok(_) => {}
                 Err(e) => {
                     self.finished = true;
@@ -269,6 +396,11 @@ impl FormatDesc {
     pub fn pixel_format(&self) -> PixelFormat {
         self.0.pixel_format
     }
+
+    /// Returns the buffer type this format was enumerated for.
+    pub fn buf_type(&self) -> BufType {
+        self.0.type_
+    }
 }
 
 impl fmt::Debug for FormatDesc {
@@ -297,7 +429,7 @@ impl FrameSizes {
                 pixel_format,
                 ..mem::zeroed()
             };
-            raw::enum_framesizes(device.fd(), &mut desc)?;
+            device.backend().enum_framesizes(&mut desc)?;
 
             match desc.type_ {
                 FrmSizeType::DISCRETE => {
@@ -311,7 +443,7 @@ impl FrameSizes {
                             pixel_format,
                             ..mem::zeroed()
                         };
-                        match raw::enum_framesizes(device.fd(), &mut desc) {
+                        match device.backend().enum_framesizes(&mut desc) {
                             Ok(_) => {
                                 assert_eq!(desc.type_, FrmSizeType::DISCRETE);
                                 sizes.push(DiscreteFrameSize {
@@ -434,7 +566,7 @@ impl FrameIntervals {
                 height,
                 ..mem::zeroed()
             };
-            raw::enum_frameintervals(device.fd(), &mut desc)?;
+            device.backend().enum_frameintervals(&mut desc)?;
 
             match desc.type_ {
                 FrmIvalType::DISCRETE => {
@@ -450,7 +582,7 @@ impl FrameIntervals {
                             height,
                             ..mem::zeroed()
                         };
-                        match raw::enum_frameintervals(device.fd(), &mut desc) {
+                        match device.backend().enum_frameintervals(&mut desc) {
                             Ok(_) => {
                                 assert_eq!(desc.type_, FrmIvalType::DISCRETE);
                                 ivals.push(DiscreteFrameInterval {
@@ -557,3 +689,143 @@ impl StepwiseFrameIntervals {
         &self.0.step
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::*;
+    use crate::mock::{MockDevice, MockFrameIntervals, MockFrameSizes, MockIoctl};
+    use crate::{set_format_raw, CapabilityFlags};
+
+    fn mock() -> MockDevice {
+        MockDevice::new("mock", "Mock Camera", CapabilityFlags::VIDEO_CAPTURE)
+            .with_format(
+                BufType::VIDEO_CAPTURE,
+                PixelFormat::YUYV,
+                "YUYV 4:2:2",
+                FormatFlags::empty(),
+            )
+            .with_format(
+                BufType::VIDEO_CAPTURE,
+                PixelFormat::MJPG,
+                "Motion-JPEG",
+                FormatFlags::COMPRESSED,
+            )
+            .with_format(
+                BufType::VIDEO_OUTPUT,
+                PixelFormat::RGB3,
+                "RGB3",
+                FormatFlags::empty(),
+            )
+    }
+
+    #[test]
+    fn format_desc_iter() -> io::Result<()> {
+        let device = mock().into_device()?;
+        let formats = device
+            .formats(BufType::VIDEO_CAPTURE)
+            .collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(formats.len(), 2);
+        assert_eq!(formats[0].pixel_format(), PixelFormat::YUYV);
+        assert_eq!(formats[0].description(), "YUYV 4:2:2");
+        assert_eq!(formats[1].flags(), FormatFlags::COMPRESSED);
+        assert_eq!(device.formats(BufType::META_CAPTURE).count(), 0);
+
+        let device = mock().fail(MockIoctl::EnumFmt, Errno::EIO).into_device()?;
+        let results = device.formats(BufType::VIDEO_CAPTURE).collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].as_ref().unwrap_err().raw_os_error(),
+            Some(Errno::EIO as i32)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn frame_sizes() -> io::Result<()> {
+        let device = mock()
+            .with_frame_sizes(
+                PixelFormat::YUYV,
+                MockFrameSizes::Discrete(vec![(640, 480), (1280, 720), (320, 240)]),
+            )
+            .with_frame_sizes(
+                PixelFormat::MJPG,
+                MockFrameSizes::Stepwise {
+                    min: (16, 16),
+                    max: (1920, 1080),
+                    step: (16, 8),
+                },
+            )
+            .into_device()?;
+
+        let sizes = device.frame_sizes(PixelFormat::YUYV)?;
+        let FrameSizes::Discrete(list) = &sizes else {
+            panic!("expected discrete frame sizes");
+        };
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[2].index(), 2);
+        assert_eq!((sizes.min_width(), sizes.max_height()), (320, 720));
+
+        let FrameSizes::Stepwise(stepwise) = device.frame_sizes(PixelFormat::MJPG)? else {
+            panic!("expected stepwise frame sizes");
+        };
+        assert_eq!((stepwise.step_width(), stepwise.step_height()), (16, 8));
+
+        assert!(device.frame_sizes(PixelFormat::RGB3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn frame_intervals() -> io::Result<()> {
+        let device = mock()
+            .with_frame_intervals(
+                PixelFormat::YUYV,
+                640,
+                480,
+                MockFrameIntervals::Discrete(vec![Fract::new(1, 30), Fract::new(1, 15)]),
+            )
+            .with_frame_intervals(PixelFormat::YUYV, 1280, 720, MockFrameIntervals::Invalid)
+            .into_device()?;
+
+        let ivals = device.frame_intervals(PixelFormat::YUYV, 640, 480)?;
+        assert_eq!(ivals.to_string(), "1/30, 1/15");
+
+        // `v4l2loopback` quirk: an unknown interval type is treated as any interval up to 1 second.
+        let ivals = device.frame_intervals(PixelFormat::YUYV, 1280, 720)?;
+        let FrameIntervals::Continuous(continuous) = &ivals else {
+            panic!("expected continuous frame intervals");
+        };
+        assert_eq!(continuous.min(), &Fract::new(1, 600));
+        assert_eq!(continuous.max(), &Fract::new(1, 1));
+
+        assert!(device.frame_intervals(PixelFormat::YUYV, 1, 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn negotiation_limits() -> io::Result<()> {
+        // The range is described backwards, which must not make negotiation panic.
+        let device = mock()
+            .with_frame_sizes(
+                PixelFormat::YUYV,
+                MockFrameSizes::Continuous {
+                    min: (1920, 1080),
+                    max: (16, 16),
+                },
+            )
+            .into_device()?;
+        let format = Format::VideoCapture(PixFormat::new(4000, 8, PixelFormat::YUYV));
+        let Format::VideoCapture(format) = set_format_raw(device.backend(), format)? else {
+            panic!("expected a video capture format");
+        };
+        assert_eq!((format.width(), format.height()), (1920, 16));
+
+        // Without frame sizes, the requested size is kept, unless the image size overflows.
+        let device = mock().into_device()?;
+        let format = Format::VideoCapture(PixFormat::new(100_000, 100_000, PixelFormat::MJPG));
+        let err = set_format_raw(device.backend(), format).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::EINVAL as i32));
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+//! Monitoring V4L2 devices being plugged in and removed.
+//!
+//! [`DeviceMonitor`] watches `/dev` with inotify and reports [`HotplugEvent`]s when V4L2 device
+//! nodes appear or disappear.
+
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fmt, fs, io};
+
+use nix::errno::Errno;
+use nix::poll::PollFlags;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use crate::stream::poll_fd;
+use crate::{is_device_node_name, Capabilities, Device};
+
+/// Information about a device reported by a [`DeviceMonitor`].
+#[derive(Clone)]
+pub struct DeviceInfo {
+    path: PathBuf,
+    capabilities: Capabilities,
+}
+
+impl DeviceInfo {
+    /// Returns the path of the device node.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the capabilities of the device, as reported when it was added.
+    ///
+    /// This includes the driver name, card name and bus info of the device.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+}
+
+impl fmt::Debug for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceInfo")
+            .field("path", &self.path)
+            .field("driver", &self.capabilities.driver())
+            .field("card", &self.capabilities.card())
+            .field("bus_info", &self.capabilities.bus_info())
+            .finish()
+    }
+}
+
+/// A change to the set of connected devices.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum HotplugEvent {
+    /// A device was plugged in.
+    Added(DeviceInfo),
+    /// A device was removed.
+    ///
+    /// The [`DeviceInfo`] is the one reported when the device was added.
+    Removed(DeviceInfo),
+}
+
+/// Reports V4L2 devices being plugged in and removed.
+///
+/// Devices that are already connected when the monitor is created are listed by
+/// [`DeviceMonitor::devices`], and don't cause [`HotplugEvent::Added`] events.
+///
+/// A device is only reported once it can be opened. When a device is plugged in, udev usually
+/// adjusts the permissions of its device node after the node is created, so devices may be added a
+/// short while after their node appears. Nodes that never become accessible are not reported.
+///
+/// # Integration with event loops
+///
+/// The monitor implements [`AsRawFd`] and [`AsFd`], and its file descriptor becomes readable when
+/// there are changes to process. Since one change can result in several events, callers should
+/// call [`DeviceMonitor::try_next_event`] until it returns `None` every time the file descriptor
+/// becomes readable.
+pub struct DeviceMonitor {
+    inotify: OwnedFd,
+    dir: PathBuf,
+    devices: Vec<DeviceInfo>,
+    /// Names of device nodes that could not be opened yet.
+    unready: Vec<OsString>,
+    pending: VecDeque<HotplugEvent>,
+    probe: fn(&Path) -> io::Result<Capabilities>,
+}
+
+impl DeviceMonitor {
+    /// Starts monitoring `/dev` for V4L2 devices.
+    pub fn new() -> io::Result<Self> {
+        Self::with_dir(Path::new("/dev"), |path| Device::open(path)?.capabilities())
+    }
+
+    fn with_dir(dir: &Path, probe: fn(&Path) -> io::Result<Capabilities>) -> io::Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let inotify_fd = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+        // Start watching before the initial scan, so that no device can be missed.
+        inotify.add_watch(
+            dir,
+            AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_DELETE
+                | AddWatchFlags::IN_ATTRIB
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM,
+        )?;
+
+        let mut this = Self {
+            inotify: inotify_fd,
+            dir: dir.to_path_buf(),
+            devices: Vec::new(),
+            unready: Vec::new(),
+            pending: VecDeque::new(),
+            probe,
+        };
+        this.rescan()?;
+        this.pending.clear();
+        Ok(this)
+    }
+
+    /// Returns the devices that are currently connected.
+    ///
+    /// This reflects the events that were returned so far.
+    pub fn devices(&self) -> &[DeviceInfo] {
+        &self.devices
+    }
+
+    /// Waits for the next [`HotplugEvent`].
+    pub fn next_event(&mut self) -> io::Result<HotplugEvent> {
+        Ok(self
+            .wait(None)?
+            .expect("no timeout, so there's always an event"))
+    }
+
+    /// Waits up to `timeout` for the next [`HotplugEvent`].
+    ///
+    /// Returns `Ok(None)` if no device was added or removed before the timeout expired.
+    pub fn next_event_timeout(&mut self, timeout: Duration) -> io::Result<Option<HotplugEvent>> {
+        self.wait(Some(timeout))
+    }
+
+    /// Returns the next [`HotplugEvent`] if one is available without blocking.
+    pub fn try_next_event(&mut self) -> io::Result<Option<HotplugEvent>> {
+        while self.pending.is_empty() {
+            if !self.process_inotify_events()? {
+                break;
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Option<HotplugEvent>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(event) = self.try_next_event()? {
+                return Ok(Some(event));
+            }
+
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::ZERO) {
+                return Ok(None);
+            }
+            poll_fd(self.inotify.as_raw_fd(), PollFlags::POLLIN, remaining)?;
+        }
+    }
+
+    /// Reads pending inotify events and turns them into [`HotplugEvent`]s.
+    ///
+    /// Returns `false` if there were no inotify events to read.
+    fn process_inotify_events(&mut self) -> io::Result<bool> {
+        let inotify = unsafe { Inotify::from_raw_fd(self.inotify.as_raw_fd()) };
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EAGAIN) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                log::debug!(
+                    "inotify queue overflowed, rescanning {}",
+                    self.dir.display()
+                );
+                self.rescan()?;
+                continue;
+            }
+
+            let Some(name) = event.name else { continue };
+            if !is_device_node_name(&name) {
+                continue;
+            }
+
+            if event
+                .mask
+                .intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM)
+            {
+                self.remove(&name);
+            } else if event
+                .mask
+                .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+                || self.unready.contains(&name)
+            {
+                // Permission changes (`IN_ATTRIB`) are only interesting for nodes that couldn't
+                // be opened before.
+                self.add(name);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Compares the contents of the directory with the known devices, and queues events for the
+    /// differences.
+    fn rescan(&mut self) -> io::Result<()> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            if is_device_node_name(&name) {
+                names.push(name);
+            }
+        }
+
+        let removed = self
+            .devices
+            .iter()
+            .filter_map(|info| info.path.file_name())
+            .filter(|name| !names.iter().any(|n| n == name))
+            .map(OsStr::to_os_string)
+            .collect::<Vec<_>>();
+        for name in removed {
+            self.remove(&name);
+        }
+        self.unready.retain(|name| names.contains(name));
+
+        names.sort();
+        for name in names {
+            self.add(name);
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, name: OsString) {
+        let path = self.dir.join(&name);
+        if self.devices.iter().any(|info| info.path == path) {
+            return;
+        }
+
+        match (self.probe)(&path) {
+            Ok(capabilities) => {
+                self.unready.retain(|n| *n != name);
+                let info = DeviceInfo { path, capabilities };
+                self.devices.push(info.clone());
+                self.pending.push_back(HotplugEvent::Added(info));
+            }
+            Err(e) => {
+                log::debug!("cannot open {}: {}", path.display(), e);
+                if !self.unready.contains(&name) {
+                    self.unready.push(name);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &OsStr) {
+        self.unready.retain(|n| n != name);
+        let path = self.dir.join(name);
+        if let Some(index) = self.devices.iter().position(|info| info.path == path) {
+            let info = self.devices.remove(index);
+            self.pending.push_back(HotplugEvent::Removed(info));
+        }
+    }
+}
+
+impl AsRawFd for DeviceMonitor {
+    /// Returns the inotify file descriptor of the monitor.
+    ///
+    /// It becomes readable when there are changes to process.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+impl AsFd for DeviceMonitor {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}
+
+impl fmt::Debug for DeviceMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceMonitor")
+            .field("dir", &self.dir)
+            .field("devices", &self.devices)
+            .field("unready", &self.unready)
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::mock::MockDevice;
+    use crate::test_util::TempDir;
+    use crate::CapabilityFlags;
+
+    /// Treats regular files as devices, unless they contain `busy`.
+    fn probe(path: &Path) -> io::Result<Capabilities> {
+        if fs::read(path)? == b"busy" {
+            return Err(Errno::EACCES.into());
+        }
+        let card = path.file_name().unwrap().to_str().unwrap();
+        MockDevice::new("mock", card, CapabilityFlags::VIDEO_CAPTURE)
+            .into_device()?
+            .capabilities()
+    }
+
+    fn expect_added(monitor: &mut DeviceMonitor, card: &str) {
+        match monitor.try_next_event().unwrap() {
+            Some(HotplugEvent::Added(info)) => assert_eq!(info.capabilities().card(), card),
+            event => panic!("expected {card} to be added, got {event:?}"),
+        }
+    }
+
+    #[test]
+    fn added_and_removed() -> io::Result<()> {
+        let tmp = TempDir::new("hotplug")?;
+        let dir = tmp.path();
+        fs::write(dir.join("video0"), "")?;
+
+        let mut monitor = DeviceMonitor::with_dir(dir, probe)?;
+        assert_eq!(monitor.devices().len(), 1);
+        assert!(monitor.try_next_event()?.is_none());
+
+        fs::write(dir.join("video1"), "")?;
+        fs::write(dir.join("not-a-camera"), "")?;
+        expect_added(&mut monitor, "video1");
+        assert!(monitor.try_next_event()?.is_none());
+
+        // Not accessible yet, then "udev" fixes the permissions.
+        fs::write(dir.join("video2"), "busy")?;
+        assert!(monitor.try_next_event()?.is_none());
+        fs::write(dir.join("video2"), "")?;
+        let file = fs::File::open(dir.join("video2"))?;
+        file.set_permissions(fs::Permissions::from_mode(0o640))?;
+        expect_added(&mut monitor, "video2");
+
+        fs::remove_file(dir.join("video0"))?;
+        match monitor.next_event_timeout(Duration::from_secs(1))? {
+            Some(HotplugEvent::Removed(info)) => assert_eq!(info.path(), dir.join("video0")),
+            event => panic!("expected video0 to be removed, got {event:?}"),
+        }
+        assert_eq!(monitor.devices().len(), 2);
+        assert!(monitor
+            .next_event_timeout(Duration::from_millis(10))?
+            .is_none());
+        Ok(())
+    }
+}
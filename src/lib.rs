@@ -5,44 +5,66 @@
 
 #[macro_use]
 mod macros;
+mod backend;
 mod buf_type;
+pub mod codec;
 pub mod controls;
+pub mod event;
 pub mod format;
+pub mod hotplug;
+pub mod media;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 mod pixel_format;
 mod raw;
 mod shared;
+#[cfg(any(test, feature = "mock"))]
+pub mod sim;
 pub mod stream;
+pub mod sysfs;
+#[cfg(test)]
+mod test_util;
 pub mod uvc;
+pub mod y4m;
 
 use nix::errno::Errno;
 use pixel_format::PixelFormat;
 use std::{
+    ffi::OsStr,
     fmt,
     fs::{self, File, OpenOptions},
     io::{self, Read, Write},
-    mem::{self, MaybeUninit},
+    mem,
     os::unix::prelude::*,
     path::{Path, PathBuf},
 };
 
+use backend::{Backend, DeviceHandle};
+use codec::{Decoder, Encoder};
 use controls::{ControlDesc, ControlIter, TextMenuIter};
-use format::{Format, FormatDescIter, FrameIntervals, FrameSizes, MetaFormat, PixFormat};
+use event::Events;
+use format::{
+    Format, FormatDesc, FormatDescIter, FormatFlags, FrameIntervals, FrameSizes, MetaFormat,
+    PixFormat, PixFormatMplane,
+};
 use raw::controls::Cid;
-use shared::{CaptureParamFlags, Memory, StreamParamCaps};
-use stream::{ReadStream, WriteStream, DEFAULT_BUFFER_COUNT};
+use shared::{CaptureParamFlags, StreamParamCaps};
+use stream::{M2mStream, ReadStream, StreamBuilder, WriteStream};
 
 pub use buf_type::*;
 pub use shared::{
-    AnalogStd, CapabilityFlags, Fract, InputCapabilities, InputStatus, InputType,
-    OutputCapabilities, OutputType,
+    AnalogStd, BufCap, CapabilityFlags, Field, Fract, InputCapabilities, InputStatus, InputType,
+    MemoryFlags, OutputCapabilities, OutputType,
 };
 
 /// Returns an iterator over all connected V4L2 devices.
+///
+/// This opens every device node. [`sysfs::list`] enumerates devices without opening them.
 pub fn list() -> io::Result<impl Iterator<Item = io::Result<Device>>> {
     Ok(fs::read_dir("/dev")?.flat_map(|file| {
         let file = match file {
             Ok(file) => file,
-            Err(e) => return Some(Err(e.into())),
+            Err(e) => return Some(Err(e)),
         };
 
         match file.file_type() {
@@ -52,30 +74,34 @@ pub fn list() -> io::Result<impl Iterator<Item = io::Result<Device>>> {
                     return None;
                 }
             }
-            Err(e) => return Some(Err(e.into())),
+            Err(e) => return Some(Err(e)),
         }
 
-        let name = file.file_name();
-        let prefixes: &[&[u8]] = &[
-            b"video",
-            b"vbi",
-            b"radio",
-            b"swradio",
-            b"v4l-touch",
-            b"v4l-subdev",
-        ];
-        if prefixes.iter().any(|p| name.as_bytes().starts_with(p)) {
-            Some(Device::open(&file.path()))
+        if is_device_node_name(&file.file_name()) {
+            Some(Device::open(file.path()))
         } else {
             None
         }
     }))
 }
 
+/// Returns whether `name` is the name of a V4L2 device node in `/dev`.
+fn is_device_node_name(name: &OsStr) -> bool {
+    let prefixes: &[&[u8]] = &[
+        b"video",
+        b"vbi",
+        b"radio",
+        b"swradio",
+        b"v4l-touch",
+        b"v4l-subdev",
+    ];
+    prefixes.iter().any(|p| name.as_bytes().starts_with(p))
+}
+
 /// A V4L2 device.
 #[derive(Debug)]
 pub struct Device {
-    file: File,
+    handle: DeviceHandle,
     available_capabilities: CapabilityFlags,
 }
 
@@ -89,8 +115,12 @@ impl Device {
 
     fn open_impl(path: &Path) -> io::Result<Self> {
         let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::from_handle(DeviceHandle::File(file))
+    }
+
+    pub(crate) fn from_handle(handle: DeviceHandle) -> io::Result<Self> {
         let mut this = Self {
-            file,
+            handle,
             available_capabilities: CapabilityFlags::empty(),
         };
         let caps = this.capabilities()?;
@@ -99,21 +129,23 @@ impl Device {
         Ok(this)
     }
 
-    fn fd(&self) -> RawFd {
-        self.file.as_raw_fd()
+    pub(crate) fn backend(&self) -> &dyn Backend {
+        self.handle.backend()
     }
 
     /// Returns the path to the V4L2 device.
+    ///
+    /// Mock devices have no path, and return an `ENOTTY` error.
     pub fn path(&self) -> io::Result<PathBuf> {
-        Ok(fs::read_link(format!("/proc/self/fd/{}", self.fd()))?)
+        let fd = self.handle.file()?.as_raw_fd();
+        fs::read_link(format!("/proc/self/fd/{}", fd))
     }
 
     pub fn capabilities(&self) -> io::Result<Capabilities> {
         unsafe {
-            let mut caps = MaybeUninit::uninit();
-            let res = raw::querycap(self.fd(), caps.as_mut_ptr())?;
-            assert_eq!(res, 0);
-            Ok(Capabilities(caps.assume_init()))
+            let mut caps = mem::zeroed();
+            self.backend().querycap(&mut caps)?;
+            Ok(Capabilities(caps))
         }
     }
 
@@ -130,6 +162,37 @@ impl Device {
         FormatDescIter::new(self, buf_type)
     }
 
+    /// Enumerates the compressed formats supported by a memory-to-memory codec device.
+    ///
+    /// Decoders list their coded formats on the output queue, while encoders list them on the
+    /// capture queue, so both are enumerated. [`FormatDesc::buf_type`] tells them apart.
+    ///
+    /// The [`FormatFlags`] of the returned formats describe how the
+    /// bitstream has to be passed to the device.
+    pub fn coded_formats(&self) -> impl Iterator<Item = io::Result<FormatDesc>> + '_ {
+        let (output_type, capture_type) = self.m2m_buf_types();
+        self.formats(output_type)
+            .chain(self.formats(capture_type))
+            .filter(|res| match res {
+                Ok(desc) => desc.flags().contains(FormatFlags::COMPRESSED),
+                Err(_) => true,
+            })
+    }
+
+    /// Returns the output and capture buffer types to use for a memory-to-memory device.
+    ///
+    /// The single-planar API is preferred if the device supports both.
+    fn m2m_buf_types(&self) -> (BufType, BufType) {
+        let caps = self.available_capabilities;
+        if caps.contains(CapabilityFlags::VIDEO_M2M_MPLANE)
+            && !caps.contains(CapabilityFlags::VIDEO_M2M)
+        {
+            (BufType::VIDEO_OUTPUT_MPLANE, BufType::VIDEO_CAPTURE_MPLANE)
+        } else {
+            (BufType::VIDEO_OUTPUT, BufType::VIDEO_CAPTURE)
+        }
+    }
+
     /// Returns the supported frame sizes for a given pixel format.
     ///
     /// # Errors
@@ -176,86 +239,32 @@ impl Device {
 
     pub fn read_control_raw(&self, cid: Cid) -> io::Result<i32> {
         let mut control = raw::controls::Control { id: cid, value: 0 };
-
-        unsafe {
-            raw::g_ctrl(self.fd(), &mut control)?;
-        }
-
+        self.backend().g_ctrl(&mut control)?;
         Ok(control.value)
     }
 
     pub fn write_control_raw(&mut self, cid: Cid, value: i32) -> io::Result<()> {
         let mut control = raw::controls::Control { id: cid, value };
-        unsafe {
-            raw::s_ctrl(self.fd(), &mut control)?;
-        }
+        self.backend().s_ctrl(&mut control)?;
         Ok(())
     }
 
+    /// Returns the event queue of the device.
+    ///
+    /// Subscriptions made here stay active when the device is turned into a stream, and pending
+    /// events can be dequeued through the stream's `events` method.
+    pub fn events(&self) -> Events<'_> {
+        Events::new(self.handle.as_fd())
+    }
+
     /// Reads the stream format in use by `buf_type`.
     ///
     /// The returned [`Format`] variant will match `buf_type`.
     ///
-    /// If no format is set, this returns `EINVAL`.
-    ///
-    /// # Panics
-    ///
-    /// This will panic if `buf_type` corresponds to a buffer type that hasn't yet been implemented
-    /// in [`Format`].
+    /// If no format is set, or `buf_type` corresponds to a buffer type that hasn't yet been
+    /// implemented in [`Format`], this returns `EINVAL`.
     pub fn format(&self, buf_type: BufType) -> io::Result<Format> {
-        unsafe {
-            let mut format = raw::Format {
-                type_: buf_type,
-                ..mem::zeroed()
-            };
-            raw::g_fmt(self.fd(), &mut format)?;
-            let fmt = Format::from_raw(format)
-                .unwrap_or_else(|| todo!("unsupported buffer type {:?}", buf_type));
-            Ok(fmt)
-        }
-    }
-
-    /// Negotiates a stream's format.
-    ///
-    /// The driver will adjust the values in `format` to the closest values it supports (the variant
-    /// will not be changed). The modified `Format` is returned.
-    fn set_format_raw(&mut self, format: Format) -> io::Result<Format> {
-        unsafe {
-            let mut raw_format: raw::Format = mem::zeroed();
-            match format {
-                Format::VideoCapture(f) => {
-                    raw_format.type_ = BufType::VIDEO_CAPTURE;
-                    raw_format.fmt.pix = f.to_raw();
-                }
-                Format::VideoOutput(f) => {
-                    raw_format.type_ = BufType::VIDEO_OUTPUT;
-                    raw_format.fmt.pix = f.to_raw();
-                }
-                Format::VideoCaptureMplane(f) => {
-                    raw_format.type_ = BufType::VIDEO_CAPTURE_MPLANE;
-                    raw_format.fmt.pix_mp = f.to_raw();
-                }
-                Format::VideoOutputMplane(f) => {
-                    raw_format.type_ = BufType::VIDEO_OUTPUT_MPLANE;
-                    raw_format.fmt.pix_mp = f.to_raw();
-                }
-                Format::VideoOverlay(f) => {
-                    raw_format.type_ = BufType::VIDEO_OVERLAY;
-                    raw_format.fmt.win = f.to_raw();
-                }
-                Format::MetaCapture(f) => {
-                    raw_format.type_ = BufType::META_CAPTURE;
-                    raw_format.fmt.meta = f.to_raw();
-                }
-                Format::MetaOutput(f) => {
-                    raw_format.type_ = BufType::META_OUTPUT;
-                    raw_format.fmt.meta = f.to_raw();
-                }
-            }
-            raw::s_fmt(self.fd(), &mut raw_format)?;
-            let fmt = Format::from_raw(raw_format).unwrap();
-            Ok(fmt)
-        }
+        get_format_raw(self.backend(), buf_type)
     }
 
     /// Puts the device into video capture mode and negotiates a pixel format.
@@ -266,14 +275,14 @@ impl Device {
     /// the requested dimensions and the [`PixelFormat`], if the provided value is not supported.
     /// However, it is not required to do so and may instead return `EINVAL` if the parameters are
     /// not supported. One example where this happens is with `v4l2loopback`.
-    pub fn video_capture(mut self, format: PixFormat) -> io::Result<VideoCaptureDevice> {
-        let format = match self.set_format_raw(Format::VideoCapture(format))? {
+    pub fn video_capture(self, format: PixFormat) -> io::Result<VideoCaptureDevice> {
+        let format = match set_format_raw(self.backend(), Format::VideoCapture(format))? {
             Format::VideoCapture(fmt) => fmt,
             _ => unreachable!(),
         };
 
         Ok(VideoCaptureDevice {
-            file: self.file,
+            file: self.handle.into_file()?,
             format,
         })
     }
@@ -286,32 +295,172 @@ impl Device {
     /// the requested dimensions and the [`PixelFormat`], if the provided value is not supported.
     /// However, it is not required to do so and may instead return `EINVAL` if the parameters are
     /// not supported. One example where this happens is with `v4l2loopback`.
-    pub fn video_output(mut self, format: PixFormat) -> io::Result<VideoOutputDevice> {
-        let format = match self.set_format_raw(Format::VideoOutput(format))? {
+    pub fn video_output(self, format: PixFormat) -> io::Result<VideoOutputDevice> {
+        let format = match set_format_raw(self.backend(), Format::VideoOutput(format))? {
             Format::VideoOutput(fmt) => fmt,
             _ => unreachable!(),
         };
 
         Ok(VideoOutputDevice {
-            file: self.file,
+            file: self.handle.into_file()?,
+            format,
+        })
+    }
+
+    /// Puts the device into multi-planar video capture mode and negotiates a pixel format.
+    ///
+    /// This is required for devices that only support the
+    /// [`VIDEO_CAPTURE_MPLANE`][CapabilityFlags::VIDEO_CAPTURE_MPLANE] capability. The same
+    /// caveats as for [`Device::video_capture`] apply to the format negotiation.
+    pub fn video_capture_mplane(
+        self,
+        format: PixFormatMplane,
+    ) -> io::Result<VideoCaptureMplaneDevice> {
+        let format = match set_format_raw(self.backend(), Format::VideoCaptureMplane(format))? {
+            Format::VideoCaptureMplane(fmt) => fmt,
+            _ => unreachable!(),
+        };
+
+        Ok(VideoCaptureMplaneDevice {
+            file: self.handle.into_file()?,
             format,
         })
     }
 
+    /// Puts the device into multi-planar video output mode and negotiates a pixel format.
+    ///
+    /// This is required for devices that only support the
+    /// [`VIDEO_OUTPUT_MPLANE`][CapabilityFlags::VIDEO_OUTPUT_MPLANE] capability. The same caveats
+    /// as for [`Device::video_output`] apply to the format negotiation.
+    pub fn video_output_mplane(
+        self,
+        format: PixFormatMplane,
+    ) -> io::Result<VideoOutputMplaneDevice> {
+        let format = match set_format_raw(self.backend(), Format::VideoOutputMplane(format))? {
+            Format::VideoOutputMplane(fmt) => fmt,
+            _ => unreachable!(),
+        };
+
+        Ok(VideoOutputMplaneDevice {
+            file: self.handle.into_file()?,
+            format,
+        })
+    }
+
+    /// Puts a memory-to-memory device into video processing mode and negotiates the formats of
+    /// both of its queues.
+    ///
+    /// Memory-to-memory devices (like hardware scalers, color space converters and codecs) have
+    /// [`VIDEO_M2M`][CapabilityFlags::VIDEO_M2M] capability. They process frames written to
+    /// their output queue (in the `output` format) and return the results on their capture queue
+    /// (in the `capture` format). The same caveats as for [`Device::video_capture`] apply to the
+    /// format negotiation.
+    pub fn video_m2m(self, output: PixFormat, capture: PixFormat) -> io::Result<VideoM2mDevice> {
+        // The output format is set first, since some drivers derive the capture format from it.
+        let output_format = match set_format_raw(self.backend(), Format::VideoOutput(output))? {
+            Format::VideoOutput(fmt) => fmt,
+            _ => unreachable!(),
+        };
+        let capture_format = match set_format_raw(self.backend(), Format::VideoCapture(capture))? {
+            Format::VideoCapture(fmt) => fmt,
+            _ => unreachable!(),
+        };
+
+        Ok(VideoM2mDevice {
+            file: self.handle.into_file()?,
+            output_format,
+            capture_format,
+        })
+    }
+
+    /// Puts a multi-planar memory-to-memory device into video processing mode and negotiates the
+    /// formats of both of its queues.
+    ///
+    /// This is required for devices that only support the
+    /// [`VIDEO_M2M_MPLANE`][CapabilityFlags::VIDEO_M2M_MPLANE] capability. See
+    /// [`Device::video_m2m`] for details.
+    pub fn video_m2m_mplane(
+        self,
+        output: PixFormatMplane,
+        capture: PixFormatMplane,
+    ) -> io::Result<VideoM2mMplaneDevice> {
+        let output_format = match set_format_raw(self.backend(), Format::VideoOutputMplane(output))?
+        {
+            Format::VideoOutputMplane(fmt) => fmt,
+            _ => unreachable!(),
+        };
+        let capture_format =
+            match set_format_raw(self.backend(), Format::VideoCaptureMplane(capture))? {
+                Format::VideoCaptureMplane(fmt) => fmt,
+                _ => unreachable!(),
+            };
+
+        Ok(VideoM2mMplaneDevice {
+            file: self.handle.into_file()?,
+            output_format,
+            capture_format,
+        })
+    }
+
+    /// Puts a stateful decoder into decoding mode.
+    ///
+    /// `coded` is the format of the bitstream that will be passed to the decoder, and should use
+    /// one of the pixel formats returned by [`Device::coded_formats`]. Its dimensions may be left
+    /// at 0 if they are not known in advance, since the decoder determines them from the
+    /// bitstream. The size of the bitstream buffers can be set with
+    /// [`PixFormat::set_size_image`].
+    ///
+    /// The format of the decoded frames is negotiated by the driver once it has parsed the
+    /// stream headers. Devices that only support the multi-planar API are handled transparently.
+    pub fn video_decoder(self, coded: PixFormat) -> io::Result<Decoder> {
+        let (output_type, capture_type) = self.m2m_buf_types();
+        Decoder::new(self.handle.into_file()?, output_type, capture_type, coded)
+    }
+
+    /// Puts a stateful encoder into encoding mode.
+    ///
+    /// `frames` is the format of the raw frames that will be passed to the encoder, and `coded`
+    /// is the format of the bitstream it should produce. `coded` should use one of the pixel
+    /// formats returned by [`Device::coded_formats`], and its dimensions should match those of
+    /// `frames`.
+    ///
+    /// Devices that only support the multi-planar API are handled transparently, but the raw
+    /// frames have to use a format that stores all planes in a single buffer.
+    pub fn video_encoder(self, frames: PixFormat, coded: PixFormat) -> io::Result<Encoder> {
+        let (output_type, capture_type) = self.m2m_buf_types();
+        Encoder::new(
+            self.handle.into_file()?,
+            output_type,
+            capture_type,
+            frames,
+            coded,
+        )
+    }
+
     /// Puts the device into metadata capture mode and negotiates a data format.
-    pub fn meta_capture(mut self, format: MetaFormat) -> io::Result<MetaCaptureDevice> {
-        let format = match self.set_format_raw(Format::MetaCapture(format))? {
+    pub fn meta_capture(self, format: MetaFormat) -> io::Result<MetaCaptureDevice> {
+        let format = match set_format_raw(self.backend(), Format::MetaCapture(format))? {
             Format::MetaCapture(fmt) => fmt,
             _ => unreachable!(),
         };
 
         Ok(MetaCaptureDevice {
-            file: self.file,
+            file: self.handle.into_file()?,
             format,
         })
     }
 }
 
+impl AsRawFd for Device {
+    /// Returns the file descriptor of the device.
+    ///
+    /// It can be polled for `POLLPRI` to wait for [`Device::events`].
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.handle.as_fd().as_raw_fd()
+    }
+}
+
 /// A video device configured for video capture.
 pub struct VideoCaptureDevice {
     file: File,
@@ -333,33 +482,54 @@ impl VideoCaptureDevice {
     /// Supported frame intervals depend on the pixel format and video resolution and can be
     /// enumerated with [`Device::frame_intervals`].
     pub fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
-        unsafe {
-            let mut parm = raw::StreamParm {
-                type_: BufType::VIDEO_CAPTURE,
-                union: raw::StreamParmUnion {
-                    capture: raw::CaptureParm {
-                        timeperframe: interval,
-                        capability: StreamParamCaps::TIMEPERFRAME,
-                        capturemode: CaptureParamFlags::empty(),
-                        extendedmode: 0,
-                        readbuffers: 0,
-                        reserved: [0; 4],
-                    },
-                },
-            };
-            raw::s_parm(self.file.as_raw_fd(), &mut parm)?;
-            Ok(parm.union.capture.timeperframe)
-        }
+        set_capture_frame_interval(&self.file, BufType::VIDEO_CAPTURE, interval)
     }
 
-    /// Initializes streaming I/O mode with the given number of buffers.
-    pub fn into_stream(self) -> io::Result<ReadStream> {
-        Ok(ReadStream::new(
-            self.file,
-            BufType::VIDEO_CAPTURE,
-            Memory::MMAP,
-            DEFAULT_BUFFER_COUNT,
-        )?)
+    /// Returns the current frame interval.
+    ///
+    /// Returns an `ENOTTY` error if the driver does not support frame interval configuration.
+    pub fn frame_interval(&self) -> io::Result<Fract> {
+        get_capture_frame_interval(&self.file, BufType::VIDEO_CAPTURE)
+    }
+
+    /// Initializes streaming I/O mode.
+    pub fn into_stream(self) -> io::Result<ReadStream<'static>> {
+        self.into_stream_with(StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode with the buffer configuration in `builder`.
+    pub fn into_stream_with<'a>(self, builder: StreamBuilder<'a>) -> io::Result<ReadStream<'a>> {
+        ReadStream::new(self.file, BufType::VIDEO_CAPTURE, builder)
+    }
+
+    /// Initializes streaming I/O mode, capturing into application-provided buffers.
+    ///
+    /// This uses the `USERPTR` memory type, which lets the driver write frames directly into
+    /// `buffers` without copying them out of a driver-allocated buffer first. Each buffer should be
+    /// at least [`PixFormat::size_image`] bytes in size. Some drivers additionally require the
+    /// buffers to be page-aligned.
+    ///
+    /// The buffers stay borrowed for as long as the returned [`ReadStream`] exists. If the driver
+    /// can not use all of the provided buffers, the excess ones are left unused.
+    pub fn into_userptr_stream<'a>(self, buffers: Vec<&'a mut [u8]>) -> io::Result<ReadStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().userptr(buffers))
+    }
+
+    /// Initializes streaming I/O mode, capturing into application-provided DMA-BUFs.
+    ///
+    /// This uses the `DMABUF` memory type, which allows sharing buffers with other devices (for
+    /// example, a GPU or another V4L2 device) without copying. Each DMA-BUF should be at least
+    /// [`PixFormat::size_image`] bytes in size.
+    ///
+    /// The DMA-BUFs are not mapped into the process, so the
+    /// [`ReadBufferView`][stream::ReadBufferView]s returned by the stream contain no data.
+    /// [`ReadBufferView::index`][stream::ReadBufferView::index] tells which of the DMA-BUFs was
+    /// filled. Use [`StreamBuilder::dmabuf_mapped`] to access the data through the views instead.
+    ///
+    /// The file descriptors stay borrowed for as long as the returned [`ReadStream`] exists. If the
+    /// driver can not use all of the provided DMA-BUFs, the excess ones are left unused.
+    pub fn into_dmabuf_stream<'a>(self, fds: Vec<BorrowedFd<'a>>) -> io::Result<ReadStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().dmabuf(fds))
     }
 }
 
@@ -373,6 +543,48 @@ impl Read for VideoCaptureDevice {
     }
 }
 
+/// A video device configured for multi-planar video capture.
+pub struct VideoCaptureMplaneDevice {
+    file: File,
+    format: PixFormatMplane,
+}
+
+impl VideoCaptureMplaneDevice {
+    /// Returns the pixel format the driver chose for capturing.
+    ///
+    /// This may (and usually will) differ from the format passed to
+    /// [`Device::video_capture_mplane`].
+    pub fn format(&self) -> &PixFormatMplane {
+        &self.format
+    }
+
+    /// Requests a change to the frame interval.
+    ///
+    /// Returns the actual frame interval chosen by the driver.
+    pub fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        set_capture_frame_interval(&self.file, BufType::VIDEO_CAPTURE_MPLANE, interval)
+    }
+
+    /// Returns the current frame interval.
+    ///
+    /// Returns an `ENOTTY` error if the driver does not support frame interval configuration.
+    pub fn frame_interval(&self) -> io::Result<Fract> {
+        get_capture_frame_interval(&self.file, BufType::VIDEO_CAPTURE_MPLANE)
+    }
+
+    /// Initializes streaming I/O mode.
+    ///
+    /// The buffers of the returned stream have one plane per plane of the negotiated format.
+    pub fn into_stream(self) -> io::Result<ReadStream<'static>> {
+        self.into_stream_with(StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode with the buffer configuration in `builder`.
+    pub fn into_stream_with<'a>(self, builder: StreamBuilder<'a>) -> io::Result<ReadStream<'a>> {
+        ReadStream::new(self.file, BufType::VIDEO_CAPTURE_MPLANE, builder)
+    }
+}
+
 /// A video device configured for video output.
 pub struct VideoOutputDevice {
     file: File,
@@ -386,13 +598,45 @@ impl VideoOutputDevice {
     }
 
     /// Initializes streaming I/O mode.
-    pub fn into_stream(self) -> io::Result<WriteStream> {
-        Ok(WriteStream::new(
-            self.file,
-            BufType::VIDEO_CAPTURE,
-            Memory::MMAP,
-            DEFAULT_BUFFER_COUNT,
-        )?)
+    pub fn into_stream(self) -> io::Result<WriteStream<'static>> {
+        self.into_stream_with(StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode with the buffer configuration in `builder`.
+    pub fn into_stream_with<'a>(self, builder: StreamBuilder<'a>) -> io::Result<WriteStream<'a>> {
+        WriteStream::new(self.file, BufType::VIDEO_OUTPUT, builder)
+    }
+
+    /// Initializes streaming I/O mode, outputting from application-provided buffers.
+    ///
+    /// This uses the `USERPTR` memory type. Each buffer should be at least
+    /// [`PixFormat::size_image`] bytes in size. Some drivers additionally require the buffers to be
+    /// page-aligned.
+    ///
+    /// The buffers stay borrowed for as long as the returned [`WriteStream`] exists. If the driver
+    /// can not use all of the provided buffers, the excess ones are left unused.
+    pub fn into_userptr_stream<'a>(
+        self,
+        buffers: Vec<&'a mut [u8]>,
+    ) -> io::Result<WriteStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().userptr(buffers))
+    }
+
+    /// Initializes streaming I/O mode, outputting from application-provided DMA-BUFs.
+    ///
+    /// This uses the `DMABUF` memory type, which allows sharing buffers with other devices (for
+    /// example, a GPU or another V4L2 device) without copying. Each DMA-BUF should be at least
+    /// [`PixFormat::size_image`] bytes in size.
+    ///
+    /// The DMA-BUFs are not mapped into the process, so the
+    /// [`WriteBufferView`][stream::WriteBufferView]s of the stream are empty, and the application
+    /// fills the DMA-BUF indicated by [`WriteBufferView::index`][stream::WriteBufferView::index]
+    /// itself. Use [`StreamBuilder::dmabuf_mapped`] to write through the views instead.
+    ///
+    /// The file descriptors stay borrowed for as long as the returned [`WriteStream`] exists. If the
+    /// driver can not use all of the provided DMA-BUFs, the excess ones are left unused.
+    pub fn into_dmabuf_stream<'a>(self, fds: Vec<BorrowedFd<'a>>) -> io::Result<WriteStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().dmabuf(fds))
     }
 }
 
@@ -413,6 +657,111 @@ impl Write for VideoOutputDevice {
     }
 }
 
+/// A video device configured for multi-planar video output.
+pub struct VideoOutputMplaneDevice {
+    file: File,
+    format: PixFormatMplane,
+}
+
+impl VideoOutputMplaneDevice {
+    /// Returns the video format chosen by the driver.
+    pub fn format(&self) -> &PixFormatMplane {
+        &self.format
+    }
+
+    /// Initializes streaming I/O mode.
+    ///
+    /// The buffers of the returned stream have one plane per plane of the negotiated format.
+    pub fn into_stream(self) -> io::Result<WriteStream<'static>> {
+        self.into_stream_with(StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode with the buffer configuration in `builder`.
+    pub fn into_stream_with<'a>(self, builder: StreamBuilder<'a>) -> io::Result<WriteStream<'a>> {
+        WriteStream::new(self.file, BufType::VIDEO_OUTPUT_MPLANE, builder)
+    }
+}
+
+/// A memory-to-memory device configured for video processing.
+pub struct VideoM2mDevice {
+    file: File,
+    output_format: PixFormat,
+    capture_format: PixFormat,
+}
+
+impl VideoM2mDevice {
+    /// Returns the format of the frames passed to the device, as chosen by the driver.
+    pub fn output_format(&self) -> &PixFormat {
+        &self.output_format
+    }
+
+    /// Returns the format of the frames returned by the device, as chosen by the driver.
+    pub fn capture_format(&self) -> &PixFormat {
+        &self.capture_format
+    }
+
+    /// Initializes streaming I/O mode on both queues.
+    pub fn into_stream(self) -> io::Result<M2mStream<'static>> {
+        self.into_stream_with(StreamBuilder::new(), StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode on both queues, with separate buffer configurations for the
+    /// output and capture queue.
+    pub fn into_stream_with<'a>(
+        self,
+        output: StreamBuilder<'a>,
+        capture: StreamBuilder<'a>,
+    ) -> io::Result<M2mStream<'a>> {
+        M2mStream::new(
+            self.file,
+            BufType::VIDEO_OUTPUT,
+            output,
+            BufType::VIDEO_CAPTURE,
+            capture,
+        )
+    }
+}
+
+/// A multi-planar memory-to-memory device configured for video processing.
+pub struct VideoM2mMplaneDevice {
+    file: File,
+    output_format: PixFormatMplane,
+    capture_format: PixFormatMplane,
+}
+
+impl VideoM2mMplaneDevice {
+    /// Returns the format of the frames passed to the device, as chosen by the driver.
+    pub fn output_format(&self) -> &PixFormatMplane {
+        &self.output_format
+    }
+
+    /// Returns the format of the frames returned by the device, as chosen by the driver.
+    pub fn capture_format(&self) -> &PixFormatMplane {
+        &self.capture_format
+    }
+
+    /// Initializes streaming I/O mode on both queues.
+    pub fn into_stream(self) -> io::Result<M2mStream<'static>> {
+        self.into_stream_with(StreamBuilder::new(), StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode on both queues, with separate buffer configurations for the
+    /// output and capture queue.
+    pub fn into_stream_with<'a>(
+        self,
+        output: StreamBuilder<'a>,
+        capture: StreamBuilder<'a>,
+    ) -> io::Result<M2mStream<'a>> {
+        M2mStream::new(
+            self.file,
+            BufType::VIDEO_OUTPUT_MPLANE,
+            output,
+            BufType::VIDEO_CAPTURE_MPLANE,
+            capture,
+        )
+    }
+}
+
 /// A device configured for metadata capture.
 pub struct MetaCaptureDevice {
     file: File,
@@ -426,13 +775,36 @@ impl MetaCaptureDevice {
     }
 
     /// Initializes streaming I/O mode.
-    pub fn into_stream(self) -> io::Result<ReadStream> {
-        Ok(ReadStream::new(
-            self.file,
-            BufType::META_CAPTURE,
-            Memory::MMAP,
-            DEFAULT_BUFFER_COUNT,
-        )?)
+    pub fn into_stream(self) -> io::Result<ReadStream<'static>> {
+        self.into_stream_with(StreamBuilder::new())
+    }
+
+    /// Initializes streaming I/O mode with the buffer configuration in `builder`.
+    pub fn into_stream_with<'a>(self, builder: StreamBuilder<'a>) -> io::Result<ReadStream<'a>> {
+        ReadStream::new(self.file, BufType::META_CAPTURE, builder)
+    }
+
+    /// Initializes streaming I/O mode, capturing into application-provided buffers.
+    ///
+    /// This uses the `USERPTR` memory type. Each buffer should be at least
+    /// [`MetaFormat::buffer_size`] bytes in size.
+    ///
+    /// The buffers stay borrowed for as long as the returned [`ReadStream`] exists. If the driver
+    /// can not use all of the provided buffers, the excess ones are left unused.
+    pub fn into_userptr_stream<'a>(self, buffers: Vec<&'a mut [u8]>) -> io::Result<ReadStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().userptr(buffers))
+    }
+
+    /// Initializes streaming I/O mode, capturing into application-provided DMA-BUFs.
+    ///
+    /// This uses the `DMABUF` memory type. Each DMA-BUF should be at least
+    /// [`MetaFormat::buffer_size`] bytes in size. The DMA-BUFs are not mapped into the process,
+    /// see [`StreamBuilder::dmabuf_mapped`] for accessing their data through the stream.
+    ///
+    /// The file descriptors stay borrowed for as long as the returned [`ReadStream`] exists. If the
+    /// driver can not use all of the provided DMA-BUFs, the excess ones are left unused.
+    pub fn into_dmabuf_stream<'a>(self, fds: Vec<BorrowedFd<'a>>) -> io::Result<ReadStream<'a>> {
+        self.into_stream_with(StreamBuilder::new().dmabuf(fds))
     }
 }
 
@@ -449,6 +821,7 @@ impl Read for MetaCaptureDevice {
 /// Stores generic device information.
 ///
 /// Returned by [`Device::capabilities`].
+#[derive(Clone)]
 pub struct Capabilities(raw::Capabilities);
 
 impl Capabilities {
@@ -474,6 +847,8 @@ impl Capabilities {
     /// Examples:
     /// - `usb-0000:0a:00.3-2.1`
     /// - `platform:v4l2loopback-002`
+    ///
+    /// [`sysfs::physical_devices`] groups the device nodes that belong to the same device.
     pub fn bus_info(&self) -> &str {
         byte_array_to_str(&self.0.bus_info)
     }
@@ -528,7 +903,7 @@ impl Iterator for OutputIter<'_> {
                 index: self.next_index,
                 ..mem::zeroed()
             };
-            match raw::enumoutput(self.device.fd(), &mut raw) {
+            match self.device.backend().enumoutput(&mut raw) {
                 Ok(_) => {}
                 Err(e) => {
                     self.finished = true;
@@ -566,7 +941,7 @@ impl Iterator for InputIter<'_> {
                 index: self.next_index,
                 ..mem::zeroed()
             };
-            match raw::enuminput(self.device.fd(), &mut raw) {
+            match self.device.backend().enuminput(&mut raw) {
                 Ok(_) => {}
                 Err(e) => {
                     self.finished = true;
@@ -719,6 +1094,73 @@ impl fmt::Debug for Input {
     }
 }
 
+/// Reads the stream format in use by `buf_type`.
+///
+/// See [`Device::format`].
+pub(crate) fn get_format_raw(backend: &dyn Backend, buf_type: BufType) -> io::Result<Format> {
+    unsafe {
+        let mut format = raw::Format {
+            type_: buf_type,
+            ..mem::zeroed()
+        };
+        backend.g_fmt(&mut format)?;
+        Ok(Format::from_raw(format).ok_or(Errno::EINVAL)?)
+    }
+}
+
+/// Negotiates a stream's format.
+///
+/// The driver will adjust the values in `format` to the closest values it supports (the variant
+/// will not be changed). The modified `Format` is returned.
+pub(crate) fn set_format_raw(backend: &dyn Backend, format: Format) -> io::Result<Format> {
+    unsafe {
+        let mut raw_format = format.into_raw();
+        backend.s_fmt(&mut raw_format)?;
+        Ok(Format::from_raw(raw_format).ok_or(Errno::EINVAL)?)
+    }
+}
+
+pub(crate) fn get_capture_frame_interval(file: &File, buf_type: BufType) -> io::Result<Fract> {
+    unsafe {
+        let mut parm = raw::StreamParm {
+            type_: buf_type,
+            union: raw::StreamParmUnion { raw_data: [0; 200] },
+        };
+        raw::g_parm(file.as_raw_fd(), &mut parm)?;
+        let capture = parm.union.capture;
+        if !capture.capability.contains(StreamParamCaps::TIMEPERFRAME)
+            || capture.timeperframe.denominator() == 0
+        {
+            return Err(Errno::ENOTTY.into());
+        }
+        Ok(capture.timeperframe)
+    }
+}
+
+pub(crate) fn set_capture_frame_interval(
+    file: &File,
+    buf_type: BufType,
+    interval: Fract,
+) -> io::Result<Fract> {
+    unsafe {
+        let mut parm = raw::StreamParm {
+            type_: buf_type,
+            union: raw::StreamParmUnion {
+                capture: raw::CaptureParm {
+                    timeperframe: interval,
+                    capability: StreamParamCaps::TIMEPERFRAME,
+                    capturemode: CaptureParamFlags::empty(),
+                    extendedmode: 0,
+                    readbuffers: 0,
+                    reserved: [0; 4],
+                },
+            },
+        };
+        raw::s_parm(file.as_raw_fd(), &mut parm)?;
+        Ok(parm.union.capture.timeperframe)
+    }
+}
+
 /// Turns a zero-padded byte array containing UTF-8 or ASCII data into a `&str`.
 fn byte_array_to_str(bytes: &[u8]) -> &str {
     let len = bytes
@@ -727,3 +1169,13 @@ fn byte_array_to_str(bytes: &[u8]) -> &str {
         .expect("missing NUL terminator");
     std::str::from_utf8(&bytes[..len]).unwrap()
 }
+
+/// Turns a `&str` into a zero-padded byte array, truncating it if it doesn't fit.
+#[cfg(any(test, feature = "mock"))]
+fn str_to_byte_array<const N: usize>(s: &str) -> [u8; N] {
+    let mut bytes = [0; N];
+    // Leave room for the NUL terminator.
+    let len = s.len().min(N - 1);
+    bytes[..len].copy_from_slice(&s.as_bytes()[..len]);
+    bytes
+}
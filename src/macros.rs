@@ -15,6 +15,7 @@ macro_rules! ffi_enum {
         #[repr(transparent)]
         $v struct $name(pub(crate) $native);
 
+        #[allow(dead_code)] // not every FFI constant is used by the library itself
         impl $name {
             $(
                 $( #[$variant_attrs] )*
@@ -0,0 +1,245 @@
+//! Media controller devices and the Request API.
+//!
+//! Requests bundle buffers and control values, so that the driver applies the controls to exactly
+//! the frames whose buffers are part of the request. They are required by stateless codecs, which
+//! receive the parsed bitstream headers as controls (starting at
+//! [`Cid::CODEC_STATELESS_BASE`][crate::controls::Cid::CODEC_STATELESS_BASE]), and can be used by
+//! capture drivers to apply settings like exposure and gain to specific frames.
+//!
+//! Requests are allocated through the media controller device (`/dev/mediaN`) that the video
+//! device belongs to. Buffers are added to a request with [`WriteStream::enqueue_in_request`] or
+//! [`ReadStream::enqueue_in_request`], and controls with [`Request::set_controls`].
+//!
+//! [`WriteStream::enqueue_in_request`]: crate::stream::WriteStream::enqueue_in_request
+//! [`ReadStream::enqueue_in_request`]: crate::stream::ReadStream::enqueue_in_request
+
+mod raw;
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io,
+    mem::MaybeUninit,
+    os::raw::c_int,
+    os::unix::prelude::*,
+    path::Path,
+    time::Duration,
+};
+
+use nix::{errno::Errno, poll::PollFlags};
+
+use crate::byte_array_to_str;
+use crate::controls::ExtControl;
+use crate::stream::poll_fd;
+
+/// A media controller device.
+pub struct MediaDevice {
+    file: File,
+}
+
+impl MediaDevice {
+    /// Opens a media controller device file from the given path.
+    ///
+    /// If the path does not refer to a media controller device node, an error will be returned.
+    pub fn open<A: AsRef<Path>>(path: A) -> io::Result<Self> {
+        Self::open_impl(path.as_ref())
+    }
+
+    fn open_impl(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let this = Self { file };
+        // Fails if this is not a media device.
+        this.info()?;
+        Ok(this)
+    }
+
+    /// Queries information about the media device.
+    pub fn info(&self) -> io::Result<DeviceInfo> {
+        unsafe {
+            let mut info = MaybeUninit::zeroed();
+            raw::device_info(self.file.as_raw_fd(), info.as_mut_ptr())?;
+            Ok(DeviceInfo(info.assume_init()))
+        }
+    }
+
+    /// Allocates a new [`Request`].
+    ///
+    /// Returns an `ENOTTY` error if the driver does not support requests.
+    pub fn alloc_request(&self) -> io::Result<Request> {
+        let mut fd: c_int = -1;
+        unsafe {
+            raw::request_alloc(self.file.as_raw_fd(), &mut fd)?;
+            Ok(Request {
+                fd: OwnedFd::from_raw_fd(fd),
+            })
+        }
+    }
+}
+
+impl AsRawFd for MediaDevice {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Information about a [`MediaDevice`].
+pub struct DeviceInfo(raw::DeviceInfo);
+
+impl DeviceInfo {
+    /// Returns the name of the driver that provides this device.
+    pub fn driver(&self) -> &str {
+        byte_array_to_str(&self.0.driver)
+    }
+
+    /// Returns the device model name.
+    pub fn model(&self) -> &str {
+        byte_array_to_str(&self.0.model)
+    }
+
+    /// Returns the serial number of the device, or an empty string if it has none.
+    pub fn serial(&self) -> &str {
+        byte_array_to_str(&self.0.serial)
+    }
+
+    /// Returns a description of where on the system the device is attached.
+    ///
+    /// This matches the [`Capabilities::bus_info`][crate::Capabilities::bus_info] of the video
+    /// devices belonging to the media device.
+    pub fn bus_info(&self) -> &str {
+        byte_array_to_str(&self.0.bus_info)
+    }
+}
+
+impl fmt::Debug for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceInfo")
+            .field("driver", &self.driver())
+            .field("model", &self.model())
+            .field("serial", &self.serial())
+            .field("bus_info", &self.bus_info())
+            .finish()
+    }
+}
+
+/// A media request, allocated by [`MediaDevice::alloc_request`].
+///
+/// A request goes through the following steps:
+///
+/// - Buffers and control values are added to it.
+/// - It is queued with [`Request::queue`], which passes its contents to the driver.
+/// - The driver completes it once all of its buffers are processed. This can be waited for with
+///   [`Request::wait`], or by polling the request's file descriptor for `POLLPRI`.
+/// - The buffers are dequeued from their streams as usual.
+/// - [`Request::reinit`] makes the request reusable.
+pub struct Request {
+    fd: OwnedFd,
+}
+
+impl Request {
+    /// Sets control values as part of this request.
+    ///
+    /// `device` is the device node the controls belong to. This is typically the video device
+    /// the request's buffers are queued to, or one of its streams, but can also be a
+    /// sub-device, for example to set the exposure time of a camera sensor.
+    ///
+    /// The values are applied when the driver processes the request, and are validated when the
+    /// request is queued.
+    pub fn set_controls(
+        &self,
+        device: &impl AsRawFd,
+        controls: &[ExtControl<'_>],
+    ) -> io::Result<()> {
+        let mut raw_controls = controls.iter().map(|c| c.to_raw()).collect::<Vec<_>>();
+        let mut raw = crate::raw::controls::ExtControls {
+            which: crate::raw::controls::CTRL_WHICH_REQUEST_VAL,
+            count: raw_controls.len() as u32,
+            error_idx: 0,
+            request_fd: self.fd.as_raw_fd(),
+            reserved: [0],
+            controls: raw_controls.as_mut_ptr(),
+        };
+        unsafe {
+            crate::raw::s_ext_ctrls(device.as_raw_fd(), &mut raw)?;
+        }
+        Ok(())
+    }
+
+    /// Queues the request, passing its buffers and control values to the driver.
+    ///
+    /// The request cannot be modified until it has completed and has been reinitialized.
+    ///
+    /// Returns an `ENOENT` error if no buffers were added to the request.
+    pub fn queue(&self) -> io::Result<()> {
+        unsafe {
+            raw::request_queue(self.fd.as_raw_fd())?;
+        }
+        Ok(())
+    }
+
+    /// Reinitializes a completed (or never queued) request so that it can be reused.
+    ///
+    /// This removes all buffers and control values from the request.
+    pub fn reinit(&self) -> io::Result<()> {
+        unsafe {
+            raw::request_reinit(self.fd.as_raw_fd())?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until the request has completed.
+    ///
+    /// Returns an `EINVAL` error if the request was not queued.
+    pub fn wait(&self) -> io::Result<()> {
+        self.poll(None).map(drop)
+    }
+
+    /// Waits up to `timeout` for the request to complete.
+    ///
+    /// Returns `Ok(false)` if the request has not completed before the timeout expired, and an
+    /// `EINVAL` error if the request was not queued.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<bool> {
+        self.poll(Some(timeout))
+    }
+
+    /// Returns whether the request has completed, without blocking.
+    pub fn is_complete(&self) -> io::Result<bool> {
+        self.wait_timeout(Duration::ZERO)
+    }
+
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let ready = poll_fd(self.fd.as_raw_fd(), PollFlags::POLLPRI, timeout)?;
+        if ready.is_empty() {
+            return Ok(false);
+        }
+
+        if ready.contains(PollFlags::POLLPRI) {
+            Ok(true)
+        } else {
+            // The driver signals `POLLERR` for requests that are not queued.
+            Err(Errno::EINVAL.into())
+        }
+    }
+}
+
+impl AsRawFd for Request {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for Request {
+    #[inline]
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("fd", &self.fd.as_raw_fd())
+            .finish()
+    }
+}
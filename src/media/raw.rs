@@ -0,0 +1,23 @@
+//! FFI definitions compatible with `media.h`.
+
+use std::os::raw::c_int;
+
+use nix::{ioctl_none, ioctl_read, ioctl_readwrite};
+
+#[repr(C)]
+pub struct DeviceInfo {
+    pub driver: [u8; 16],
+    pub model: [u8; 32],
+    pub serial: [u8; 40],
+    pub bus_info: [u8; 32],
+    pub media_version: u32,
+    pub hw_revision: u32,
+    pub driver_version: u32,
+    pub reserved: [u32; 31],
+}
+
+ioctl_readwrite!(device_info, '|', 0x00, DeviceInfo);
+ioctl_read!(request_alloc, '|', 0x05, c_int);
+
+ioctl_none!(request_queue, '|', 0x80);
+ioctl_none!(request_reinit, '|', 0x81);
@@ -0,0 +1,636 @@
+//! Scriptable mock devices for testing code that uses [`Device`] without hardware.
+//!
+//! This module requires the `mock` cargo feature.
+//!
+//! A [`MockDevice`] describes a driver: its capabilities, formats, frame sizes and intervals,
+//! controls and inputs. Once turned into a [`Device`] with [`MockDevice::into_device`], the
+//! ioctls issued by [`Device`] and the iterators it returns are answered from that description,
+//! following the same rules as the kernel (for example, enumerations end with `EINVAL`).
+//!
+//! Drivers with quirks can be simulated by describing the quirky behavior, for example with
+//! [`MockFrameIntervals::Invalid`] or [`MockDevice::without_next_ctrl`], or by making an ioctl fail
+//! with [`MockDevice::fail`].
+//!
+//! Mock devices support enumeration, control access and format negotiation, during which
+//! single-planar video formats are adjusted to the enumerated formats and frame sizes like a driver
+//! would. Methods that need a real device file, like the ones that turn a [`Device`] into a
+//! stream, fail with `ENOTTY`.
+//! Capture streams can be simulated with a [`SimDevice`][crate::sim::SimDevice], which is built on
+//! top of a mock device.
+
+use std::sync::Mutex;
+use std::{fmt, io, mem};
+
+use nix::errno::Errno;
+
+use crate::backend::{Backend, DeviceHandle};
+use crate::controls::{Cid, ControlFlags, CtrlType};
+use crate::format::{FormatFlags, PixelFormat};
+use crate::shared::{FrmIvalType, FrmSizeType, CONTROL_FLAGS_NEXT_CTRL};
+use crate::{
+    raw, str_to_byte_array, BufType, CapabilityFlags, Device, Field, Fract, InputType, OutputType,
+};
+
+/// The ioctls answered by a [`MockDevice`].
+///
+/// Used to inject failures with [`MockDevice::fail`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MockIoctl {
+    /// `VIDIOC_QUERYCAP`, used by [`Device::capabilities`].
+    QueryCap,
+    /// `VIDIOC_ENUM_FMT`, used by [`Device::formats`].
+    EnumFmt,
+    /// `VIDIOC_ENUM_FRAMESIZES`, used by [`Device::frame_sizes`].
+    EnumFrameSizes,
+    /// `VIDIOC_ENUM_FRAMEINTERVALS`, used by [`Device::frame_intervals`].
+    EnumFrameIntervals,
+    /// `VIDIOC_ENUMINPUT`, used by [`Device::inputs`].
+    EnumInput,
+    /// `VIDIOC_ENUMOUTPUT`, used by [`Device::outputs`].
+    EnumOutput,
+    /// `VIDIOC_QUERYCTRL`, used by [`Device::controls`].
+    QueryCtrl,
+    /// `VIDIOC_QUERYMENU`, used by [`Device::enumerate_menu`].
+    QueryMenu,
+    /// `VIDIOC_G_CTRL`, used by [`Device::read_control_raw`].
+    GCtrl,
+    /// `VIDIOC_S_CTRL`, used by [`Device::write_control_raw`].
+    SCtrl,
+    /// `VIDIOC_G_FMT`, used by [`Device::format`].
+    GFmt,
+    /// `VIDIOC_S_FMT`, used by format negotiation methods like [`Device::video_capture`].
+    SFmt,
+}
+
+/// The frame sizes reported by a [`MockDevice`] for a pixel format.
+#[derive(Debug, Clone)]
+pub enum MockFrameSizes {
+    /// A list of `(width, height)` pairs.
+    Discrete(Vec<(u32, u32)>),
+    /// A range of sizes, with the given `(width, height)` step size.
+    Stepwise {
+        min: (u32, u32),
+        max: (u32, u32),
+        step: (u32, u32),
+    },
+    /// A range of sizes, with a step size of 1.
+    Continuous { min: (u32, u32), max: (u32, u32) },
+}
+
+impl MockFrameSizes {
+    /// Returns the supported size closest to `width`x`height`.
+    fn nearest(&self, width: u32, height: u32) -> (u32, u32) {
+        let snap = |value: u32, a: u32, b: u32, step: u32| {
+            // Tolerate ranges described with `min > max` instead of panicking in `clamp`.
+            let (min, max) = (a.min(b), a.max(b));
+            let step = step.max(1);
+            min + (value.clamp(min, max) - min) / step * step
+        };
+        match *self {
+            Self::Discrete(ref sizes) => sizes
+                .iter()
+                .copied()
+                .min_by_key(|&(w, h)| u64::from(w.abs_diff(width)) + u64::from(h.abs_diff(height)))
+                .unwrap_or((width, height)),
+            Self::Stepwise { min, max, step } => (
+                snap(width, min.0, max.0, step.0),
+                snap(height, min.1, max.1, step.1),
+            ),
+            Self::Continuous { min, max } => {
+                (snap(width, min.0, max.0, 1), snap(height, min.1, max.1, 1))
+            }
+        }
+    }
+}
+
+/// The frame intervals reported by a [`MockDevice`] for a pixel format and frame size.
+#[derive(Debug, Clone)]
+pub enum MockFrameIntervals {
+    /// A list of frame intervals.
+    Discrete(Vec<Fract>),
+    /// A range of frame intervals, with the given step size.
+    Stepwise { min: Fract, max: Fract, step: Fract },
+    /// A range of frame intervals, with a step size of 1.
+    Continuous { min: Fract, max: Fract },
+    /// Reports a frame interval type that is not defined by V4L2.
+    ///
+    /// `v4l2loopback` does this for devices that no application is outputting data to.
+    Invalid,
+}
+
+/// A control of a [`MockDevice`].
+pub struct MockControl {
+    raw: raw::QueryCtrl,
+    value: i32,
+    menu: Vec<(u32, String)>,
+}
+
+impl MockControl {
+    /// Creates a control with a range of `0..=0` and a default value of 0.
+    pub fn new(cid: Cid, control_type: CtrlType, name: &str) -> Self {
+        Self {
+            raw: raw::QueryCtrl {
+                id: cid.0,
+                type_: control_type,
+                name: str_to_byte_array(name),
+                minimum: 0,
+                maximum: 0,
+                step: 1,
+                default_value: 0,
+                flags: ControlFlags::empty(),
+                reserved: [0; 2],
+            },
+            value: 0,
+            menu: Vec::new(),
+        }
+    }
+
+    /// Sets the range of valid values.
+    ///
+    /// For menu controls, this is the range of menu indices.
+    pub fn range(mut self, minimum: i32, maximum: i32, step: i32) -> Self {
+        self.raw.minimum = minimum;
+        self.raw.maximum = maximum;
+        self.raw.step = step;
+        self
+    }
+
+    /// Sets the default value, which is also the initial value of the control.
+    pub fn default_value(mut self, value: i32) -> Self {
+        self.raw.default_value = value;
+        self.value = value;
+        self
+    }
+
+    /// Sets the control's flags.
+    ///
+    /// Controls with [`ControlFlags::DISABLED`] are reported by the driver, but skipped by
+    /// [`Device::controls`].
+    pub fn flags(mut self, flags: ControlFlags) -> Self {
+        self.raw.flags = flags;
+        self
+    }
+
+    /// Adds an item to a menu control.
+    ///
+    /// Indices in the control's range without an item are reported as invalid by the driver.
+    pub fn menu_item(mut self, index: u32, name: &str) -> Self {
+        self.menu.push((index, name.to_string()));
+        self
+    }
+}
+
+impl fmt::Debug for MockControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockControl")
+            .field("id", &Cid(self.raw.id))
+            .field("type", &self.raw.type_)
+            .field("value", &self.value)
+            .field("menu", &self.menu)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A simulated V4L2 driver.
+///
+/// Created with [`MockDevice::new`], configured with the `with_*` methods, and turned into a
+/// [`Device`] with [`MockDevice::into_device`].
+pub struct MockDevice {
+    state: Mutex<State>,
+}
+
+struct State {
+    caps: raw::Capabilities,
+    formats: Vec<raw::FmtDesc>,
+    frame_sizes: Vec<(PixelFormat, MockFrameSizes)>,
+    frame_intervals: Vec<((PixelFormat, u32, u32), MockFrameIntervals)>,
+    inputs: Vec<raw::Input>,
+    outputs: Vec<raw::Output>,
+    controls: Vec<MockControl>,
+    next_ctrl: bool,
+    current_formats: Vec<raw::Format>,
+    failures: Vec<(MockIoctl, Errno)>,
+}
+
+// The pointers in `raw::Format`s (used by overlay formats) are never dereferenced by the mock.
+unsafe impl Send for State {}
+
+impl State {
+    /// Adjusts a single-planar format to one the device supports, like a driver would.
+    ///
+    /// An unsupported pixel format is replaced with the first format of `buf_type`, and the frame
+    /// size is changed to the closest supported one. If the device has no formats for `buf_type`,
+    /// the format is left unchanged.
+    ///
+    /// Fails with `EINVAL` if the frame is too large for its size to fit in 32 bits.
+    fn negotiate(&self, buf_type: BufType, pix: &mut raw::PixFormat) -> nix::Result<()> {
+        let mut formats = self.formats.iter().filter(|f| f.type_ == buf_type);
+        let Some(first) = formats.clone().next() else {
+            return Ok(());
+        };
+        if !formats.any(|f| f.pixel_format == pix.pixel_format) {
+            pix.pixel_format = first.pixel_format;
+        }
+        if let Some((_, sizes)) = self
+            .frame_sizes
+            .iter()
+            .find(|(pixel_format, _)| *pixel_format == pix.pixel_format)
+        {
+            (pix.width, pix.height) = sizes.nearest(pix.width, pix.height);
+        }
+        if pix.field == Field::ANY {
+            pix.field = Field::NONE;
+        }
+        (pix.bytesperline, pix.sizeimage) =
+            image_layout(pix.pixel_format, pix.width, pix.height).ok_or(Errno::EINVAL)?;
+        Ok(())
+    }
+}
+
+/// Returns the line stride and image size of a frame, like a driver would compute them.
+///
+/// Returns `None` if they don't fit in a `u32`.
+fn image_layout(pixel_format: PixelFormat, width: u32, height: u32) -> Option<(u32, u32)> {
+    let bytes_per_pixel = match pixel_format {
+        PixelFormat::GREY | PixelFormat::YU12 | PixelFormat::NV12 => 1,
+        PixelFormat::YUYV | PixelFormat::UYVY => 2,
+        PixelFormat::RGB3 | PixelFormat::BGR3 => 3,
+        PixelFormat::ABGR32
+        | PixelFormat::XBGR32
+        | PixelFormat::BGRA32
+        | PixelFormat::BGRX32
+        | PixelFormat::RGBA32
+        | PixelFormat::RGBX32
+        | PixelFormat::ARGB32
+        | PixelFormat::XRGB32
+        | PixelFormat::BGR32
+        | PixelFormat::RGB32 => 4,
+        // Compressed formats have no line stride, leave enough room for any reasonable frame.
+        _ => return Some((0, width.checked_mul(height)?.checked_mul(2)?)),
+    };
+
+    let bytes_per_line = width.checked_mul(bytes_per_pixel)?;
+    let size = bytes_per_line.checked_mul(height)?;
+    let size_image = match pixel_format {
+        PixelFormat::YU12 | PixelFormat::NV12 => size.checked_mul(3)? / 2,
+        _ => size,
+    };
+    Some((bytes_per_line, size_image))
+}
+
+impl MockDevice {
+    /// Creates a device with the given driver name, card name and device capabilities.
+    pub fn new(driver: &str, card: &str, capabilities: CapabilityFlags) -> Self {
+        let mut caps: raw::Capabilities = unsafe { mem::zeroed() };
+        caps.driver = str_to_byte_array(driver);
+        caps.card = str_to_byte_array(card);
+        caps.bus_info = str_to_byte_array("platform:mock");
+        caps.capabilities = capabilities | CapabilityFlags::DEVICE_CAPS;
+        caps.device_caps = capabilities;
+
+        Self {
+            state: Mutex::new(State {
+                caps,
+                formats: Vec::new(),
+                frame_sizes: Vec::new(),
+                frame_intervals: Vec::new(),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                controls: Vec::new(),
+                next_ctrl: true,
+                current_formats: Vec::new(),
+                failures: Vec::new(),
+            }),
+        }
+    }
+
+    fn state(&mut self) -> &mut State {
+        self.state.get_mut().unwrap()
+    }
+
+    /// Sets the bus info reported by the device.
+    ///
+    /// Defaults to `platform:mock`.
+    pub fn with_bus_info(mut self, bus_info: &str) -> Self {
+        self.state().caps.bus_info = str_to_byte_array(bus_info);
+        self
+    }
+
+    /// Adds a format to the formats enumerated for `buf_type`.
+    pub fn with_format(
+        mut self,
+        buf_type: BufType,
+        pixel_format: PixelFormat,
+        description: &str,
+        flags: FormatFlags,
+    ) -> Self {
+        let state = self.state();
+        let index = state.formats.iter().filter(|f| f.type_ == buf_type).count();
+        state.formats.push(raw::FmtDesc {
+            index: index as u32,
+            type_: buf_type,
+            flags,
+            description: str_to_byte_array(description),
+            pixel_format,
+            mbus_code: 0,
+            reserved: [0; 3],
+        });
+        self
+    }
+
+    /// Sets the frame sizes reported for `pixel_format`.
+    ///
+    /// Pixel formats without frame sizes are reported as invalid.
+    pub fn with_frame_sizes(mut self, pixel_format: PixelFormat, sizes: MockFrameSizes) -> Self {
+        self.state().frame_sizes.push((pixel_format, sizes));
+        self
+    }
+
+    /// Sets the frame intervals reported for `pixel_format` at a frame size.
+    ///
+    /// Combinations without frame intervals are reported as invalid.
+    pub fn with_frame_intervals(
+        mut self,
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+        intervals: MockFrameIntervals,
+    ) -> Self {
+        self.state()
+            .frame_intervals
+            .push(((pixel_format, width, height), intervals));
+        self
+    }
+
+    /// Adds a video input.
+    pub fn with_input(mut self, name: &str, input_type: InputType) -> Self {
+        let state = self.state();
+        let mut input: raw::Input = unsafe { mem::zeroed() };
+        input.index = state.inputs.len() as u32;
+        input.name = str_to_byte_array(name);
+        input.type_ = input_type;
+        state.inputs.push(input);
+        self
+    }
+
+    /// Adds a video output.
+    pub fn with_output(mut self, name: &str, output_type: OutputType) -> Self {
+        let state = self.state();
+        let mut output: raw::Output = unsafe { mem::zeroed() };
+        output.index = state.outputs.len() as u32;
+        output.name = str_to_byte_array(name);
+        output.type_ = output_type;
+        state.outputs.push(output);
+        self
+    }
+
+    /// Adds a control.
+    pub fn with_control(mut self, control: MockControl) -> Self {
+        let controls = &mut self.state().controls;
+        controls.push(control);
+        // `V4L2_CTRL_FLAG_NEXT_CTRL` enumerates controls in order of their IDs.
+        controls.sort_by_key(|control| control.raw.id);
+        self
+    }
+
+    /// Makes the device reject `V4L2_CTRL_FLAG_NEXT_CTRL`, like some older drivers do.
+    ///
+    /// Controls then have to be enumerated by probing every control ID.
+    pub fn without_next_ctrl(mut self) -> Self {
+        self.state().next_ctrl = false;
+        self
+    }
+
+    /// Makes every call of `ioctl` fail with `errno`.
+    pub fn fail(mut self, ioctl: MockIoctl, errno: Errno) -> Self {
+        self.state().failures.push((ioctl, errno));
+        self
+    }
+
+    /// Turns the mock into a [`Device`] that is backed by it.
+    pub fn into_device(self) -> io::Result<Device> {
+        Device::from_handle(DeviceHandle::mock(self)?)
+    }
+
+    /// Locks the state, after checking for injected failures of `ioctl`.
+    fn call(&self, ioctl: MockIoctl) -> nix::Result<std::sync::MutexGuard<'_, State>> {
+        let state = self.state.lock().unwrap();
+        match state.failures.iter().find(|(i, _)| *i == ioctl) {
+            Some(&(_, errno)) => Err(errno),
+            None => Ok(state),
+        }
+    }
+}
+
+impl fmt::Debug for MockDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockDevice")
+            .field("caps", &state.caps)
+            .field("frame_sizes", &state.frame_sizes)
+            .field("frame_intervals", &state.frame_intervals)
+            .field("controls", &state.controls)
+            .field("failures", &state.failures)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Backend for MockDevice {
+    fn querycap(&self, caps: &mut raw::Capabilities) -> nix::Result<()> {
+        let state = self.call(MockIoctl::QueryCap)?;
+        *caps = state.caps;
+        Ok(())
+    }
+
+    fn enum_fmt(&self, desc: &mut raw::FmtDesc) -> nix::Result<()> {
+        let state = self.call(MockIoctl::EnumFmt)?;
+        let format = state
+            .formats
+            .iter()
+            .find(|f| f.type_ == desc.type_ && f.index == desc.index)
+            .ok_or(Errno::EINVAL)?;
+        desc.flags = format.flags;
+        desc.description = format.description;
+        desc.pixel_format = format.pixel_format;
+        Ok(())
+    }
+
+    fn enum_framesizes(&self, desc: &mut raw::FrmSizeEnum) -> nix::Result<()> {
+        let state = self.call(MockIoctl::EnumFrameSizes)?;
+        let (_, sizes) = state
+            .frame_sizes
+            .iter()
+            .find(|(pixel_format, _)| *pixel_format == desc.pixel_format)
+            .ok_or(Errno::EINVAL)?;
+        let stepwise = |min: (u32, u32), max: (u32, u32), step: (u32, u32)| raw::FrmSizeStepwise {
+            min_width: min.0,
+            max_width: max.0,
+            step_width: step.0,
+            min_height: min.1,
+            max_height: max.1,
+            step_height: step.1,
+        };
+        match (sizes, desc.index) {
+            (MockFrameSizes::Discrete(sizes), index) => {
+                let &(width, height) = sizes.get(index as usize).ok_or(Errno::EINVAL)?;
+                desc.type_ = FrmSizeType::DISCRETE;
+                desc.union.discrete = raw::FrmSizeDiscrete { width, height };
+            }
+            (&MockFrameSizes::Stepwise { min, max, step }, 0) => {
+                desc.type_ = FrmSizeType::STEPWISE;
+                desc.union.stepwise = stepwise(min, max, step);
+            }
+            (&MockFrameSizes::Continuous { min, max }, 0) => {
+                desc.type_ = FrmSizeType::CONTINUOUS;
+                desc.union.stepwise = stepwise(min, max, (1, 1));
+            }
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+
+    fn enum_frameintervals(&self, desc: &mut raw::FrmIvalEnum) -> nix::Result<()> {
+        let state = self.call(MockIoctl::EnumFrameIntervals)?;
+        let key = (desc.pixel_format, desc.width, desc.height);
+        let (_, intervals) = state
+            .frame_intervals
+            .iter()
+            .find(|(k, _)| *k == key)
+            .ok_or(Errno::EINVAL)?;
+        match (intervals, desc.index) {
+            (MockFrameIntervals::Discrete(intervals), index) => {
+                let &interval = intervals.get(index as usize).ok_or(Errno::EINVAL)?;
+                desc.type_ = FrmIvalType::DISCRETE;
+                desc.union.discrete = interval;
+            }
+            (&MockFrameIntervals::Stepwise { min, max, step }, 0) => {
+                desc.type_ = FrmIvalType::STEPWISE;
+                desc.union.stepwise = raw::FrmIvalStepwise { min, max, step };
+            }
+            (&MockFrameIntervals::Continuous { min, max }, 0) => {
+                desc.type_ = FrmIvalType::CONTINUOUS;
+                desc.union.stepwise = raw::FrmIvalStepwise {
+                    min,
+                    max,
+                    step: Fract::new(1, 1),
+                };
+            }
+            (MockFrameIntervals::Invalid, 0) => {
+                // The type is left at 0, like `v4l2loopback` does.
+                desc.type_ = FrmIvalType(0);
+            }
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+
+    fn enuminput(&self, input: &mut raw::Input) -> nix::Result<()> {
+        let state = self.call(MockIoctl::EnumInput)?;
+        let found = state
+            .inputs
+            .get(input.index as usize)
+            .ok_or(Errno::EINVAL)?;
+        *input = *found;
+        Ok(())
+    }
+
+    fn enumoutput(&self, output: &mut raw::Output) -> nix::Result<()> {
+        let state = self.call(MockIoctl::EnumOutput)?;
+        let found = state
+            .outputs
+            .get(output.index as usize)
+            .ok_or(Errno::EINVAL)?;
+        *output = *found;
+        Ok(())
+    }
+
+    fn queryctrl(&self, ctrl: &mut raw::QueryCtrl) -> nix::Result<()> {
+        let state = self.call(MockIoctl::QueryCtrl)?;
+        let found = if ctrl.id & CONTROL_FLAGS_NEXT_CTRL != 0 {
+            if !state.next_ctrl {
+                return Err(Errno::EINVAL);
+            }
+            // Like the kernel, return the first control with a *higher* ID than the given one.
+            let id = ctrl.id & !CONTROL_FLAGS_NEXT_CTRL;
+            state.controls.iter().find(|control| control.raw.id > id)
+        } else {
+            state
+                .controls
+                .iter()
+                .find(|control| control.raw.id == ctrl.id)
+        };
+        *ctrl = found.ok_or(Errno::EINVAL)?.raw;
+        Ok(())
+    }
+
+    fn querymenu(&self, menu: &mut raw::QueryMenu) -> nix::Result<()> {
+        let state = self.call(MockIoctl::QueryMenu)?;
+        let control = state
+            .controls
+            .iter()
+            .find(|control| control.raw.id == menu.id)
+            .ok_or(Errno::EINVAL)?;
+        let (_, name) = control
+            .menu
+            .iter()
+            .find(|(index, _)| *index == menu.index)
+            .ok_or(Errno::EINVAL)?;
+        menu.name_or_value.name = str_to_byte_array(name);
+        Ok(())
+    }
+
+    fn g_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()> {
+        let state = self.call(MockIoctl::GCtrl)?;
+        let control = state
+            .controls
+            .iter()
+            .find(|control| control.raw.id == ctrl.id.0)
+            .ok_or(Errno::EINVAL)?;
+        if control.raw.flags.contains(ControlFlags::WRITE_ONLY) {
+            return Err(Errno::EACCES);
+        }
+        ctrl.value = control.value;
+        Ok(())
+    }
+
+    fn s_ctrl(&self, ctrl: &mut raw::controls::Control) -> nix::Result<()> {
+        let mut state = self.call(MockIoctl::SCtrl)?;
+        let control = state
+            .controls
+            .iter_mut()
+            .find(|control| control.raw.id == ctrl.id.0)
+            .ok_or(Errno::EINVAL)?;
+        if control.raw.flags.contains(ControlFlags::READ_ONLY) {
+            return Err(Errno::EACCES);
+        }
+        if ctrl.value < control.raw.minimum || ctrl.value > control.raw.maximum {
+            return Err(Errno::ERANGE);
+        }
+        control.value = ctrl.value;
+        Ok(())
+    }
+
+    fn g_fmt(&self, format: &mut raw::Format) -> nix::Result<()> {
+        let state = self.call(MockIoctl::GFmt)?;
+        *format = *state
+            .current_formats
+            .iter()
+            .find(|f| f.type_ == format.type_)
+            .ok_or(Errno::EINVAL)?;
+        Ok(())
+    }
+
+    fn s_fmt(&self, format: &mut raw::Format) -> nix::Result<()> {
+        // Single-planar video formats are adjusted to the enumerated formats and frame sizes, other
+        // formats are accepted unchanged. Drivers that reject formats can be simulated by making
+        // this ioctl fail.
+        let mut state = self.call(MockIoctl::SFmt)?;
+        if matches!(format.type_, BufType::VIDEO_CAPTURE | BufType::VIDEO_OUTPUT) {
+            state.negotiate(format.type_, unsafe { &mut format.fmt.pix })?;
+        }
+        state.current_formats.retain(|f| f.type_ != format.type_);
+        state.current_formats.push(*format);
+        Ok(())
+    }
+}
@@ -90,6 +90,31 @@ impl PixelFormat {
     /// pixel's Y value, and `YYYYYYYY` is the right pixel's Y value.
     pub const YUYV: Self = f(b"YUYV");
 
+    /// **`UYVY`**: `uuuuuuuu yyyyyyyy vvvvvvvv YYYYYYYY`
+    ///
+    /// Like [`Self::YUYV`], but with the order of luma and chroma samples swapped.
+    pub const UYVY: Self = f(b"UYVY");
+
+    /// **`YU12`**: Planar YUV/YCbCr data with 4:2:0 chroma subsampling.
+    ///
+    /// The Y plane (`width * height` bytes) is followed by the U plane and the V plane, each
+    /// containing one sample per 2x2 pixel block. The chroma planes use half the
+    /// `bytes_per_line` of the Y plane.
+    ///
+    /// Also known as **`I420`**.
+    pub const YU12: Self = f(b"YU12");
+
+    /// **`NV12`**: Semi-planar YUV/YCbCr data with 4:2:0 chroma subsampling.
+    ///
+    /// The Y plane is followed by a single plane of interleaved `uuuuuuuu vvvvvvvv` samples, one
+    /// pair per 2x2 pixel block. Both planes use the same `bytes_per_line`.
+    pub const NV12: Self = f(b"NV12");
+
+    /// **`GREY`**: `yyyyyyyy`
+    ///
+    /// 8-bit luminance-only (greyscale) image data.
+    pub const GREY: Self = f(b"GREY");
+
     /// **`MJPG`**: Motion JPEG, a sequence of JPEG images with omitted huffman tables.
     ///
     /// The transmitted JPEG images lack the "DHT" frame (Define Huffman Table), and instead use a
@@ -102,6 +127,11 @@ impl PixelFormat {
     /// Images can be decoded with any off-the-shelf JPEG decoder, no preprocessing is needed.
     pub const JPEG: Self = f(b"JPEG");
 
+    /// **`FWHT`**: Fast Walsh Hadamard Transform bitstream.
+    ///
+    /// A simple codec used by the `vicodec` test driver, not intended for real-world use.
+    pub const FWHT: Self = f(b"FWHT");
+
     /// **`UVCH`**: UVC payload header metadata.
     ///
     /// Data is a stream of [`UvcMetadata`][crate::uvc::UvcMetadata] structures.
@@ -9,7 +9,7 @@ pub mod controls;
 use std::ffi::c_void;
 use std::os::raw::c_ulong;
 
-use nix::libc::timeval;
+use nix::libc::{timespec, timeval};
 use nix::{ioctl_read, ioctl_readwrite, ioctl_write_ptr};
 
 use crate::buf_type::BufType;
@@ -17,8 +17,11 @@ use crate::{shared::*, PixelFormat};
 
 pub const VIDEO_MAX_PLANES: usize = 8;
 
+/// Value of [`PixFormat::priv_`] indicating that the fields following it are valid.
+pub const PIX_FMT_PRIV_MAGIC: u32 = 0xfeedcafe;
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct Capabilities {
     pub driver: [u8; 16],
     pub card: [u8; 32],
@@ -56,12 +59,14 @@ pub struct FmtDesc {
     pub reserved: [u32; 3],
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Format {
     pub type_: BufType,
     pub fmt: FormatUnion,
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub union FormatUnion {
     pub pix: PixFormat,
@@ -152,6 +157,7 @@ pub struct PixFormatMplane {
     pub reserved: [u8; 7],
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Output {
     pub index: u32,
@@ -173,6 +179,7 @@ pub struct MetaFormat {
     pub buffersize: u32,
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Input {
     pub index: u32,
@@ -186,6 +193,7 @@ pub struct Input {
     pub reserved: [u32; 3],
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct QueryCtrl {
     pub id: u32,
@@ -220,9 +228,32 @@ pub struct RequestBuffers {
     pub type_: BufType,
     pub memory: Memory,
     pub capabilities: BufCap,
-    pub reserved: [u32; 1],
+    pub flags: MemoryFlags,
+    pub reserved: [u8; 3],
 }
 
+#[repr(C)]
+pub struct CreateBuffers {
+    pub index: u32,
+    pub count: u32,
+    pub memory: Memory,
+    pub format: Format,
+    pub capabilities: BufCap,
+    /// [`MemoryFlags`], widened to 32 bits.
+    pub flags: u32,
+    pub max_num_buffers: u32,
+    pub reserved: [u32; 5],
+}
+
+#[repr(C)]
+pub struct RemoveBuffers {
+    pub index: u32,
+    pub count: u32,
+    pub type_: BufType,
+    pub reserved: [u32; 13],
+}
+
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Timecode {
     pub type_: TimecodeType,
@@ -240,7 +271,7 @@ pub struct Buffer {
     pub type_: BufType,
     pub bytesused: u32,
     pub flags: BufFlag,
-    pub field: u32,
+    pub field: Field,
     pub timestamp: timeval,
     pub timecode: Timecode,
     pub sequence: u32,
@@ -281,6 +312,16 @@ pub union PlaneMemoryUnion {
     pub fd: i32,
 }
 
+#[repr(C)]
+pub struct ExportBuffer {
+    pub type_: BufType,
+    pub index: u32,
+    pub plane: u32,
+    pub flags: u32,
+    pub fd: i32,
+    pub reserved: [u32; 11],
+}
+
 #[repr(C)]
 pub struct FrmSizeEnum {
     pub index: u32,
@@ -374,6 +415,118 @@ pub struct OutputParm {
     pub reserved: [u32; 4],
 }
 
+#[repr(C)]
+pub struct EventSubscription {
+    pub type_: EventType,
+    pub id: u32,
+    pub flags: EventSubFlags,
+    pub reserved: [u32; 5],
+}
+
+#[repr(C)]
+pub struct Event {
+    pub type_: EventType,
+    pub u: EventUnion,
+    pub pending: u32,
+    pub sequence: u32,
+    pub timestamp: timespec,
+    pub id: u32,
+    pub reserved: [u32; 8],
+}
+
+#[repr(C)]
+pub union EventUnion {
+    pub vsync: EventVsync,
+    pub ctrl: EventCtrl,
+    pub frame_sync: EventFrameSync,
+    pub src_change: EventSrcChange,
+    pub motion_det: EventMotionDet,
+    pub data: [u8; 64],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct EventVsync {
+    pub field: u8,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct EventCtrl {
+    pub changes: CtrlChanges,
+    pub type_: CtrlType,
+    pub value: EventCtrlValue,
+    pub flags: ControlFlags,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub step: i32,
+    pub default_value: i32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub union EventCtrlValue {
+    pub value: i32,
+    pub value64: i64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct EventFrameSync {
+    pub frame_sequence: u32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct EventSrcChange {
+    pub changes: SrcChanges,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct EventMotionDet {
+    pub flags: MotionDetFlags,
+    pub frame_sequence: u32,
+    pub region_mask: u32,
+}
+
+#[repr(C)]
+pub struct DecoderCmd {
+    pub cmd: u32,
+    pub flags: u32,
+    pub u: DecoderCmdUnion,
+}
+
+#[repr(C)]
+pub union DecoderCmdUnion {
+    pub stop_pts: u64,
+    pub raw: [u32; 16],
+}
+
+pub const DEC_CMD_START: u32 = 0;
+pub const DEC_CMD_STOP: u32 = 1;
+
+#[repr(C)]
+pub struct EncoderCmd {
+    pub cmd: u32,
+    pub flags: u32,
+    pub raw: [u32; 8],
+}
+
+pub const ENC_CMD_START: u32 = 0;
+pub const ENC_CMD_STOP: u32 = 1;
+
+/// `dma_buf_sync` from `<linux/dma-buf.h>`.
+#[repr(C)]
+pub struct DmaBufSync {
+    pub flags: u64,
+}
+
+pub const DMA_BUF_SYNC_READ: u64 = 1;
+pub const DMA_BUF_SYNC_WRITE: u64 = 2;
+pub const DMA_BUF_SYNC_START: u64 = 0;
+pub const DMA_BUF_SYNC_END: u64 = 4;
+
 ioctl_read!(querycap, 'V', 0, Capabilities);
 ioctl_readwrite!(enum_fmt, 'V', 2, FmtDesc);
 ioctl_readwrite!(enuminput, 'V', 26, Input);
@@ -385,11 +538,47 @@ ioctl_readwrite!(querymenu, 'V', 37, QueryMenu);
 ioctl_readwrite!(reqbufs, 'V', 8, RequestBuffers);
 ioctl_readwrite!(querybuf, 'V', 9, Buffer);
 ioctl_readwrite!(qbuf, 'V', 15, Buffer);
+ioctl_readwrite!(expbuf, 'V', 16, ExportBuffer);
 ioctl_readwrite!(dqbuf, 'V', 17, Buffer);
 ioctl_write_ptr!(streamon, 'V', 18, BufType);
 ioctl_write_ptr!(streamoff, 'V', 19, BufType);
+ioctl_readwrite!(g_parm, 'V', 21, StreamParm);
 ioctl_readwrite!(s_parm, 'V', 22, StreamParm);
 ioctl_readwrite!(g_ctrl, 'V', 27, controls::Control);
 ioctl_readwrite!(s_ctrl, 'V', 28, controls::Control);
+ioctl_readwrite!(s_ext_ctrls, 'V', 72, controls::ExtControls);
 ioctl_readwrite!(enum_framesizes, 'V', 74, FrmSizeEnum);
 ioctl_readwrite!(enum_frameintervals, 'V', 75, FrmIvalEnum);
+ioctl_readwrite!(encoder_cmd, 'V', 77, EncoderCmd);
+ioctl_read!(dqevent, 'V', 89, Event);
+ioctl_write_ptr!(subscribe_event, 'V', 90, EventSubscription);
+ioctl_write_ptr!(unsubscribe_event, 'V', 91, EventSubscription);
+ioctl_readwrite!(create_bufs, 'V', 92, CreateBuffers);
+ioctl_readwrite!(prepare_buf, 'V', 93, Buffer);
+ioctl_readwrite!(decoder_cmd, 'V', 96, DecoderCmd);
+ioctl_readwrite!(remove_bufs, 'V', 104, RemoveBuffers);
+
+ioctl_write_ptr!(dma_buf_sync, 'b', 0, DmaBufSync);
+
+#[cfg(all(test, target_pointer_width = "64"))]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn struct_sizes() {
+        // The sizes are encoded in the ioctl request codes, so they have to match `videodev2.h`.
+        assert_eq!(size_of::<Format>(), 208);
+        assert_eq!(size_of::<Buffer>(), 88);
+        assert_eq!(size_of::<CreateBuffers>(), 256);
+        assert_eq!(size_of::<RemoveBuffers>(), 64);
+        assert_eq!(size_of::<EventSubscription>(), 32);
+        assert_eq!(size_of::<Event>(), 136);
+        assert_eq!(size_of::<EventCtrl>(), 40);
+        assert_eq!(size_of::<DecoderCmd>(), 72);
+        assert_eq!(size_of::<EncoderCmd>(), 40);
+        assert_eq!(size_of::<controls::ExtControl>(), 20);
+        assert_eq!(size_of::<controls::ExtControls>(), 32);
+    }
+}
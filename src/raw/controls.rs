@@ -1,3 +1,5 @@
+use std::ffi::c_void;
+
 ffi_enum! {
     pub enum CtrlClass: u32 {
         USER            = 0x00980000,
@@ -22,7 +24,7 @@ ffi_enum! {
     /// This type has associated constants to refer to standard controls with predefined meanings,
     /// but drivers can add their own driver-specific controls as well.
     pub enum Cid: u32 {
-        BRIGHTNESS                  = Self::BASE.0 + 0, // comes first so it shows up in debug output
+        BRIGHTNESS                  = Self::BASE.0, // comes first so it shows up in debug output
         BASE                        = CtrlClass::USER.0 | 0x900,
 
         /// User-class control base ID.
@@ -124,10 +126,24 @@ ffi_enum! {
 
         CAMERA_ORIENTATION          = Self::CAMERA_CLASS_BASE.0 + 34,
         CAMERA_SENSOR_ROTATION      = Self::CAMERA_CLASS_BASE.0 + 35,
+
+        /// Stateless-codec-class control base ID.
+        CODEC_STATELESS_BASE        = CtrlClass::CODEC_STATELESS.0 | 0x900,
+        STATELESS_H264_DECODE_MODE  = Self::CODEC_STATELESS_BASE.0,
+        STATELESS_H264_START_CODE   = Self::CODEC_STATELESS_BASE.0 + 1,
+        STATELESS_H264_SPS          = Self::CODEC_STATELESS_BASE.0 + 2,
+        STATELESS_H264_PPS          = Self::CODEC_STATELESS_BASE.0 + 3,
+        STATELESS_H264_SCALING_MATRIX = Self::CODEC_STATELESS_BASE.0 + 4,
+        STATELESS_H264_PRED_WEIGHTS = Self::CODEC_STATELESS_BASE.0 + 5,
+        STATELESS_H264_SLICE_PARAMS = Self::CODEC_STATELESS_BASE.0 + 6,
+        STATELESS_H264_DECODE_PARAMS = Self::CODEC_STATELESS_BASE.0 + 7,
+        STATELESS_FWHT_PARAMS       = Self::CODEC_STATELESS_BASE.0 + 100,
+        STATELESS_VP8_FRAME         = Self::CODEC_STATELESS_BASE.0 + 200,
     }
 }
 
 ffi_enum! {
+    #[allow(dead_code)] // currently unused
     pub enum PowerLineFrequency: u32 {
         DISABLED  = 0,
         FREQ_50HZ = 1,
@@ -137,6 +153,7 @@ ffi_enum! {
 }
 
 ffi_enum! {
+    #[allow(dead_code)] // currently unused
     pub enum ColorFx: u32 {
         NONE         = 0,
         BW           = 1,
@@ -162,3 +179,31 @@ pub struct Control {
     pub id: Cid,
     pub value: i32,
 }
+
+#[repr(C, packed)]
+pub struct ExtControl {
+    pub id: Cid,
+    pub size: u32,
+    pub reserved2: [u32; 1],
+    pub value: ExtControlUnion,
+}
+
+#[repr(C)]
+pub union ExtControlUnion {
+    pub value: i32,
+    pub value64: i64,
+    pub ptr: *mut c_void,
+}
+
+#[repr(C)]
+pub struct ExtControls {
+    /// Also called `ctrl_class` in older code.
+    pub which: u32,
+    pub count: u32,
+    pub error_idx: u32,
+    pub request_fd: i32,
+    pub reserved: [u32; 1],
+    pub controls: *mut ExtControl,
+}
+
+pub const CTRL_WHICH_REQUEST_VAL: u32 = 0x0f010000;
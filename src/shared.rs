@@ -1,6 +1,7 @@
 //! FFI-compatible types that may also be exposed to Rust code.
 
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 // This macro enforces that all `bitflags!` types in here are marked
 // `#[repr(transparent)]` and thus FFI-safe.
@@ -14,6 +15,7 @@ macro_rules! bitflags {
 }
 
 ffi_enum! {
+    #[allow(dead_code)] // currently unused
     pub enum TunerType: u32 {
         RADIO      = 1,
         ANALOG_TV  = 2,
@@ -87,13 +89,17 @@ ffi_enum! {
 }
 
 ffi_enum! {
+    /// Field order of (possibly interlaced) video frames.
     pub enum Field: u32 {
         /// Lets the driver choose.
         ANY           = 0,
         /// Don't use fields.
         NONE          = 1,
+        /// The buffer contains only the top field.
         TOP           = 2,
+        /// The buffer contains only the bottom field.
         BOTTOM        = 3,
+        /// Both fields are interlaced in the buffer.
         INTERLACED    = 4,
         SEQ_TB        = 5,
         SEQ_BT        = 6,
@@ -176,6 +182,29 @@ ffi_enum! {
     }
 }
 
+ffi_enum! {
+    /// Types of events a device can signal.
+    pub enum EventType: u32 {
+        /// Only valid for unsubscribing, where it unsubscribes from all events.
+        ALL           = 0,
+        /// The vertical sync pulse of an analog video signal. The event ID is unused.
+        VSYNC         = 1,
+        /// The end of the stream was reached. The event ID is unused.
+        EOS           = 2,
+        /// A control has changed. The event ID is the [`Cid`][crate::controls::Cid] of the control.
+        CTRL          = 3,
+        /// The driver has started receiving a frame. The event ID is unused.
+        FRAME_SYNC    = 4,
+        /// The properties of the video source have changed, for example the resolution of an HDMI
+        /// input. The event ID is the input or pad index.
+        SOURCE_CHANGE = 5,
+        /// Motion detection was triggered. The event ID is unused.
+        MOTION_DET    = 6,
+        /// Driver-specific events use types starting at this value.
+        PRIVATE_START = 0x08000000,
+    }
+}
+
 bitflags! {
     /// Flags describing the state of a device control.
     pub struct ControlFlags: u32 {
@@ -215,9 +244,13 @@ pub(crate) const CONTROL_FLAGS_NEXT_CTRL: u32 = 0x80000000;
 
 bitflags! {
     pub struct FormatFlags: u32 {
+        /// The format describes a compressed bitstream rather than raw image data.
         const COMPRESSED             = 0x0001;
         const EMULATED               = 0x0002;
+        /// A decoder can parse the bitstream on its own, so chunks passed to it do not have to
+        /// contain exactly one frame.
         const CONTINUOUS_BYTESTREAM  = 0x0004;
+        /// A decoder supports resolution changes in the middle of the stream.
         const DYN_RESOLUTION         = 0x0008;
         const ENC_CAP_FRAME_INTERVAL = 0x0010;
         const CSC_COLORSPACE         = 0x0020;
@@ -303,9 +336,17 @@ bitflags! {
         const HW_FREQ_SEEK         = 0x00000400;
         const RDS_OUTPUT           = 0x00000800;
 
+        /// Device supports multi-planar video capture via
+        /// [`Device::video_capture_mplane`][crate::Device::video_capture_mplane].
         const VIDEO_CAPTURE_MPLANE = 0x00001000;
+        /// Device supports multi-planar video output via
+        /// [`Device::video_output_mplane`][crate::Device::video_output_mplane].
         const VIDEO_OUTPUT_MPLANE  = 0x00002000;
+        /// Device supports multi-planar memory-to-memory processing via
+        /// [`Device::video_m2m_mplane`][crate::Device::video_m2m_mplane].
         const VIDEO_M2M_MPLANE     = 0x00004000;
+        /// Device supports memory-to-memory processing via
+        /// [`Device::video_m2m`][crate::Device::video_m2m].
         const VIDEO_M2M            = 0x00008000;
 
         const TUNER                = 0x00010000;
@@ -401,6 +442,7 @@ bitflags! {
 }
 
 bitflags! {
+    /// Capabilities of a buffer queue.
     pub struct BufCap: u32 {
         const SUPPORTS_MMAP                 = 1 << 0;
         const SUPPORTS_USERPTR              = 1 << 1;
@@ -409,6 +451,20 @@ bitflags! {
         const SUPPORTS_ORPHANED_BUFS        = 1 << 4;
         const SUPPORTS_M2M_HOLD_CAPTURE_BUF = 1 << 5;
         const SUPPORTS_MMAP_CACHE_HINTS     = 1 << 6;
+        const SUPPORTS_MAX_NUM_BUFFERS      = 1 << 7;
+        /// The queue supports removing buffers with `VIDIOC_REMOVE_BUFS` (Linux 6.10+).
+        const SUPPORTS_REMOVE_BUFS          = 1 << 8;
+    }
+}
+
+bitflags! {
+    /// Flags passed when allocating buffers.
+    pub struct MemoryFlags: u8 {
+        /// Requests non-coherent memory, which can improve performance of CPU accesses if the
+        /// driver supports it ([`BufCap::SUPPORTS_MMAP_CACHE_HINTS`]).
+        ///
+        /// The kernel still synchronizes the CPU caches when buffers are queued and dequeued.
+        const NON_COHERENT = 1 << 0;
     }
 }
 
@@ -461,8 +517,49 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Flags for an event subscription.
+    pub struct EventSubFlags: u32 {
+        /// Sends an event with the current state right after subscribing.
+        ///
+        /// This is only supported by [`EventType::CTRL`] and [`EventType::SOURCE_CHANGE`] events,
+        /// and makes it possible to initialize a UI from the same events that keep it updated.
+        const SEND_INITIAL   = 0x0001;
+        /// Also sends control events for changes made through this file descriptor.
+        const ALLOW_FEEDBACK = 0x0002;
+    }
+}
+
+bitflags! {
+    /// Properties of a control that were changed, as reported by a control event.
+    pub struct CtrlChanges: u32 {
+        /// The value of the control has changed.
+        const VALUE      = 0x0001;
+        /// The control's flags have changed.
+        const FLAGS      = 0x0002;
+        /// The minimum, maximum, step or default value has changed.
+        const RANGE      = 0x0004;
+        /// The dimensions of an array control have changed.
+        const DIMENSIONS = 0x0008;
+    }
+}
+
+bitflags! {
+    /// Properties of a video source that were changed, as reported by a source change event.
+    pub struct SrcChanges: u32 {
+        /// The resolution of the source has changed.
+        const RESOLUTION = 0x0001;
+    }
+}
+
+bitflags! {
+    pub struct MotionDetFlags: u32 {
+        const HAVE_FRAME_SEQ = 0x0001;
+    }
+}
+
 /// A fractional value (`numerator / denominator`).
-#[derive(Clone, Copy, Hash)]
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct Fract {
     numerator: u32,
@@ -517,10 +614,19 @@ impl PartialEq for Fract {
 
 impl Eq for Fract {}
 
+impl Hash for Fract {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal fractions must hash identically, so hash the reduced form. Drivers report `0/0`
+        // for unset intervals, which can't be reduced.
+        let divisor = gcd(self.numerator, self.denominator).max(1);
+        (self.numerator / divisor).hash(state);
+        (self.denominator / divisor).hash(state);
+    }
+}
+
 impl PartialOrd for Fract {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let [a, b] = same_denom(*self, *other);
-        a.numerator.partial_cmp(&b.numerator)
+        Some(self.cmp(other))
     }
 }
 
@@ -590,4 +696,23 @@ mod tests {
         assert_eq!(y.numerator, 9);
         assert_eq!(y.denominator, 15);
     }
+
+    #[test]
+    fn test_hash() {
+        use std::collections::hash_map::DefaultHasher;
+
+        fn hash(fract: Fract) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            fract.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        assert_eq!(hash(Fract::new(1, 2)), hash(Fract::new(2, 4)));
+        assert_ne!(hash(Fract::new(1, 2)), hash(Fract::new(1, 3)));
+        // drivers report this for unset intervals, must not panic
+        hash(Fract {
+            numerator: 0,
+            denominator: 0,
+        });
+    }
 }
@@ -0,0 +1,742 @@
+//! Simulated capture devices for testing without hardware.
+//!
+//! This module requires the `mock` cargo feature.
+//!
+//! [`SimDevice`] adds capture streaming to a [`MockDevice`]: the driver's formats, frame sizes and
+//! intervals, and controls are described by the mock and accessed through the regular [`Device`]
+//! API, and the [`SimStream`] obtained after negotiating a format mirrors [`ReadStream`],
+//! delivering frames as [`ReadBufferView`]s. Code that is generic over [`CaptureStream`] works
+//! with both real and simulated streams.
+//!
+//! Frame contents come from a [`FrameSource`], which can be a generator closure or a recorded
+//! [`Y4mReader`]. Time is simulated by a [`SimClock`]: instead of sleeping, dequeuing a frame
+//! advances the clock to the frame's timestamp, which makes tests fast and deterministic. Frame
+//! timing, dropped frames, corrupted buffers and failures are scripted with [`SimEvent`]s.
+//!
+//! The simulation never drops frames on its own, even if the clock is advanced past several frame
+//! intervals between two dequeues. Drops that real drivers would perform have to be scripted with
+//! [`SimEvent::Drop`].
+//!
+//! [`ReadStream`]: crate::stream::ReadStream
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nix::errno::Errno;
+
+use crate::format::{Format, FrameIntervals, PixFormat};
+use crate::mock::MockDevice;
+use crate::shared::BufFlag;
+use crate::stream::{CaptureStats, CaptureStream, ReadBufferView, StatsTracker};
+use crate::y4m::Y4mReader;
+use crate::{set_format_raw, Device, Fract};
+
+/// Number of buffers a [`SimStream`] cycles through, for the purpose of buffer indices.
+const BUFFER_COUNT: u64 = 4;
+
+/// A simulated `CLOCK_MONOTONIC`, shared by a [`SimDevice`] and the streams created from it.
+///
+/// The clock starts at zero, and only advances when a [`SimStream`] waits for a frame, or when
+/// [`SimClock::advance`] is called (for example, to simulate the time it takes the application to
+/// process a frame).
+#[derive(Clone, Default)]
+pub struct SimClock(Arc<AtomicU64>);
+
+impl SimClock {
+    /// Creates a clock starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current simulated time.
+    pub fn now(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Advances the clock by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.0
+            .fetch_add(duration_to_nanos(duration), Ordering::Relaxed);
+    }
+
+    /// Advances the clock to `time`, unless it is already later than that.
+    fn advance_to(&self, time: Duration) {
+        self.0.fetch_max(duration_to_nanos(time), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for SimClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SimClock").field(&self.now()).finish()
+    }
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+fn interval_to_duration(interval: Fract) -> Duration {
+    Duration::from_nanos(
+        u64::from(interval.numerator()) * 1_000_000_000 / u64::from(interval.denominator()),
+    )
+}
+
+/// Produces the contents of simulated frames.
+///
+/// This is implemented for closures with the same signature as [`FrameSource::fill`], and for
+/// [`Y4mReader`]s, which play back a recorded file.
+pub trait FrameSource: Send {
+    /// Writes frame number `sequence` to `buf`, using the negotiated `format`.
+    ///
+    /// `buf` is [`PixFormat::size_image`] bytes large. Returns the number of bytes written, or
+    /// `None` if there are no more frames. Errors are returned from the dequeue operation of the
+    /// [`SimStream`], which fails with `EINVAL` if the returned length exceeds `buf.len()`.
+    fn fill(
+        &mut self,
+        format: &PixFormat,
+        sequence: u32,
+        buf: &mut [u8],
+    ) -> io::Result<Option<usize>>;
+}
+
+impl<F> FrameSource for F
+where
+    F: FnMut(&PixFormat, u32, &mut [u8]) -> io::Result<Option<usize>> + Send,
+{
+    fn fill(
+        &mut self,
+        format: &PixFormat,
+        sequence: u32,
+        buf: &mut [u8],
+    ) -> io::Result<Option<usize>> {
+        self(format, sequence, buf)
+    }
+}
+
+impl<R: BufRead + Send> FrameSource for Y4mReader<R> {
+    fn fill(&mut self, format: &PixFormat, _: u32, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        self.read_frame(format, buf)
+    }
+}
+
+/// A scripted occurrence in a simulated capture session.
+///
+/// Events are processed in order as frames are dequeued. Once all events are processed, frames are
+/// delivered at the regular frame interval until the [`FrameSource`] runs out of frames.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum SimEvent {
+    /// Deliver this many frames normally.
+    Frames(u32),
+    /// The driver drops this many frames.
+    ///
+    /// Their sequence numbers and time slots are skipped.
+    Drop(u32),
+    /// Deliver a frame flagged as corrupted (see [`ReadBufferView::is_error`]).
+    ErrorFrame,
+    /// Delay the next frame by this duration, in addition to the frame interval.
+    Stall(Duration),
+    /// Fail the next dequeue operation with this error.
+    Fail(Errno),
+    /// The device is unplugged: every following dequeue operation fails with `ENODEV`.
+    Disconnect,
+}
+
+/// The frame interval used if the driver reports no frame intervals for the negotiated format.
+fn default_interval() -> Fract {
+    Fract::new(1, 30)
+}
+
+/// A simulated video capture device.
+///
+/// The driver is described by a [`MockDevice`]. Its formats, frame sizes and intervals, and
+/// controls are enumerated and accessed through the [`Device`] returned by [`SimDevice::device`],
+/// and [`SimDevice::video_capture`] negotiates the capture format with it like
+/// [`Device::video_capture`] does with a real driver.
+pub struct SimDevice {
+    device: Device,
+    clock: SimClock,
+    script: Vec<SimEvent>,
+    source: Box<dyn FrameSource>,
+}
+
+impl SimDevice {
+    /// Creates a device with the driver described by `driver`, capturing frames produced by
+    /// `source`.
+    pub fn new(driver: MockDevice, source: impl FrameSource + 'static) -> io::Result<Self> {
+        Ok(Self {
+            device: driver.into_device()?,
+            clock: SimClock::new(),
+            script: Vec::new(),
+            source: Box::new(source),
+        })
+    }
+
+    /// Appends events to the script of the capture session.
+    pub fn with_script(mut self, events: impl IntoIterator<Item = SimEvent>) -> Self {
+        self.script.extend(events);
+        self
+    }
+
+    /// Makes the device use `clock` instead of its own clock.
+    pub fn with_clock(mut self, clock: SimClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the clock used by this device.
+    #[inline]
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// Returns the [`Device`] backed by the simulated driver.
+    #[inline]
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Returns the [`Device`] backed by the simulated driver, for writing controls.
+    #[inline]
+    pub fn device_mut(&mut self) -> &mut Device {
+        &mut self.device
+    }
+
+    /// Negotiates a capture format, like [`Device::video_capture`].
+    ///
+    /// The format is adjusted by the [`MockDevice`]: if the pixel format is not supported, the
+    /// first supported format is used instead, and the frame size is rounded to the closest
+    /// supported size.
+    ///
+    /// The frame interval defaults to the first one the driver reports for the negotiated format,
+    /// or to 30 frames per second if it reports none.
+    pub fn video_capture(self, format: PixFormat) -> io::Result<SimCaptureDevice> {
+        let format = match set_format_raw(self.device.backend(), Format::VideoCapture(format))? {
+            Format::VideoCapture(fmt) => fmt,
+            _ => unreachable!(),
+        };
+
+        let intervals = self
+            .device
+            .frame_intervals(format.pixel_format(), format.width(), format.height())
+            .ok();
+        let interval = match &intervals {
+            Some(FrameIntervals::Discrete(list)) => list
+                .iter()
+                .map(|ival| *ival.fract())
+                .find(is_valid_interval)
+                .unwrap_or_else(default_interval),
+            intervals => nearest_interval(intervals.as_ref(), default_interval())?,
+        };
+        Ok(SimCaptureDevice {
+            format,
+            interval: Mutex::new(interval),
+            intervals,
+            clock: self.clock,
+            script: self.script,
+            source: self.source,
+        })
+    }
+}
+
+impl fmt::Debug for SimDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimDevice")
+            .field("device", &self.device)
+            .field("clock", &self.clock)
+            .field("script", &self.script)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns whether `interval` can be used as a frame interval.
+fn is_valid_interval(interval: &Fract) -> bool {
+    interval.denominator() != 0
+}
+
+/// Returns the supported interval closest to `requested`.
+///
+/// Invalid intervals reported by the driver are ignored. Returns `EINVAL` if `requested` is
+/// invalid.
+fn nearest_interval(intervals: Option<&FrameIntervals>, requested: Fract) -> io::Result<Fract> {
+    if !is_valid_interval(&requested) {
+        return Err(Errno::EINVAL.into());
+    }
+
+    let secs = |fract: Fract| f64::from(fract.numerator()) / f64::from(fract.denominator());
+    let distance = |fract: Fract| (secs(fract) - secs(requested)).abs();
+    Ok(match intervals {
+        Some(FrameIntervals::Discrete(list)) => list
+            .iter()
+            .map(|ival| *ival.fract())
+            .filter(is_valid_interval)
+            .min_by(|a, b| distance(*a).total_cmp(&distance(*b)))
+            .unwrap_or_else(default_interval),
+        Some(FrameIntervals::Stepwise(range) | FrameIntervals::Continuous(range))
+            if is_valid_interval(range.min()) && is_valid_interval(range.max()) =>
+        {
+            if secs(requested) < secs(*range.min()) {
+                *range.min()
+            } else if secs(requested) > secs(*range.max()) {
+                *range.max()
+            } else {
+                requested
+            }
+        }
+        _ => default_interval(),
+    })
+}
+
+/// A [`SimDevice`] configured for video capture.
+///
+/// Returned by [`SimDevice::video_capture`].
+pub struct SimCaptureDevice {
+    format: PixFormat,
+    intervals: Option<FrameIntervals>,
+    interval: Mutex<Fract>,
+    clock: SimClock,
+    script: Vec<SimEvent>,
+    source: Box<dyn FrameSource>,
+}
+
+impl SimCaptureDevice {
+    /// Returns the negotiated pixel format.
+    pub fn format(&self) -> &PixFormat {
+        &self.format
+    }
+
+    /// Requests a change to the frame interval.
+    ///
+    /// Returns the closest supported frame interval, which will be used by the stream.
+    pub fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        let interval = nearest_interval(self.intervals.as_ref(), interval)?;
+        *self.interval.lock().unwrap() = interval;
+        Ok(interval)
+    }
+
+    /// Returns the current frame interval.
+    pub fn frame_interval(&self) -> io::Result<Fract> {
+        Ok(*self.interval.lock().unwrap())
+    }
+
+    /// Starts the simulated capture session.
+    ///
+    /// The first frame is due one frame interval after the current time of the clock.
+    pub fn into_stream(self) -> io::Result<SimStream> {
+        let interval = self.interval.into_inner().unwrap();
+        let buffer = vec![0; self.format.size_image() as usize];
+        Ok(SimStream {
+            next_frame_at: self.clock.now() + interval_to_duration(interval),
+            format: self.format,
+            intervals: self.intervals,
+            interval: Mutex::new(interval),
+            clock: self.clock,
+            script: self.script.into(),
+            source: self.source,
+            buffer,
+            sequence: 0,
+            dequeued: 0,
+            state: StreamState::Running,
+            stats: StatsTracker::new(),
+        })
+    }
+}
+
+impl fmt::Debug for SimCaptureDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimCaptureDevice")
+            .field("format", &self.format)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamState {
+    Running,
+    /// The frame source has no more frames.
+    Exhausted,
+    Disconnected,
+}
+
+/// A simulated capture stream.
+///
+/// Returned by [`SimCaptureDevice::into_stream`]. Dequeue operations never block, but advance the
+/// [`SimClock`] to the time the next frame is due instead.
+///
+/// Once the [`FrameSource`] runs out of frames, dequeue operations fail with `EPIPE`, like they do
+/// after the last buffer of a drained codec stream.
+pub struct SimStream {
+    format: PixFormat,
+    intervals: Option<FrameIntervals>,
+    interval: Mutex<Fract>,
+    clock: SimClock,
+    script: VecDeque<SimEvent>,
+    source: Box<dyn FrameSource>,
+    buffer: Vec<u8>,
+    sequence: u32,
+    next_frame_at: Duration,
+    /// Number of frames dequeued so far, used to assign buffer indices.
+    dequeued: u64,
+    state: StreamState,
+    stats: StatsTracker,
+}
+
+impl SimStream {
+    /// Returns the negotiated pixel format.
+    #[inline]
+    pub fn format(&self) -> &PixFormat {
+        &self.format
+    }
+
+    /// Returns the clock used by this stream.
+    #[inline]
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// Advances the clock to the next frame, passes it to `cb`, then returns the buffer.
+    pub fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let flags = self
+            .next_frame(None)?
+            .expect("no timeout, so there's always a frame");
+        self.deliver(flags, cb)
+    }
+
+    /// Passes the next frame to `cb` if it is due within `timeout`.
+    ///
+    /// Returns `Ok(None)` after advancing the clock by `timeout` if the next frame is due later
+    /// than that.
+    pub fn dequeue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        match self.next_frame(Some(timeout))? {
+            Some(flags) => self.deliver(flags, cb).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Passes the next frame to `cb` if it is already due.
+    pub fn try_dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        self.dequeue_timeout(Duration::ZERO, cb)
+    }
+
+    /// Requests a change to the frame interval.
+    ///
+    /// Returns the closest supported frame interval. The new interval applies after the next
+    /// frame.
+    pub fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        let interval = nearest_interval(self.intervals.as_ref(), interval)?;
+        *self.interval.lock().unwrap() = interval;
+        Ok(interval)
+    }
+
+    /// Returns the current frame interval.
+    pub fn frame_interval(&self) -> io::Result<Fract> {
+        Ok(*self.interval.lock().unwrap())
+    }
+
+    /// Returns statistics about the frames dequeued from this stream, measured on the simulated
+    /// clock.
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+
+    /// Resets the statistics returned by [`SimStream::stats`].
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    /// Processes script events up to the next frame, and advances the clock to it.
+    ///
+    /// Returns the flags to set on the frame, or `None` if it isn't due within `timeout`.
+    fn next_frame(&mut self, timeout: Option<Duration>) -> io::Result<Option<BufFlag>> {
+        loop {
+            match self.state {
+                StreamState::Running => {}
+                StreamState::Exhausted => return Err(Errno::EPIPE.into()),
+                StreamState::Disconnected => return Err(Errno::ENODEV.into()),
+            }
+
+            let event = match self.script.front() {
+                None | Some(SimEvent::ErrorFrame) => break,
+                Some(SimEvent::Frames(count)) if *count > 0 => break,
+                Some(_) => self.script.pop_front().unwrap(),
+            };
+            match event {
+                SimEvent::Frames(_) | SimEvent::ErrorFrame => {}
+                SimEvent::Drop(count) => {
+                    self.sequence = self.sequence.wrapping_add(count);
+                    self.next_frame_at += self.interval_duration() * count;
+                }
+                SimEvent::Stall(duration) => self.next_frame_at += duration,
+                SimEvent::Fail(errno) => return Err(errno.into()),
+                SimEvent::Disconnect => self.state = StreamState::Disconnected,
+            }
+        }
+
+        if let Some(timeout) = timeout {
+            if self.next_frame_at > self.clock.now() + timeout {
+                self.clock.advance(timeout);
+                return Ok(None);
+            }
+        }
+        self.clock.advance_to(self.next_frame_at);
+
+        let flags = match self.script.front_mut() {
+            Some(SimEvent::ErrorFrame) => {
+                self.script.pop_front();
+                BufFlag::ERROR
+            }
+            Some(SimEvent::Frames(count)) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.script.pop_front();
+                }
+                BufFlag::empty()
+            }
+            _ => BufFlag::empty(),
+        };
+        Ok(Some(flags))
+    }
+
+    fn deliver<T>(
+        &mut self,
+        flags: BufFlag,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let sequence = self.sequence;
+        let timestamp = self.next_frame_at;
+        self.sequence = sequence.wrapping_add(1);
+        self.next_frame_at += self.interval_duration();
+
+        let Some(len) = self.source.fill(&self.format, sequence, &mut self.buffer)? else {
+            self.state = StreamState::Exhausted;
+            return Err(Errno::EPIPE.into());
+        };
+        if len > self.buffer.len() {
+            log::error!(
+                "frame source returned {len} bytes, but the buffer only holds {}",
+                self.buffer.len()
+            );
+            return Err(Errno::EINVAL.into());
+        }
+
+        let flags = flags | BufFlag::MAPPED | BufFlag::TIMESTAMP_MONOTONIC;
+        self.stats
+            .record(sequence, flags, timestamp, self.clock.now());
+        let index = (self.dequeued % BUFFER_COUNT) as u32;
+        self.dequeued += 1;
+
+        cb(ReadBufferView::from_parts(
+            index,
+            flags,
+            self.format.field(),
+            timestamp,
+            sequence,
+            &self.buffer[..len],
+        ))
+    }
+
+    fn interval_duration(&self) -> Duration {
+        interval_to_duration(*self.interval.lock().unwrap())
+    }
+}
+
+impl CaptureStream for SimStream {
+    fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        SimStream::dequeue(self, cb)
+    }
+
+    fn dequeue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        SimStream::dequeue_timeout(self, timeout, cb)
+    }
+
+    fn frame_interval(&self) -> io::Result<Fract> {
+        SimStream::frame_interval(self)
+    }
+
+    fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        SimStream::set_frame_interval(self, interval)
+    }
+
+    fn stats(&self) -> CaptureStats {
+        SimStream::stats(self)
+    }
+}
+
+impl fmt::Debug for SimStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimStream")
+            .field("format", &self.format)
+            .field("clock", &self.clock)
+            .field("sequence", &self.sequence)
+            .field("next_frame_at", &self.next_frame_at)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controls::{Cid, CtrlType};
+    use crate::format::{FormatFlags, PixelFormat};
+    use crate::mock::{MockControl, MockFrameIntervals, MockFrameSizes};
+    use crate::{BufType, CapabilityFlags};
+
+    fn counter(_: &PixFormat, sequence: u32, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        buf.fill(sequence as u8);
+        Ok(Some(buf.len()))
+    }
+
+    fn driver() -> MockDevice {
+        let yuyv_intervals =
+            || MockFrameIntervals::Discrete(vec![Fract::new(1, 30), Fract::new(1, 10)]);
+        MockDevice::new("sim", "Simulated Camera", CapabilityFlags::VIDEO_CAPTURE)
+            .with_format(
+                BufType::VIDEO_CAPTURE,
+                PixelFormat::YUYV,
+                "YUYV 4:2:2",
+                FormatFlags::empty(),
+            )
+            .with_format(
+                BufType::VIDEO_CAPTURE,
+                PixelFormat::GREY,
+                "GREY",
+                FormatFlags::empty(),
+            )
+            .with_frame_sizes(
+                PixelFormat::YUYV,
+                MockFrameSizes::Discrete(vec![(640, 480), (320, 240)]),
+            )
+            .with_frame_sizes(PixelFormat::GREY, MockFrameSizes::Discrete(vec![(64, 64)]))
+            .with_frame_intervals(PixelFormat::YUYV, 640, 480, yuyv_intervals())
+            .with_frame_intervals(PixelFormat::YUYV, 320, 240, yuyv_intervals())
+            .with_control(
+                MockControl::new(Cid::BRIGHTNESS, CtrlType::INTEGER, "Brightness")
+                    .range(0, 255, 1)
+                    .default_value(128),
+            )
+    }
+
+    fn device() -> SimDevice {
+        SimDevice::new(driver(), counter).unwrap()
+    }
+
+    #[test]
+    fn negotiation() -> io::Result<()> {
+        let mut sim = device();
+
+        assert_eq!(sim.device().read_control_raw(Cid::BRIGHTNESS)?, 128);
+        sim.device_mut().write_control_raw(Cid::BRIGHTNESS, 200)?;
+        assert_eq!(sim.device().read_control_raw(Cid::BRIGHTNESS)?, 200);
+
+        let capture = sim.video_capture(PixFormat::new(300, 200, PixelFormat::RGB3))?;
+        let format = capture.format();
+        assert_eq!(format.pixel_format(), PixelFormat::YUYV);
+        assert_eq!((format.width(), format.height()), (320, 240));
+        assert_eq!(format.bytes_per_line(), 640);
+        assert_eq!(format.size_image(), 640 * 240);
+        assert_eq!(capture.frame_interval()?, Fract::new(1, 30));
+        assert_eq!(
+            capture.set_frame_interval(Fract::new(1, 12))?,
+            Fract::new(1, 10)
+        );
+
+        // No frame intervals are reported for GREY.
+        let capture = device().video_capture(PixFormat::new(64, 64, PixelFormat::GREY))?;
+        assert_eq!(capture.frame_interval()?, Fract::new(1, 30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_stream() -> io::Result<()> {
+        let ms = Duration::from_millis;
+        let device = device().with_script([
+            SimEvent::Frames(2),
+            SimEvent::Drop(1),
+            SimEvent::ErrorFrame,
+            SimEvent::Stall(ms(50)),
+            SimEvent::Frames(1),
+            SimEvent::Fail(Errno::EIO),
+            SimEvent::Frames(1),
+            SimEvent::Disconnect,
+        ]);
+        let clock = device.clock().clone();
+        let capture = device.video_capture(PixFormat::new(640, 480, PixelFormat::YUYV))?;
+        capture.set_frame_interval(Fract::new(1, 10))?;
+        let mut stream = capture.into_stream()?;
+
+        let mut frames = Vec::new();
+        for _ in 0..4 {
+            stream.dequeue(|buf| {
+                frames.push((buf.sequence(), buf.timestamp(), buf.is_error(), buf[0]));
+                Ok(())
+            })?;
+        }
+        assert_eq!(
+            frames,
+            [
+                (0, ms(100), false, 0),
+                (1, ms(200), false, 1),
+                (3, ms(400), true, 3),
+                (4, ms(550), false, 4),
+            ]
+        );
+        assert_eq!(clock.now(), ms(550));
+
+        let err = stream.dequeue(|_| Ok(())).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::EIO as i32));
+
+        // The next frame is due at 650 ms.
+        assert!(stream.dequeue_timeout(ms(40), |_| Ok(()))?.is_none());
+        assert_eq!(clock.now(), ms(590));
+        assert!(stream.dequeue_timeout(ms(80), |_| Ok(()))?.is_some());
+        assert_eq!(clock.now(), ms(650));
+
+        let err = stream.dequeue(|_| Ok(())).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::ENODEV as i32));
+
+        let stats = stream.stats();
+        assert_eq!(stats.frames(), 5);
+        assert_eq!(stats.dropped_frames(), 1);
+        assert_eq!(stats.error_frames(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_frame() -> io::Result<()> {
+        let source = |_: &PixFormat, _: u32, buf: &mut [u8]| Ok(Some(buf.len() + 1));
+        let capture = SimDevice::new(driver(), source)?.video_capture(PixFormat::new(
+            640,
+            480,
+            PixelFormat::YUYV,
+        ))?;
+        let mut stream = capture.into_stream()?;
+        let err = stream.dequeue(|_| Ok(())).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::EINVAL as i32));
+        Ok(())
+    }
+}
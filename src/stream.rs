@@ -1,440 +1,2722 @@
 //! Streaming I/O.
 
+use std::collections::VecDeque;
 use std::ffi::c_void;
 use std::fs::File;
+use std::marker::PhantomData;
 use std::mem;
 use std::num::NonZeroUsize;
-use std::ops::{Deref, DerefMut};
-use std::os::raw::c_int;
-use std::os::unix::prelude::{AsRawFd, RawFd};
-use std::{io, slice};
-
+use std::ops::{Deref, DerefMut, Range};
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::prelude::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, io, ptr, slice};
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags};
 use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+use nix::unistd::{lseek, Whence};
 
 use crate::buf_type::BufType;
+use crate::event::Events;
+use crate::format::{Format, PixFormat};
+use crate::media::Request;
 use crate::raw;
-use crate::shared::{BufFlag, Memory};
 
+mod nonblocking;
+mod stats;
+#[cfg(feature = "tokio")]
+mod tokio;
+mod worker;
+
+#[cfg(feature = "tokio")]
+pub use self::tokio::{TokioReadStream, TokioWriteStream};
+use crate::raw::controls::Cid;
+use crate::shared::{
+    BufCap, BufFlag, Field, Fract, Memory, MemoryFlags, TimecodeFlags, TimecodeType,
+};
+pub use nonblocking::{AsyncReadStream, AsyncWriteStream};
+pub use stats::CaptureStats;
+pub(crate) use stats::StatsTracker;
+pub use worker::{CaptureWorker, Frame, OverflowPolicy};
+
+bitflags::bitflags! {
+    /// What a stream is ready for, as returned by [`ReadStream::wait`] and [`WriteStream::wait`].
+    pub struct Readiness: u8 {
+        /// A buffer can be dequeued (for [`ReadStream`]s) or enqueued (for [`WriteStream`]s)
+        /// without blocking, or the stream is in an error state that the next operation will
+        /// report.
+        const BUFFER = 1 << 0;
+        /// An event is pending, and can be dequeued with [`Events::try_dequeue`].
+        const EVENT  = 1 << 1;
+    }
+}
+
+#[derive(Clone, Copy)]
 enum AllocType {
     /// The buffer was `mmap`ped into our address space, use `munmap` to free it.
     Mmap,
+    /// The buffer is owned by the application and borrowed for the lifetime of the stream.
+    UserPtr,
+    /// The buffer is a DMA-BUF borrowed from the application, which we `mmap` for CPU access if
+    /// requested.
+    DmaBuf,
 }
 
-struct Buffer {
-    /// Pointer in our address space where this buffer is mapped or allocated.
+/// A memory plane of a [`Buffer`].
+///
+/// Single-planar buffers consist of exactly one plane.
+struct Plane {
+    /// Pointer in our address space where this plane is mapped or allocated.
+    ///
+    /// Null for DMA-BUFs that are not mapped.
     ptr: *mut c_void,
-    /// Size of the buffer in bytes.
+    /// Size of the plane in bytes.
     length: u32,
-    queued: bool,
+    /// The DMA-BUF file descriptor backing this plane (only for `DMABUF` memory).
+    dmabuf_fd: RawFd,
+}
+
+impl Plane {
+    /// Returns the plane's memory, or an empty slice if the plane is not mapped.
+    ///
+    /// # Safety
+    ///
+    /// The plane must not be written to by the device or the application while the slice exists.
+    unsafe fn as_slice<'b>(&self) -> &'b [u8] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        slice::from_raw_parts(self.ptr as *const u8, self.length as usize)
+    }
+
+    /// Returns the plane's memory, or an empty slice if the plane is not mapped.
+    ///
+    /// # Safety
+    ///
+    /// The plane must not be accessed by the device or anything else while the slice exists.
+    unsafe fn as_mut_slice<'b>(&self) -> &'b mut [u8] {
+        if self.ptr.is_null() {
+            return &mut [];
+        }
+        slice::from_raw_parts_mut(self.ptr as *mut u8, self.length as usize)
+    }
+}
+
+struct Buffer {
+    planes: Vec<Plane>,
+    /// Whether the buffer is currently owned by the driver.
+    ///
+    /// For capture streams using requests, this also covers dequeued buffers that the application
+    /// has not released yet.
+    ///
+    /// This is atomic so that held buffers can be enqueued again through a shared reference to the
+    /// stream.
+    queued: AtomicBool,
+    /// The format the buffer was allocated for.
+    format: raw::Format,
+    /// Whether a capture stream enqueues the buffer again after it was dequeued.
+    ///
+    /// This is cleared for buffers added with `create_buffers`, which are only enqueued on request.
+    requeue: bool,
+}
+
+/// Describes where the memory backing the buffers of a stream comes from.
+enum BufferSource<'a> {
+    /// Let the driver allocate the given number of buffers and `mmap` them.
+    Mmap(u32),
+    /// Use application-provided memory.
+    UserPtr(Vec<&'a mut [u8]>),
+    /// Import application-provided DMA-BUF file descriptors, and whether to `mmap` them.
+    DmaBuf(Vec<BorrowedFd<'a>>, bool),
+}
+
+/// Configures the buffers of a stream.
+///
+/// Passed to the `into_stream_with` method of the configured device types, for example
+/// [`VideoCaptureDevice::into_stream_with`][crate::VideoCaptureDevice::into_stream_with].
+///
+/// By default, the driver allocates 2 buffers that are `mmap`ped into the process. Drivers that
+/// need more buffers to operate (as indicated by the `MIN_BUFFERS_FOR_CAPTURE` and
+/// `MIN_BUFFERS_FOR_OUTPUT` controls) are given at least as many buffers as they require.
+pub struct StreamBuilder<'a> {
+    source: BufferSource<'a>,
+    memory_flags: MemoryFlags,
+    requests: bool,
+}
+
+impl StreamBuilder<'static> {
+    /// Creates a builder using the default configuration.
+    pub fn new() -> Self {
+        Self {
+            source: BufferSource::Mmap(DEFAULT_BUFFER_COUNT),
+            memory_flags: MemoryFlags::empty(),
+            requests: false,
+        }
+    }
+}
+
+impl Default for StreamBuilder<'static> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> StreamBuilder<'a> {
+    /// Uses `count` driver-allocated buffers that are `mmap`ped into the process.
+    ///
+    /// The driver may allocate fewer or more buffers than requested. The number of buffers that
+    /// was actually allocated can be queried with [`ReadStream::buffer_count`] and
+    /// [`WriteStream::buffer_count`].
+    pub fn mmap(self, count: u32) -> StreamBuilder<'static> {
+        StreamBuilder {
+            source: BufferSource::Mmap(count),
+            memory_flags: self.memory_flags,
+            requests: self.requests,
+        }
+    }
+
+    /// Uses application-provided buffers (the `USERPTR` memory type).
+    ///
+    /// One buffer is used per element of `buffers`. If the driver can not use all of the provided
+    /// buffers, the excess ones are left unused.
+    ///
+    /// Only formats that store a frame in a single plane are supported. Creating the stream fails
+    /// with `EINVAL` if the format has more than one plane.
+    pub fn userptr<'b>(self, buffers: Vec<&'b mut [u8]>) -> StreamBuilder<'b> {
+        StreamBuilder {
+            source: BufferSource::UserPtr(buffers),
+            memory_flags: self.memory_flags,
+            requests: self.requests,
+        }
+    }
+
+    /// Imports application-provided DMA-BUF file descriptors (the `DMABUF` memory type).
+    ///
+    /// One buffer is used per element of `fds`. If the driver can not use all of the provided
+    /// DMA-BUFs, the excess ones are left unused.
+    ///
+    /// Only formats that store a frame in a single plane are supported. Creating the stream fails
+    /// with `EINVAL` if the format has more than one plane.
+    ///
+    /// The DMA-BUFs are only passed to the driver, and not mapped into the process, so the buffer
+    /// views of the stream are empty. Use [`StreamBuilder::dmabuf_mapped`] to access their contents
+    /// through the buffer views.
+    pub fn dmabuf<'b>(self, fds: Vec<BorrowedFd<'b>>) -> StreamBuilder<'b> {
+        StreamBuilder {
+            source: BufferSource::DmaBuf(fds, false),
+            memory_flags: self.memory_flags,
+            requests: self.requests,
+        }
+    }
+
+    /// Imports application-provided DMA-BUF file descriptors, and `mmap`s them into the process.
+    ///
+    /// This works like [`StreamBuilder::dmabuf`], except that the data of the DMA-BUFs can be
+    /// accessed through the buffer views of the stream. The DMA-BUFs have to support being mapped
+    /// (read-only for capture streams, read-write for output streams).
+    pub fn dmabuf_mapped<'b>(self, fds: Vec<BorrowedFd<'b>>) -> StreamBuilder<'b> {
+        StreamBuilder {
+            source: BufferSource::DmaBuf(fds, true),
+            memory_flags: self.memory_flags,
+            requests: self.requests,
+        }
+    }
+
+    /// Sets the flags passed to the driver when allocating buffers.
+    ///
+    /// Drivers ignore flags they don't support.
+    pub fn memory_flags(mut self, flags: MemoryFlags) -> Self {
+        self.memory_flags = flags;
+        self
+    }
+
+    /// Makes a capture stream queue its buffers through media [`Request`]s.
+    ///
+    /// A [`ReadStream`] created this way starts streaming without enqueuing any buffers, and does
+    /// not enqueue dequeued buffers again. Instead, buffers have to be added to requests with
+    /// [`ReadStream::enqueue_in_request`].
+    ///
+    /// Output streams don't need this, since buffers are only enqueued when the application
+    /// provides data. They can use [`WriteStream::enqueue_in_request`] either way.
+    pub fn requests(mut self, requests: bool) -> Self {
+        self.requests = requests;
+        self
+    }
+}
+
+/// Reads the minimum number of buffers the driver needs for `buf_type`, if it reports one.
+fn min_buffers(fd: c_int, buf_type: BufType) -> Option<u32> {
+    let id = if buf_type.is_output() {
+        Cid::MIN_BUFFERS_FOR_OUTPUT
+    } else {
+        Cid::MIN_BUFFERS_FOR_CAPTURE
+    };
+    let mut control = raw::controls::Control { id, value: 0 };
+    match unsafe { raw::g_ctrl(fd, &mut control) } {
+        Ok(_) => control.value.try_into().ok(),
+        // Most drivers don't have this control.
+        Err(_) => None,
+    }
+}
+
+/// Returns an `EINVAL` error if `format` has more than one plane.
+///
+/// `USERPTR` and `DMABUF` streams take one application-provided buffer per stream buffer, so they
+/// only support formats that store the whole frame in a single plane.
+fn check_single_plane(format: &raw::Format) -> io::Result<()> {
+    if format.type_.is_multiplanar() && unsafe { format.fmt.pix_mp.num_planes } > 1 {
+        log::error!("application-provided buffers only support single-plane formats");
+        return Err(Errno::EINVAL.into());
+    }
+    Ok(())
+}
+
+fn check_min_buffers(min_buffers: Option<u32>, count: usize) {
+    if let Some(min) = min_buffers {
+        if (min as usize) > count {
+            log::warn!("driver requires {min} buffers, but only {count} were provided");
+        }
+    }
+}
+
+/// A `raw::Buffer`, along with the plane array it points to for multi-planar buffer types.
+struct RawBuffer {
+    buf: raw::Buffer,
+    planes: [raw::Plane; raw::VIDEO_MAX_PLANES],
+}
+
+impl RawBuffer {
+    fn new(buf_type: BufType, mem_type: Memory, index: u32) -> Self {
+        let mut this: Self = unsafe { mem::zeroed() };
+        this.buf.type_ = buf_type;
+        this.buf.memory = mem_type;
+        this.buf.index = index;
+        if buf_type.is_multiplanar() {
+            // For multi-planar buffers, `length` is the number of elements in the plane array.
+            this.buf.length = raw::VIDEO_MAX_PLANES as u32;
+        }
+        this
+    }
+
+    /// Returns the `raw::Buffer` to pass to an ioctl.
+    ///
+    /// The plane pointer is updated here since `self` might have been moved since the last call.
+    fn get(&mut self) -> &mut raw::Buffer {
+        if self.buf.type_.is_multiplanar() {
+            self.buf.m.planes = self.planes.as_mut_ptr();
+        }
+        &mut self.buf
+    }
+
+    /// Makes the buffer part of `request` when it is enqueued.
+    fn set_request(&mut self, request: &Request) {
+        self.buf.flags |= BufFlag::REQUEST_FD;
+        self.buf.tail.request_fd = request.as_raw_fd();
+    }
+
+    /// Returns the number of planes described by this buffer.
+    fn num_planes(&self) -> usize {
+        if self.buf.type_.is_multiplanar() {
+            self.buf.length as usize
+        } else {
+            1
+        }
+    }
 }
 
 /// Owns all buffers allocated or mapped for a device stream.
-struct Buffers {
+struct Buffers<'a> {
     ty: AllocType,
-    /// The buffer index equals its index in this vector.
-    buffers: Vec<Buffer>,
+    buf_type: BufType,
+    mem_type: Memory,
+    flags: MemoryFlags,
+    /// Capabilities of the queue, as reported by the last `REQBUFS` or `CREATE_BUFS` call.
+    capabilities: BufCap,
+    /// The buffer index equals its index in this vector. Removed buffers leave a `None` behind.
+    buffers: Vec<Option<Buffer>>,
+    /// Set when reallocating the buffers failed in a way that left the driver's buffers in an
+    /// unknown state. The stream can't be started again after that.
+    lost: bool,
+    /// `USERPTR` and `DMABUF` buffers are borrowed from the application.
+    _p: PhantomData<&'a mut [u8]>,
 }
 
-unsafe impl Send for Buffers {}
-unsafe impl Sync for Buffers {}
+unsafe impl Send for Buffers<'_> {}
+unsafe impl Sync for Buffers<'_> {}
 
 /// Number of buffers we request by default.
-pub(super) const DEFAULT_BUFFER_COUNT: u32 = 2;
+const DEFAULT_BUFFER_COUNT: u32 = 2;
+
+impl<'a> Buffers<'a> {
+    fn allocate(fd: c_int, buf_type: BufType, builder: StreamBuilder<'a>) -> io::Result<Self> {
+        let flags = builder.memory_flags;
+        let min_buffers = min_buffers(fd, buf_type);
+        match builder.source {
+            BufferSource::Mmap(buffer_count) => {
+                let buffer_count = match min_buffers {
+                    Some(min) if min > buffer_count => {
+                        log::debug!("driver requires {min} buffers, requesting {min} instead of {buffer_count}");
+                        min
+                    }
+                    _ => buffer_count,
+                };
+                Self::allocate_mmap(fd, buf_type, flags, buffer_count)
+            }
+            BufferSource::UserPtr(buffers) => {
+                check_min_buffers(min_buffers, buffers.len());
+                Self::allocate_userptr(fd, buf_type, flags, buffers)
+            }
+            BufferSource::DmaBuf(fds, map) => {
+                check_min_buffers(min_buffers, fds.len());
+                Self::allocate_dmabuf(fd, buf_type, flags, fds, map)
+            }
+        }
+    }
 
-impl Buffers {
-    fn allocate(
-        fd: c_int,
-        buf_type: BufType,
-        mem_type: Memory,
-        mut buffer_count: u32,
-    ) -> io::Result<Self> {
-        let alloc_type = match mem_type {
-            Memory::MMAP => AllocType::Mmap,
-            _ => unimplemented!("only `mmap` memory type is currently supported"),
-        };
+    fn empty(ty: AllocType, buf_type: BufType, mem_type: Memory, flags: MemoryFlags) -> Self {
+        Self {
+            ty,
+            buf_type,
+            mem_type,
+            flags,
+            capabilities: BufCap::empty(),
+            buffers: Vec::new(),
+            lost: false,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns an `EIO` error if the buffers were lost by a failed [`Buffers::reallocate`].
+    fn check_usable(&self) -> io::Result<()> {
+        if self.lost {
+            return Err(Errno::EIO.into());
+        }
+        Ok(())
+    }
+
+    /// Releases all buffers and marks them as lost.
+    fn lose(&mut self) {
+        *self = Self::empty(self.ty, self.buf_type, self.mem_type, self.flags);
+        self.lost = true;
+    }
 
+    /// Requests `buffer_count` buffers from the driver, returning the number actually allocated.
+    fn request(&mut self, fd: c_int, buffer_count: u32) -> io::Result<u32> {
         let mut req_bufs: raw::RequestBuffers = unsafe { mem::zeroed() };
         req_bufs.count = buffer_count;
-        req_bufs.type_ = buf_type;
-        req_bufs.memory = mem_type;
+        req_bufs.type_ = self.buf_type;
+        req_bufs.memory = self.mem_type;
+        req_bufs.flags = self.flags;
 
         unsafe {
             raw::reqbufs(fd, &mut req_bufs)?;
         }
 
         log::debug!("{:?}", req_bufs);
+        self.capabilities = req_bufs.capabilities;
 
         if req_bufs.count < buffer_count {
             log::trace!("failed to allocate {buffer_count} buffers (driver only allocated {0}), using {0} instead", req_bufs.count);
-            buffer_count = req_bufs.count;
+            return Ok(req_bufs.count);
         }
 
-        // Query the buffer locations and map them into our process.
-        let mut buffers = Vec::with_capacity(buffer_count as usize);
+        Ok(buffer_count)
+    }
+
+    fn allocate_mmap(
+        fd: c_int,
+        buf_type: BufType,
+        flags: MemoryFlags,
+        buffer_count: u32,
+    ) -> io::Result<Self> {
+        let mem_type = Memory::MMAP;
+        let mut this = Self::empty(AllocType::Mmap, buf_type, mem_type, flags);
+        let buffer_count = this.request(fd, buffer_count)?;
+        let format = crate::get_format_raw(&fd, buf_type)?.into_raw();
+
+        this.buffers.reserve(buffer_count as usize);
         for i in 0..buffer_count {
-            let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-            buf.type_ = buf_type;
-            buf.memory = mem_type;
-            buf.index = i;
+            this.map(fd, i, format, true)?;
+        }
 
-            unsafe {
-                raw::querybuf(fd, &mut buf)?;
-            }
+        Ok(this)
+    }
+
+    /// Queries the location of the `mmap` buffer at `index` and maps it into our process.
+    fn map(&mut self, fd: c_int, index: u32, format: raw::Format, requeue: bool) -> io::Result<()> {
+        let buf_type = self.buf_type;
+        let mut raw_buf = RawBuffer::new(buf_type, self.mem_type, index);
+
+        unsafe {
+            raw::querybuf(fd, raw_buf.get())?;
+        }
+
+        assert_eq!(raw_buf.buf.index, index);
+
+        // The buffer is added before its planes are mapped, so that they are unmapped on error.
+        let slot = index as usize;
+        if self.buffers.len() <= slot {
+            self.buffers.resize_with(slot + 1, || None);
+        }
+        let buffer = self.buffers[slot].insert(Buffer {
+            planes: Vec::with_capacity(raw_buf.num_planes()),
+            queued: AtomicBool::new(false),
+            format,
+            requeue,
+        });
+        for plane in 0..raw_buf.num_planes() {
+            let (offset, length) = if buf_type.is_multiplanar() {
+                let plane = &raw_buf.planes[plane];
+                (unsafe { plane.m.mem_offset }, plane.length)
+            } else {
+                (unsafe { raw_buf.buf.m.offset }, raw_buf.buf.length)
+            };
 
             // NB: buffer sizes are usually `PixFormat::size_image(_)` rounded up to whole pages
             let ptr = unsafe {
                 mmap(
                     None,
-                    NonZeroUsize::try_from(buf.length as usize)
+                    NonZeroUsize::try_from(length as usize)
                         .expect("V4L2 returned buffer size of 0"),
                     // XXX is PROT_WRITE allowed for `ReadStream`s?
                     ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
                     MapFlags::MAP_SHARED,
                     fd,
-                    buf.m.offset.into(),
+                    offset.into(),
                 )?
             };
 
-            assert_eq!(buf.index, i);
-            assert_eq!(buf.index as usize, buffers.len());
-
-            buffers.push(Buffer {
+            buffer.planes.push(Plane {
                 ptr,
-                length: buf.length,
-                queued: false,
+                length,
+                dmabuf_fd: -1,
             });
         }
 
-        Ok(Self {
-            ty: alloc_type,
-            buffers,
-        })
+        Ok(())
     }
-}
 
-impl Drop for Buffers {
-    fn drop(&mut self) {
-        for buffer in &self.buffers {
-            match self.ty {
-                AllocType::Mmap => unsafe {
-                    munmap(buffer.ptr, buffer.length as usize).ok();
-                },
-            }
+    fn allocate_userptr(
+        fd: c_int,
+        buf_type: BufType,
+        flags: MemoryFlags,
+        mut user_buffers: Vec<&'a mut [u8]>,
+    ) -> io::Result<Self> {
+        let mem_type = Memory::USERPTR;
+        let mut this = Self::empty(AllocType::UserPtr, buf_type, mem_type, flags);
+        let format = crate::get_format_raw(&fd, buf_type)?.into_raw();
+        check_single_plane(&format)?;
+        if user_buffers.iter().any(|buf| buf.len() > u32::MAX as usize) {
+            return Err(Errno::EINVAL.into());
         }
-    }
-}
+        let buffer_count = this.request(fd, user_buffers.len() as u32)?;
+        user_buffers.truncate(buffer_count as usize);
+
+        this.buffers.extend(user_buffers.into_iter().map(|buf| {
+            Some(Buffer {
+                planes: vec![Plane {
+                    ptr: buf.as_mut_ptr().cast(),
+                    length: buf.len() as u32,
+                    dmabuf_fd: -1,
+                }],
+                queued: AtomicBool::new(false),
+                format,
+                requeue: true,
+            })
+        }));
 
-/// A stream that reads data from a V4L2 device.
-pub struct ReadStream {
-    file: File,
-    buffers: Buffers,
-    buf_type: BufType,
-    mem_type: Memory,
-}
+        Ok(this)
+    }
 
-impl ReadStream {
-    pub(crate) fn new(
-        file: File,
+    fn allocate_dmabuf(
+        fd: c_int,
         buf_type: BufType,
-        mem_type: Memory,
-        buffer_count: u32,
+        flags: MemoryFlags,
+        mut fds: Vec<BorrowedFd<'a>>,
+        map: bool,
     ) -> io::Result<Self> {
-        let fd = file.as_raw_fd();
-        let buffers = Buffers::allocate(fd, buf_type, mem_type, buffer_count)?;
-
-        let mut this = Self {
-            file,
-            buffers,
-            buf_type,
-            mem_type,
+        let mem_type = Memory::DMABUF;
+        let mut this = Self::empty(AllocType::DmaBuf, buf_type, mem_type, flags);
+        let format = crate::get_format_raw(&fd, buf_type)?.into_raw();
+        check_single_plane(&format)?;
+        let buffer_count = this.request(fd, fds.len() as u32)?;
+        fds.truncate(buffer_count as usize);
+
+        let prot = if buf_type.is_output() {
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+        } else {
+            ProtFlags::PROT_READ
         };
-        this.enqueue_all()?;
-        this.stream_on()?;
+        for dmabuf in fds {
+            let dmabuf_fd = dmabuf.as_raw_fd();
+            // The size of a DMA-BUF can be queried by seeking to its end.
+            let length = lseek(dmabuf_fd, 0, Whence::SeekEnd)?;
+            let length = u32::try_from(length).map_err(|_| Errno::EINVAL)?;
+            let map_length = NonZeroUsize::new(length as usize).ok_or(Errno::EINVAL)?;
+
+            let ptr = if map {
+                unsafe { mmap(None, map_length, prot, MapFlags::MAP_SHARED, dmabuf_fd, 0)? }
+            } else {
+                ptr::null_mut()
+            };
+
+            this.buffers.push(Some(Buffer {
+                planes: vec![Plane {
+                    ptr,
+                    length,
+                    dmabuf_fd,
+                }],
+                queued: AtomicBool::new(false),
+                format,
+                requeue: true,
+            }));
+        }
 
         Ok(this)
     }
 
-    fn enqueue(&mut self, index: u32) -> io::Result<()> {
-        let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-        buf.type_ = self.buf_type;
-        buf.memory = self.mem_type;
-        buf.index = index;
+    /// Frees the driver's buffers, calls `f`, then allocates the same number of buffers again.
+    ///
+    /// This allows changing the format of a stream, which is not allowed while buffers are
+    /// allocated. No buffer may be queued when calling this. If `f` fails, the buffers are still
+    /// reallocated (with the old format).
+    ///
+    /// All buffers are reallocated for the stream's format, including the ones added with
+    /// [`Buffers::create`]. If the old buffers can't be freed, they are kept. If the new buffers
+    /// can't be allocated, the buffers are [lost][Buffers::lose].
+    fn reallocate<T>(&mut self, fd: c_int, f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        self.check_usable()?;
+        let (buf_type, mem_type, flags) = (self.buf_type, self.mem_type, self.flags);
+        let count = self.count() as u32;
+        match self.ty {
+            AllocType::Mmap => {
+                // The driver can only free the buffers once they're no longer mapped. Remember
+                // them, so that they can be mapped again if the driver refuses to free them.
+                let old = self
+                    .iter()
+                    .map(|(i, b)| (i, b.format, b.requeue))
+                    .collect::<Vec<_>>();
+                *self = Self::empty(AllocType::Mmap, buf_type, mem_type, flags);
+                if let Err(e) = self.request(fd, 0) {
+                    for (index, format, requeue) in old {
+                        if self.map(fd, index, format, requeue).is_err() {
+                            self.lose();
+                            break;
+                        }
+                    }
+                    return Err(e);
+                }
+                let res = f();
+                match Self::allocate_mmap(fd, buf_type, flags, count) {
+                    Ok(buffers) => *self = buffers,
+                    Err(e) => {
+                        self.lose();
+                        return Err(e);
+                    }
+                }
+                res
+            }
+            AllocType::UserPtr | AllocType::DmaBuf => {
+                // Buffers can only be removed from `mmap` streams, so there are no gaps here.
+                self.request(fd, 0)?;
+                let res = f();
+                let realloc = self.request(fd, count).and_then(|count| {
+                    let format = crate::get_format_raw(&fd, buf_type)?.into_raw();
+                    Ok((count, format))
+                });
+                let (count, format) = match realloc {
+                    Ok(realloc) => realloc,
+                    Err(e) => {
+                        self.lose();
+                        return Err(e);
+                    }
+                };
+                self.buffers.truncate(count as usize);
+                for (_, buffer) in self.iter_mut() {
+                    buffer.format = format;
+                }
+                res
+            }
+        }
+    }
 
-        unsafe {
-            raw::qbuf(self.file.as_raw_fd(), &mut buf)?;
+    /// Allocates `count` additional `mmap` buffers for `format` with `CREATE_BUFS`, and returns
+    /// the range of their indices.
+    fn create(&mut self, fd: c_int, count: u32, format: Format) -> io::Result<Range<u32>> {
+        self.check_usable()?;
+        // Only driver-allocated buffers can be created, others are provided by the application
+        // when the stream is created.
+        if !matches!(self.ty, AllocType::Mmap) {
+            return Err(Errno::EINVAL.into());
+        }
+        let format = format.into_raw();
+        if format.type_ != self.buf_type {
+            return Err(Errno::EINVAL.into());
         }
 
-        self.buffers.buffers[index as usize].queued = true;
+        let mut create: raw::CreateBuffers = unsafe { mem::zeroed() };
+        create.count = count;
+        create.memory = self.mem_type;
+        create.format = format;
+        create.flags = self.flags.bits().into();
 
-        Ok(())
-    }
+        unsafe {
+            raw::create_bufs(fd, &mut create)?;
+        }
+        self.capabilities = create.capabilities;
 
-    fn enqueue_all(&mut self) -> io::Result<()> {
-        for i in 0..self.buffers.buffers.len() {
-            if !self.buffers.buffers[i].queued {
-                self.enqueue(i as u32)?;
-            }
+        let indices = create.index..create.index + create.count;
+        log::debug!("created buffers {indices:?}");
+        for index in indices.clone() {
+            self.map(fd, index, format, false)?;
         }
-        Ok(())
+
+        Ok(indices)
     }
 
-    /// Starts streaming.
+    /// Removes the buffers with the given indices with `REMOVE_BUFS`.
     ///
-    /// This function can potentially block for a noticeable amount of time.
-    fn stream_on(&mut self) -> io::Result<()> {
-        unsafe {
-            raw::streamon(self.file.as_raw_fd(), &self.buf_type)?;
+    /// None of the buffers may be queued or in use by the application.
+    fn remove(&mut self, fd: c_int, indices: Range<u32>) -> io::Result<()> {
+        // `USERPTR` and `DMABUF` streams are expected to keep their buffers.
+        if !matches!(self.ty, AllocType::Mmap) {
+            return Err(Errno::EINVAL.into());
+        }
+        for index in indices.clone() {
+            if self.get(index)?.queued.load(Ordering::Acquire) {
+                return Err(Errno::EBUSY.into());
+            }
         }
 
-        Ok(())
-    }
+        let mut remove: raw::RemoveBuffers = unsafe { mem::zeroed() };
+        remove.index = indices.start;
+        remove.count = indices.len() as u32;
+        remove.type_ = self.buf_type;
 
-    // XXX to publicly expose this, we have to handle the fact that it dequeues all buffers
-    fn stream_off(&mut self) -> io::Result<()> {
         unsafe {
-            raw::streamoff(self.file.as_raw_fd(), &self.buf_type)?;
+            raw::remove_bufs(fd, &mut remove)?;
         }
 
-        for b in &mut self.buffers.buffers {
-            b.queued = false;
+        for index in indices {
+            if let Some(buffer) = self.buffers[index as usize].take() {
+                self.unmap(&buffer);
+            }
+        }
+        while let Some(None) = self.buffers.last() {
+            self.buffers.pop();
         }
 
         Ok(())
     }
 
-    /// Dequeues a buffer, passes it to `cb`, then enqueues it again.
-    ///
-    /// If `cb` returns an error, this function will still try to enqueue the buffer again. If that
-    /// fails, the error that occurred during enqueuing will be returned, if it succeeds, the error
-    /// returned by `cb` will be returned.
-    pub fn dequeue<T>(
-        &mut self,
-        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
-    ) -> io::Result<T> {
-        let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-        buf.type_ = self.buf_type;
-        buf.memory = self.mem_type;
+    /// Prepares the buffer at `index` with `PREPARE_BUF`, so that enqueuing it is faster.
+    fn prepare(&self, fd: c_int, index: u32) -> io::Result<()> {
+        if self.get(index)?.queued.load(Ordering::Acquire) {
+            return Err(Errno::EBUSY.into());
+        }
 
+        let mut raw_buf = self.raw_buffer(index);
         unsafe {
-            raw::dqbuf(self.file.as_raw_fd(), &mut buf)?;
+            raw::prepare_buf(fd, raw_buf.get())?;
         }
+        Ok(())
+    }
 
-        let buffer = &mut self.buffers.buffers[buf.index as usize];
-        buffer.queued = false;
-        let data =
-            unsafe { slice::from_raw_parts(buffer.ptr as *const u8, buffer.length as usize) };
-        let view = ReadBufferView {
-            flags: buf.flags,
-            data,
-            bytesused: buf.bytesused as usize,
-        };
+    /// Returns the buffer at `index`, or an `EINVAL` error if there is no such buffer.
+    ///
+    /// Used for indices passed in by the application.
+    fn get(&self, index: u32) -> io::Result<&Buffer> {
+        self.buffers
+            .get(index as usize)
+            .and_then(Option::as_ref)
+            .ok_or_else(|| Errno::EINVAL.into())
+    }
 
-        let res = cb(view);
-        // XXX not sure if we should short-circuit here
+    /// Returns the buffer at `index`, which must exist.
+    fn buffer(&self, index: u32) -> &Buffer {
+        self.buffers[index as usize]
+            .as_ref()
+            .expect("buffer was removed")
+    }
 
-        self.enqueue(buf.index)?;
+    fn buffer_mut(&mut self, index: u32) -> &mut Buffer {
+        self.buffers[index as usize]
+            .as_mut()
+            .expect("buffer was removed")
+    }
 
-        res
+    /// Returns an iterator over all buffers, along with their indices.
+    fn iter(&self) -> impl Iterator<Item = (u32, &Buffer)> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, buf)| Some((i as u32, buf.as_ref()?)))
     }
 
-    /// Tests whether the next call to [`ReadStream::dequeue`] will block.
-    ///
-    /// If this returns `false`, a filled buffer is already available and the next call to
-    /// [`ReadStream::dequeue`] will not block, but finish immediately. If this returns `true`,
-    /// the next call will block until the next buffer is available.
-    pub fn will_block(&self) -> io::Result<bool> {
-        for i in 0..self.buffers.buffers.len() {
-            let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-            buf.type_ = self.buf_type;
-            buf.memory = self.mem_type;
-            buf.index = i as u32;
+    fn iter_mut(&mut self) -> impl Iterator<Item = (u32, &mut Buffer)> {
+        self.buffers
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, buf)| Some((i as u32, buf.as_mut()?)))
+    }
 
-            unsafe {
-                raw::querybuf(self.file.as_raw_fd(), &mut buf)?;
-            }
+    /// Returns the number of buffers.
+    fn count(&self) -> usize {
+        self.iter().count()
+    }
 
-            if buf.flags.contains(BufFlag::DONE) {
-                // A buffer is marked `DONE`, so it will be returned immediately when calling
-                // `dequeue`.
-                return Ok(false);
+    fn unmap(&self, buffer: &Buffer) {
+        for plane in &buffer.planes {
+            match self.ty {
+                AllocType::Mmap => unsafe {
+                    munmap(plane.ptr, plane.length as usize).ok();
+                },
+                AllocType::DmaBuf if !plane.ptr.is_null() => unsafe {
+                    munmap(plane.ptr, plane.length as usize).ok();
+                },
+                // Not mapped.
+                AllocType::DmaBuf => {}
+                // Owned by the application.
+                AllocType::UserPtr => {}
             }
         }
+    }
 
-        Ok(true)
+    /// Exports a plane of the buffer at `index` as a DMA-BUF file descriptor.
+    fn export(&self, fd: c_int, index: u32, plane: u32) -> io::Result<OwnedFd> {
+        // Only driver-allocated buffers can be exported.
+        if !matches!(self.ty, AllocType::Mmap) {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let mut exp: raw::ExportBuffer = unsafe { mem::zeroed() };
+        exp.type_ = self.buf_type;
+        exp.index = index;
+        exp.plane = plane;
+        exp.flags = (OFlag::O_CLOEXEC | OFlag::O_RDWR).bits() as u32;
+
+        unsafe {
+            raw::expbuf(fd, &mut exp)?;
+            Ok(OwnedFd::from_raw_fd(exp.fd))
+        }
     }
-}
 
-impl Drop for ReadStream {
-    fn drop(&mut self) {
-        // Turn off the stream to dequeue all buffers.
-        // This must be done before `Buffers` can be dropped safely, at least for userptr I/O.
-        self.stream_off().ok();
+    fn export_all(&self, fd: c_int) -> io::Result<Vec<ExportedBuffer>> {
+        self.iter()
+            .map(|(index, buffer)| {
+                let planes = (0..buffer.planes.len() as u32)
+                    .map(|plane| self.export(fd, index, plane))
+                    .collect::<io::Result<_>>()?;
+                Ok(ExportedBuffer { index, planes })
+            })
+            .collect()
     }
-}
 
-impl AsRawFd for ReadStream {
-    #[inline]
-    fn as_raw_fd(&self) -> RawFd {
-        self.file.as_raw_fd()
+    /// Prepares the buffer at `index` for access by the CPU.
+    ///
+    /// This is required to keep CPU caches coherent with the device when accessing DMA-BUFs, and a
+    /// no-op for other memory types.
+    fn begin_cpu_access(&self, index: u32, write: bool) -> io::Result<()> {
+        self.sync_dmabuf(index, write, raw::DMA_BUF_SYNC_START)
     }
-}
 
-/// Immutable view into a dequeued (filled) read buffer.
+    /// Ends an access started with [`Buffers::begin_cpu_access`].
+    fn end_cpu_access(&self, index: u32, write: bool) -> io::Result<()> {
+        self.sync_dmabuf(index, write, raw::DMA_BUF_SYNC_END)
+    }
+
+    fn sync_dmabuf(&self, index: u32, write: bool, phase: u64) -> io::Result<()> {
+        if let AllocType::DmaBuf = self.ty {
+            let direction = if write {
+                raw::DMA_BUF_SYNC_WRITE
+            } else {
+                raw::DMA_BUF_SYNC_READ
+            };
+            let sync = raw::DmaBufSync {
+                flags: direction | phase,
+            };
+            // Unmapped DMA-BUFs are not accessed by the CPU.
+            let planes = self.buffer(index).planes.iter();
+            for plane in planes.filter(|p| !p.ptr.is_null()) {
+                unsafe {
+                    raw::dma_buf_sync(plane.dmabuf_fd, &sync)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a [`RawBuffer`] for passing the buffer at `index` to `VIDIOC_QBUF`.
+    fn raw_buffer(&self, index: u32) -> RawBuffer {
+        let mut raw_buf = RawBuffer::new(self.buf_type, self.mem_type, index);
+
+        let buffer = self.buffer(index);
+        if self.buf_type.is_multiplanar() {
+            raw_buf.buf.length = buffer.planes.len() as u32;
+            for (raw_plane, plane) in raw_buf.planes.iter_mut().zip(&buffer.planes) {
+                match self.ty {
+                    AllocType::Mmap => {}
+                    AllocType::UserPtr => raw_plane.m.userptr = plane.ptr as c_ulong,
+                    AllocType::DmaBuf => raw_plane.m.fd = plane.dmabuf_fd,
+                }
+                raw_plane.length = plane.length;
+            }
+        } else {
+            let plane = &buffer.planes[0];
+            match self.ty {
+                AllocType::Mmap => {}
+                AllocType::UserPtr => {
+                    raw_buf.buf.m.userptr = plane.ptr as c_ulong;
+                    raw_buf.buf.length = plane.length;
+                }
+                AllocType::DmaBuf => {
+                    raw_buf.buf.m.fd = plane.dmabuf_fd;
+                    raw_buf.buf.length = plane.length;
+                }
+            }
+        }
+
+        raw_buf
+    }
+
+    /// Returns the planes of the dequeued buffer described by `raw_buf`.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must not be queued, and the returned slices must not outlive it.
+    unsafe fn plane_views<'b>(&self, raw_buf: &RawBuffer) -> PlaneViews<'b> {
+        let buffer = self.buffer(raw_buf.buf.index);
+        let mut views = PlaneViews {
+            planes: Default::default(),
+            len: buffer.planes.len(),
+        };
+        for (i, plane) in buffer.planes.iter().enumerate() {
+            let (bytesused, data_offset) = if self.buf_type.is_multiplanar() {
+                let raw_plane = &raw_buf.planes[i];
+                (raw_plane.bytesused, raw_plane.data_offset)
+            } else {
+                (raw_buf.buf.bytesused, 0)
+            };
+
+            views.planes[i] = PlaneView {
+                data: plane.as_slice(),
+                bytesused: bytesused as usize,
+                data_offset: data_offset as usize,
+            };
+        }
+        views
+    }
+}
+
+impl Drop for Buffers<'_> {
+    fn drop(&mut self) {
+        for (_, buffer) in self.iter() {
+            self.unmap(buffer);
+        }
+    }
+}
+
+/// A stream that reads data from a V4L2 device.
+///
+/// The lifetime `'a` is the lifetime of the application-provided buffer memory, if any. Streams
+/// using driver-allocated buffers are `ReadStream<'static>`.
+pub struct ReadStream<'a> {
+    file: File,
+    buffers: Buffers<'a>,
+    buf_type: BufType,
+    streaming: bool,
+    /// Whether buffers are enqueued through requests instead of automatically.
+    requests: bool,
+    /// Number of outstanding [`HeldBuffer`]s.
+    held: AtomicUsize,
+    stats: Mutex<StatsTracker>,
+}
+
+impl<'a> ReadStream<'a> {
+    pub(crate) fn new(
+        file: File,
+        buf_type: BufType,
+        builder: StreamBuilder<'a>,
+    ) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+        let requests = builder.requests;
+        let buffers = Buffers::allocate(fd, buf_type, builder)?;
+
+        let mut this = Self {
+            file,
+            buffers,
+            buf_type,
+            streaming: false,
+            requests,
+            held: AtomicUsize::new(0),
+            stats: Mutex::new(StatsTracker::new()),
+        };
+        this.start()?;
+
+        Ok(this)
+    }
+
+    fn enqueue(&self, index: u32) -> io::Result<()> {
+        let mut raw_buf = self.buffers.raw_buffer(index);
+
+        unsafe {
+            raw::qbuf(self.file.as_raw_fd(), raw_buf.get())?;
+        }
+
+        self.buffers
+            .buffer(index)
+            .queued
+            .store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Returns a dequeued buffer to the stream.
+    ///
+    /// The buffer is enqueued again, unless buffers are enqueued through requests or the buffer was
+    /// added with [`ReadStream::create_buffers`]. In that case, it becomes available to
+    /// [`ReadStream::enqueue_in_request`] or [`ReadStream::enqueue_buffer`].
+    fn release(&self, index: u32) -> io::Result<()> {
+        let buffer = self.buffers.buffer(index);
+        if self.requests || !buffer.requeue {
+            buffer.queued.store(false, Ordering::Release);
+            Ok(())
+        } else {
+            self.enqueue(index)
+        }
+    }
+
+    /// Enqueues an unused buffer as part of `request`, and returns its index.
+    ///
+    /// The buffer is passed to the driver when the request is queued. Once the request has
+    /// completed, the buffer can be dequeued as usual, after which it becomes available to this
+    /// method again.
+    ///
+    /// This can only be used with streams created with [`StreamBuilder::requests`], otherwise an
+    /// `EBUSY` error is returned. An `EBUSY` error is also returned if all buffers are in use.
+    pub fn enqueue_in_request(&self, request: &Request) -> io::Result<u32> {
+        if !self.requests {
+            return Err(Errno::EBUSY.into());
+        }
+
+        let (index, _) = self
+            .buffers
+            .iter()
+            .find(|(_, b)| claim(b))
+            .ok_or(Errno::EBUSY)?;
+
+        self.enqueue_claimed(index, Some(request))?;
+        Ok(index)
+    }
+
+    /// Enqueues the unused buffer at `index`.
+    ///
+    /// This is meant for buffers added with [`ReadStream::create_buffers`], which are not enqueued
+    /// automatically. Once the buffer has been filled and dequeued, it is unused again.
+    ///
+    /// Returns an `EBUSY` error if the buffer is already enqueued or in use, or if the stream uses
+    /// [requests][StreamBuilder::requests].
+    pub fn enqueue_buffer(&self, index: u32) -> io::Result<()> {
+        if self.requests || !claim(self.buffers.get(index)?) {
+            return Err(Errno::EBUSY.into());
+        }
+
+        self.enqueue_claimed(index, None)
+    }
+
+    /// Enqueues a buffer whose `queued` flag was set by [`claim`], releasing it on error.
+    fn enqueue_claimed(&self, index: u32, request: Option<&Request>) -> io::Result<()> {
+        let mut raw_buf = self.buffers.raw_buffer(index);
+        if let Some(request) = request {
+            raw_buf.set_request(request);
+        }
+        if let Err(e) = unsafe { raw::qbuf(self.file.as_raw_fd(), raw_buf.get()) } {
+            self.buffers
+                .buffer(index)
+                .queued
+                .store(false, Ordering::Release);
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    fn enqueue_all(&mut self) -> io::Result<()> {
+        let indices = self
+            .buffers
+            .iter()
+            .filter(|(_, b)| b.requeue && !b.queued.load(Ordering::Relaxed))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        for i in indices {
+            self.enqueue(i)?;
+        }
+        Ok(())
+    }
+
+    /// Starts (or resumes) streaming.
+    ///
+    /// All buffers are enqueued with the driver before streaming is started, unless the stream uses
+    /// [requests][StreamBuilder::requests]. Buffers added with [`ReadStream::create_buffers`] are
+    /// not enqueued. Streams are started when they are created, so this
+    /// only needs to be called after [`ReadStream::stop`]. Does nothing if the stream is already
+    /// running.
+    ///
+    /// This function can potentially block for a noticeable amount of time.
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.streaming {
+            return Ok(());
+        }
+        self.buffers.check_usable()?;
+
+        if !self.requests {
+            self.enqueue_all()?;
+        }
+        unsafe {
+            raw::streamon(self.file.as_raw_fd(), &self.buf_type)?;
+        }
+        self.streaming = true;
+        self.stats.get_mut().unwrap().restart();
+
+        Ok(())
+    }
+
+    /// Stops (pauses) streaming.
+    ///
+    /// This makes the driver release all buffers, discarding any frames that were captured but not
+    /// yet dequeued. The device stays open and configured, and streaming can be resumed with
+    /// [`ReadStream::start`].
+    pub fn stop(&mut self) -> io::Result<()> {
+        unsafe {
+            raw::streamoff(self.file.as_raw_fd(), &self.buf_type)?;
+        }
+
+        // `STREAMOFF` removes all buffers from the driver's queues.
+        for (_, b) in self.buffers.iter_mut() {
+            *b.queued.get_mut() = false;
+        }
+        self.streaming = false;
+
+        Ok(())
+    }
+
+    /// Returns whether the stream is currently running.
+    #[inline]
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Returns the number of buffers the driver allocated for this stream.
+    #[inline]
+    pub fn buffer_count(&self) -> u32 {
+        self.buffers.count() as u32
+    }
+
+    /// Returns the capabilities of the driver's buffer queue.
+    #[inline]
+    pub fn buffer_capabilities(&self) -> BufCap {
+        self.buffers.capabilities
+    }
+
+    /// Returns statistics about the frames dequeued from this stream.
+    ///
+    /// Every dequeued buffer is counted, including those skipped by
+    /// [`ReadStream::dequeue_latest`].
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.lock().unwrap().snapshot()
+    }
+
+    /// Resets the statistics returned by [`ReadStream::stats`].
+    pub fn reset_stats(&self) {
+        self.stats.lock().unwrap().reset();
+    }
+
+    /// Changes the pixel format of the stream without closing the device.
+    ///
+    /// This stops the stream, frees the driver's buffers, negotiates the new format, allocates new
+    /// buffers, and resumes streaming if the stream was running before. Returns the format chosen
+    /// by the driver, which may differ from `format` (see [`Device::video_capture`] for details).
+    ///
+    /// For streams using application-provided buffers, the same buffers are used with the new
+    /// format, so they have to be large enough to hold a frame in that format.
+    ///
+    /// This is only supported for single-planar video streams, other streams will return an
+    /// `EINVAL` error.
+    ///
+    /// If the driver rejects the new format, the stream keeps its old format and is resumed if it
+    /// was running. If the buffers can't be reallocated, the stream is left stopped, and every
+    /// later attempt to start it or add buffers fails with `EIO`.
+    ///
+    /// [`Device::video_capture`]: crate::Device::video_capture
+    pub fn reconfigure(&mut self, format: PixFormat) -> io::Result<PixFormat> {
+        let format = pix_format(self.buf_type, format)?;
+
+        let was_streaming = self.streaming;
+        self.stop()?;
+        let fd = self.file.as_raw_fd();
+        let format = self
+            .buffers
+            .reallocate(fd, || crate::set_format_raw(&fd, format));
+        // The old buffers are kept if the format can't be changed, so streaming can continue.
+        if was_streaming && self.buffers.check_usable().is_ok() {
+            self.start()?;
+        }
+
+        Ok(negotiated_pix_format(format?))
+    }
+
+    /// Requests a change to the frame interval of a running capture stream.
+    ///
+    /// Returns the actual frame interval chosen by the driver. Some drivers do not allow changing
+    /// the frame interval while streaming and return `EBUSY`, in that case the stream has to be
+    /// stopped with [`ReadStream::stop`] first.
+    pub fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        crate::set_capture_frame_interval(&self.file, self.buf_type, interval)
+    }
+
+    /// Returns the current frame interval of the capture stream.
+    ///
+    /// Returns an `ENOTTY` error if the driver does not support frame interval configuration.
+    pub fn frame_interval(&self) -> io::Result<Fract> {
+        crate::get_capture_frame_interval(&self.file, self.buf_type)
+    }
+
+    /// Dequeues a buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// If `cb` returns an error, this function will still try to enqueue the buffer again. If that
+    /// fails, the error that occurred during enqueuing will be returned, if it succeeds, the error
+    /// returned by `cb` will be returned.
+    pub fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let view = self.dequeue_view()?;
+        self.finish_dequeue(view, cb)
+    }
+
+    /// Dequeues a buffer if one is available without blocking, passes it to `cb`, then enqueues
+    /// it again.
+    ///
+    /// Returns `Ok(None)` without calling `cb` if no filled buffer is available yet.
+    pub fn try_dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        self.dequeue_timeout(Duration::ZERO, cb)
+    }
+
+    /// Waits up to `timeout` for a filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// Returns `Ok(None)` without calling `cb` if no buffer was filled before the timeout expired.
+    /// This can be used to detect sources that have stopped delivering frames.
+    ///
+    /// Error handling works like in [`ReadStream::dequeue`].
+    pub fn dequeue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        if !wait_ready(self.file.as_raw_fd(), PollFlags::POLLIN, timeout)? {
+            return Ok(None);
+        }
+
+        self.dequeue(cb).map(Some)
+    }
+
+    /// Dequeues the most recently filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// This blocks until at least one buffer is filled. All buffers that are already filled are
+    /// dequeued, and all but the newest one are enqueued again right away without being passed to
+    /// `cb`. This keeps latency low for applications that only care about the most recent frame
+    /// (like live previews), even if they process frames slower than the device captures them.
+    ///
+    /// Returns the value returned by `cb`, along with the number of older frames that were
+    /// skipped. Error handling works like in [`ReadStream::dequeue`].
+    pub fn dequeue_latest<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<(T, u32)> {
+        let view = self.dequeue_view()?;
+        let (view, skipped) = self.skip_stale(view)?;
+        self.finish_dequeue(view, cb).map(|val| (val, skipped))
+    }
+
+    /// Replaces `view` with newer filled buffers, as long as they are available without blocking.
+    ///
+    /// Returns the newest buffer, and the number of buffers that were enqueued again.
+    fn skip_stale<'s>(
+        &'s self,
+        mut view: ReadBufferView<'s>,
+    ) -> io::Result<(ReadBufferView<'s>, u32)> {
+        let mut skipped = 0;
+        loop {
+            let ready = poll_fd(
+                self.file.as_raw_fd(),
+                PollFlags::POLLIN,
+                Some(Duration::ZERO),
+            )?;
+            if !ready.contains(PollFlags::POLLIN) {
+                break;
+            }
+
+            // Enqueue the stale buffer first, so that the driver has it available again as soon
+            // as possible.
+            self.release(view.index)?;
+            skipped += 1;
+            view = self.dequeue_view()?;
+        }
+
+        Ok((view, skipped))
+    }
+
+    /// Passes a dequeued buffer to `cb`, then enqueues it again.
+    fn finish_dequeue<T>(
+        &self,
+        view: ReadBufferView<'_>,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let index = view.index;
+
+        let res = self.buffers.begin_cpu_access(index, false).and_then(|()| {
+            let res = cb(view);
+            self.buffers.end_cpu_access(index, false).and(res)
+        });
+        // XXX not sure if we should short-circuit here
+
+        self.release(index)?;
+
+        res
+    }
+
+    /// Dequeues a buffer and returns a guard that enqueues it again when dropped.
+    ///
+    /// Unlike [`ReadStream::dequeue`], this allows holding on to a buffer (for example, while
+    /// another thread processes its contents) while continuing to dequeue more buffers. Since the
+    /// driver needs at least one buffer to capture into, at most `N - 1` buffers can be held at
+    /// the same time, where `N` is the number of buffers of the stream. If that many buffers are
+    /// already held, this returns an `EBUSY` error without dequeuing a buffer.
+    ///
+    /// The stream cannot be dropped or used mutably while any [`HeldBuffer`] is alive.
+    pub fn dequeue_held(&self) -> io::Result<HeldBuffer<'_, 'a>> {
+        let max_held = self.buffers.count().saturating_sub(1);
+        self.held
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |held| {
+                (held < max_held).then_some(held + 1)
+            })
+            .map_err(|_| Errno::EBUSY)?;
+
+        let view = self.dequeue_view().and_then(|view| {
+            match self.buffers.begin_cpu_access(view.index, false) {
+                Ok(()) => Ok(view),
+                Err(e) => {
+                    self.release(view.index).ok();
+                    Err(e)
+                }
+            }
+        });
+        match view {
+            Ok(view) => Ok(HeldBuffer {
+                stream: self,
+                view,
+                released: false,
+            }),
+            Err(e) => {
+                self.held.fetch_sub(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the number of [`HeldBuffer`]s that are currently alive.
+    pub fn held_buffers(&self) -> usize {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    /// Dequeues a filled buffer without enqueuing it again.
+    ///
+    /// The caller is responsible for enqueuing the buffer again.
+    fn dequeue_view(&self) -> io::Result<ReadBufferView<'_>> {
+        let mut raw_buf = RawBuffer::new(self.buf_type, self.buffers.mem_type, 0);
+
+        unsafe {
+            raw::dqbuf(self.file.as_raw_fd(), raw_buf.get())?;
+        }
+        self.stats.lock().unwrap().record_dequeue(&raw_buf.buf);
+
+        // The buffer's `queued` flag stays set until it is released, so that it isn't enqueued
+        // again while the application is still accessing it.
+        let index = raw_buf.buf.index;
+        Ok(ReadBufferView {
+            index,
+            flags: raw_buf.buf.flags,
+            field: raw_buf.buf.field,
+            timestamp: raw_buf.buf.timestamp,
+            sequence: raw_buf.buf.sequence,
+            timecode: raw_buf.buf.timecode,
+            planes: unsafe { self.buffers.plane_views(&raw_buf) },
+        })
+    }
+
+    /// Tests whether the next call to [`ReadStream::dequeue`] will block.
+    ///
+    /// If this returns `false`, a filled buffer is already available and the next call to
+    /// [`ReadStream::dequeue`] will not block, but finish immediately. If this returns `true`,
+    /// the next call will block until the next buffer is available.
+    ///
+    /// Note that this queries every buffer of the stream, and the result may be outdated by the
+    /// time [`ReadStream::dequeue`] is called. Prefer [`ReadStream::try_dequeue`] or
+    /// [`ReadStream::dequeue_timeout`] to avoid blocking.
+    pub fn will_block(&self) -> io::Result<bool> {
+        for (i, _) in self.buffers.iter() {
+            let mut raw_buf = RawBuffer::new(self.buf_type, self.buffers.mem_type, i);
+
+            unsafe {
+                raw::querybuf(self.file.as_raw_fd(), raw_buf.get())?;
+            }
+
+            if raw_buf.buf.flags.contains(BufFlag::DONE) {
+                // A buffer is marked `DONE`, so it will be returned immediately when calling
+                // `dequeue`.
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Blocks until a filled buffer can be dequeued or a device event is pending.
+    ///
+    /// Events have to be subscribed to with [`Events::subscribe`] to be signaled.
+    pub fn wait(&self) -> io::Result<Readiness> {
+        wait_readiness(self.file.as_raw_fd(), PollFlags::POLLIN, None)
+    }
+
+    /// Waits up to `timeout` for a filled buffer or a device event.
+    ///
+    /// Returns an empty [`Readiness`] if the timeout expired.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Readiness> {
+        wait_readiness(self.file.as_raw_fd(), PollFlags::POLLIN, Some(timeout))
+    }
+
+    /// Returns the event queue of the device.
+    pub fn events(&self) -> Events<'_> {
+        Events::new(self.file.as_fd())
+    }
+
+    /// Allocates `count` additional buffers for `format`, and returns the range of their indices.
+    ///
+    /// `format` has to be the [`Format`] variant matching the stream, and only determines the size
+    /// of the new buffers: the stream keeps capturing in its current format. This allows adding
+    /// larger buffers to a running stream, for example to capture still images at a higher
+    /// resolution than the preview once the format is changed.
+    ///
+    /// Unlike the stream's initial buffers, the new buffers are not enqueued automatically. They
+    /// can be enqueued with [`ReadStream::enqueue_buffer`] (or [`ReadStream::enqueue_in_request`]
+    /// for streams using requests), and become unused again once they have been dequeued.
+    ///
+    /// The driver may allocate fewer buffers than requested. Only streams using driver-allocated
+    /// (`mmap`) buffers can create buffers, other streams will return an `EINVAL` error.
+    /// [`ReadStream::reconfigure`] replaces all buffers with buffers for the new format.
+    pub fn create_buffers(&mut self, count: u32, format: Format) -> io::Result<Range<u32>> {
+        self.buffers.create(self.file.as_raw_fd(), count, format)
+    }
+
+    /// Prepares the unused buffer at `index` to be enqueued.
+    ///
+    /// This does the work the driver would do when enqueuing the buffer, like CPU cache
+    /// maintenance, ahead of time, so that [`ReadStream::enqueue_buffer`] finishes faster.
+    ///
+    /// Returns an `EBUSY` error if the buffer is enqueued or in use.
+    pub fn prepare_buffer(&self, index: u32) -> io::Result<()> {
+        self.buffers.prepare(self.file.as_raw_fd(), index)
+    }
+
+    /// Removes the buffers in the `indices` range from the stream, freeing their memory.
+    ///
+    /// Returns an `EBUSY` error if any of the buffers is enqueued or in use. Since buffers that are
+    /// enqueued automatically are always owned by the driver while streaming, they can only be
+    /// removed while the stream is stopped.
+    ///
+    /// This requires driver-allocated (`mmap`) buffers, and a queue with the
+    /// [`BufCap::SUPPORTS_REMOVE_BUFS`] capability (Linux 6.10 or newer).
+    pub fn remove_buffers(&mut self, indices: Range<u32>) -> io::Result<()> {
+        self.buffers.remove(self.file.as_raw_fd(), indices)
+    }
+
+    /// Returns an iterator over the buffers of this stream, in index order.
+    pub fn buffers(&self) -> impl Iterator<Item = BufferInfo<'_>> {
+        self.buffers.iter().map(BufferInfo::new)
+    }
+
+    /// Exports a buffer of this stream as a DMA-BUF file descriptor.
+    ///
+    /// `plane` selects the plane to export, and must be 0 for single-planar streams.
+    ///
+    /// The returned file descriptor refers to the same memory the driver captures into, so it can
+    /// be passed to another process or device to access the frames without copying them. Only
+    /// streams using driver-allocated (`mmap`) buffers can be exported, other streams will return
+    /// an `EINVAL` error.
+    pub fn export_buffer(&self, index: u32, plane: u32) -> io::Result<OwnedFd> {
+        self.buffers.export(self.file.as_raw_fd(), index, plane)
+    }
+
+    /// Exports every plane of every buffer of this stream as DMA-BUF file descriptors.
+    ///
+    /// The returned list is ordered by buffer index. See [`ReadStream::export_buffer`] for details.
+    pub fn export_buffers(&self) -> io::Result<Vec<ExportedBuffer>> {
+        self.buffers.export_all(self.file.as_raw_fd())
+    }
+
+    /// Turns this stream into an [`AsyncReadStream`] that can be used with any async executor.
+    ///
+    /// This puts the device file into non-blocking mode. Waiting tasks are woken by a background
+    /// thread shared by all asynchronous streams.
+    pub fn into_async(self) -> io::Result<AsyncReadStream<'a>> {
+        AsyncReadStream::new(self)
+    }
+
+    /// Turns this stream into a [`TokioReadStream`] driven by the tokio reactor.
+    ///
+    /// This puts the device file into non-blocking mode. It must be called from within a tokio
+    /// runtime.
+    #[cfg(feature = "tokio")]
+    pub fn into_tokio(self) -> io::Result<TokioReadStream<'a>> {
+        TokioReadStream::new(self)
+    }
+}
+
+impl ReadStream<'static> {
+    /// Moves this stream to a background thread that captures frames and sends copies of them
+    /// through a channel that can hold up to `capacity` frames.
+    ///
+    /// `policy` determines what happens when a frame is captured while the channel is full. See
+    /// [`CaptureWorker`] for details.
+    ///
+    /// Returns an `EINVAL` error if `capacity` is 0, or if the stream uses media requests, since
+    /// the worker has no way of enqueuing buffers in requests.
+    pub fn into_worker(self, capacity: usize, policy: OverflowPolicy) -> io::Result<CaptureWorker> {
+        if self.requests {
+            return Err(Errno::EINVAL.into());
+        }
+        CaptureWorker::new(self, capacity, policy)
+    }
+}
+
+/// Operations shared by streams that deliver captured frames.
+///
+/// This is implemented by [`ReadStream`] and by the simulated `SimStream` of the `sim` module
+/// (which requires the `mock` cargo feature), so that code written against this trait can be
+/// tested without hardware. See the inherent methods of [`ReadStream`] for documentation.
+pub trait CaptureStream {
+    /// Dequeues a buffer, passes it to `cb`, then enqueues it again.
+    fn dequeue<T>(&mut self, cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>)
+        -> io::Result<T>;
+
+    /// Waits up to `timeout` for a filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// Returns `Ok(None)` without calling `cb` if no buffer was filled before the timeout expired.
+    fn dequeue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>>;
+
+    /// Dequeues a buffer if one is available without blocking, passes it to `cb`, then enqueues
+    /// it again.
+    fn try_dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        self.dequeue_timeout(Duration::ZERO, cb)
+    }
+
+    /// Returns the current frame interval.
+    fn frame_interval(&self) -> io::Result<Fract>;
+
+    /// Requests a change to the frame interval, and returns the interval chosen by the driver.
+    fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract>;
+
+    /// Returns statistics about the frames dequeued from the stream.
+    fn stats(&self) -> CaptureStats;
+}
+
+impl CaptureStream for ReadStream<'_> {
+    fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        ReadStream::dequeue(self, cb)
+    }
+
+    fn dequeue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        ReadStream::dequeue_timeout(self, timeout, cb)
+    }
+
+    fn frame_interval(&self) -> io::Result<Fract> {
+        ReadStream::frame_interval(self)
+    }
+
+    fn set_frame_interval(&self, interval: Fract) -> io::Result<Fract> {
+        ReadStream::set_frame_interval(self, interval)
+    }
+
+    fn stats(&self) -> CaptureStats {
+        ReadStream::stats(self)
+    }
+}
+
+impl Drop for ReadStream<'_> {
+    fn drop(&mut self) {
+        // Turn off the stream to dequeue all buffers.
+        // This must be done before `Buffers` can be dropped safely, at least for userptr I/O.
+        self.stop().ok();
+    }
+}
+
+impl AsRawFd for ReadStream<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// A buffer dequeued with [`ReadStream::dequeue_held`].
+///
+/// Dereferences to a [`ReadBufferView`] of the buffer's contents. The buffer is enqueued again when
+/// this guard is dropped, or when [`HeldBuffer::requeue`] is called.
+pub struct HeldBuffer<'s, 'a> {
+    stream: &'s ReadStream<'a>,
+    view: ReadBufferView<'s>,
+    released: bool,
+}
+
+impl HeldBuffer<'_, '_> {
+    /// Enqueues the buffer again, returning any error that occurs.
+    ///
+    /// Dropping the [`HeldBuffer`] does the same, but ignores errors.
+    pub fn requeue(mut self) -> io::Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        self.released = true;
+        let index = self.view.index;
+        let res = self.stream.buffers.end_cpu_access(index, false);
+        let res = self.stream.release(index).and(res);
+        self.stream.held.fetch_sub(1, Ordering::Relaxed);
+        res
+    }
+}
+
+impl<'s> Deref for HeldBuffer<'s, '_> {
+    type Target = ReadBufferView<'s>;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.view
+    }
+}
+
+impl Drop for HeldBuffer<'_, '_> {
+    fn drop(&mut self) {
+        if !self.released {
+            if let Err(e) = self.release() {
+                log::warn!("failed to enqueue held buffer {}: {}", self.view.index, e);
+            }
+        }
+    }
+}
+
+fn timeval_to_duration(tv: libc::timeval) -> Duration {
+    // Negative values are not valid timestamps, and are mapped to zero.
+    let secs = tv.tv_sec.try_into().unwrap_or(0);
+    let micros: u32 = tv.tv_usec.try_into().unwrap_or(0);
+    Duration::new(secs, 0) + Duration::from_micros(micros.into())
+}
+
+fn duration_to_timeval(duration: Duration) -> libc::timeval {
+    libc::timeval {
+        tv_sec: duration.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+        tv_usec: duration.subsec_micros().into(),
+    }
+}
+
+/// Wraps `format` in the [`Format`] variant matching `buf_type`.
+fn pix_format(buf_type: BufType, format: PixFormat) -> io::Result<Format> {
+    match buf_type {
+        BufType::VIDEO_CAPTURE => Ok(Format::VideoCapture(format)),
+        BufType::VIDEO_OUTPUT => Ok(Format::VideoOutput(format)),
+        _ => Err(Errno::EINVAL.into()),
+    }
+}
+
+/// Extracts the [`PixFormat`] from a format returned by the driver for a [`pix_format`].
+fn negotiated_pix_format(format: Format) -> PixFormat {
+    match format {
+        Format::VideoCapture(format) | Format::VideoOutput(format) => format,
+        _ => unreachable!(),
+    }
+}
+
+/// Waits up to `timeout` for `fd` to signal any of `events`.
 ///
-/// Dereferences to a byte slice.
-pub struct ReadBufferView<'a> {
-    flags: BufFlag,
+/// Returns `false` if the timeout expired. Error conditions signaled by `poll` are treated as
+/// readiness, so that the following operation reports the error.
+fn wait_ready(fd: RawFd, events: PollFlags, timeout: Duration) -> io::Result<bool> {
+    poll_fd(fd, events, Some(timeout)).map(|ready| !ready.is_empty())
+}
+
+/// Waits up to `timeout` (or forever, if `None`) for `fd` to signal any of `events`, and returns
+/// the signaled events.
+///
+/// Returns an empty set if the timeout expired.
+pub(crate) fn poll_fd(
+    fd: RawFd,
+    events: PollFlags,
+    timeout: Option<Duration>,
+) -> io::Result<PollFlags> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let millis = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                // Round up, so that we don't spin when less than a millisecond is remaining.
+                let millis = remaining.as_nanos().div_ceil(1_000_000);
+                millis.try_into().unwrap_or(c_int::MAX)
+            }
+            None => -1,
+        };
+
+        let mut fds = [PollFd::new(fd, events)];
+        match poll(&mut fds, millis) {
+            Ok(0) => return Ok(PollFlags::empty()),
+            Ok(_) => return Ok(fds[0].revents().unwrap_or(PollFlags::empty())),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Waits up to `timeout` (or forever, if `None`) for a buffer or a device event, where `buffer`
+/// is the `poll` event signaling buffer readiness.
+fn wait_readiness(
+    fd: RawFd,
+    buffer: PollFlags,
+    timeout: Option<Duration>,
+) -> io::Result<Readiness> {
+    let ready = poll_fd(fd, buffer | PollFlags::POLLPRI, timeout)?;
+    let mut readiness = Readiness::empty();
+    // Error conditions are reported as buffer readiness, so that dequeuing reports the error.
+    if ready.intersects(!PollFlags::POLLPRI) {
+        readiness |= Readiness::BUFFER;
+    }
+    if ready.contains(PollFlags::POLLPRI) {
+        readiness |= Readiness::EVENT;
+    }
+    Ok(readiness)
+}
+
+/// Reserves an unused buffer for enqueuing by setting its `queued` flag.
+///
+/// Returns `false` if the buffer is already queued or in use.
+fn claim(buffer: &Buffer) -> bool {
+    buffer
+        .queued
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// Sets or clears `O_NONBLOCK` on `fd`.
+fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
+    let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+    let flags = if nonblocking {
+        flags | OFlag::O_NONBLOCK
+    } else {
+        flags - OFlag::O_NONBLOCK
+    };
+    fcntl(fd, FcntlArg::F_SETFL(flags))?;
+    Ok(())
+}
+
+/// The data of a single plane of a dequeued buffer.
+#[derive(Clone, Copy, Default)]
+struct PlaneView<'a> {
     data: &'a [u8],
     bytesused: usize,
+    data_offset: usize,
+}
+
+impl<'a> PlaneView<'a> {
+    fn used(&self) -> &'a [u8] {
+        // Clamp the offsets so that bogus values reported by the driver can't cause a panic.
+        let end = self.bytesused.min(self.data.len());
+        let start = self.data_offset.min(end);
+        &self.data[start..end]
+    }
+}
+
+struct PlaneViews<'a> {
+    planes: [PlaneView<'a>; raw::VIDEO_MAX_PLANES],
+    len: usize,
+}
+
+/// Immutable view into a dequeued (filled) read buffer.
+///
+/// Dereferences to a byte slice containing the data of the first plane. Multi-planar buffers can
+/// be accessed with [`ReadBufferView::plane`] and [`ReadBufferView::planes`].
+pub struct ReadBufferView<'a> {
+    index: u32,
+    flags: BufFlag,
+    field: Field,
+    timestamp: libc::timeval,
+    sequence: u32,
+    timecode: raw::Timecode,
+    planes: PlaneViews<'a>,
+}
+
+impl<'a> ReadBufferView<'a> {
+    /// Creates a view of a single-planar buffer that was not dequeued from a device.
+    #[cfg(any(test, feature = "mock"))]
+    pub(crate) fn from_parts(
+        index: u32,
+        flags: BufFlag,
+        field: Field,
+        timestamp: Duration,
+        sequence: u32,
+        data: &'a [u8],
+    ) -> Self {
+        let mut planes = PlaneViews {
+            planes: Default::default(),
+            len: 1,
+        };
+        planes.planes[0] = PlaneView {
+            data,
+            bytesused: data.len(),
+            data_offset: 0,
+        };
+        Self {
+            index,
+            flags,
+            field,
+            timestamp: duration_to_timeval(timestamp),
+            sequence,
+            timecode: unsafe { mem::zeroed() },
+            planes,
+        }
+    }
+
+    /// Returns the index of this buffer in the stream.
+    ///
+    /// For streams using application-provided buffers, this is the index of the buffer in the list
+    /// passed when creating the stream.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns whether the error flag for this buffer is set.
+    ///
+    /// If this returns `true`, the application should expect data corruption in the buffer data.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.flags.contains(BufFlag::ERROR)
+    }
+
+    /// Returns whether this is the last buffer the driver will produce before stopping.
+    ///
+    /// Memory-to-memory devices like codecs set this flag on the last buffer of a drain sequence
+    /// or before a resolution change. The buffer may be empty. Dequeuing more buffers afterwards
+    /// fails with `EPIPE` until the device is restarted.
+    #[inline]
+    pub fn is_last(&self) -> bool {
+        self.flags.contains(BufFlag::LAST)
+    }
+
+    /// Returns the timestamp the driver attached to this buffer.
+    ///
+    /// The meaning of the timestamp depends on [`ReadBufferView::timestamp_type`]. For
+    /// [`TimestampType::Monotonic`] timestamps, this is the time since an unspecified point in the
+    /// past as measured by `CLOCK_MONOTONIC`, so it can be compared against the current time
+    /// returned by `clock_gettime(CLOCK_MONOTONIC)`, or against timestamps of other devices.
+    /// [`ReadBufferView::timestamp_source`] determines which point in the capture process the
+    /// timestamp refers to.
+    pub fn timestamp(&self) -> Duration {
+        timeval_to_duration(self.timestamp)
+    }
+
+    /// Returns the clock that was used for [`ReadBufferView::timestamp`].
+    pub fn timestamp_type(&self) -> TimestampType {
+        TimestampType::from_flags(self.flags)
+    }
+
+    /// Returns which event [`ReadBufferView::timestamp`] was taken at.
+    pub fn timestamp_source(&self) -> TimestampSource {
+        TimestampSource::from_flags(self.flags)
+    }
+
+    /// Returns the sequence number of this buffer.
+    ///
+    /// The driver increments the sequence number for every frame it captures, even if it has to
+    /// drop the frame because no buffer is available. Gaps in the sequence numbers of consecutive
+    /// buffers thus indicate dropped frames.
+    ///
+    /// For [`Field::ALTERNATE`] streams, the sequence number is incremented for every field, not
+    /// every frame.
+    #[inline]
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the field(s) contained in this buffer.
+    #[inline]
+    pub fn field(&self) -> Field {
+        self.field
+    }
+
+    /// Returns the timecode attached to this buffer, if any.
+    pub fn timecode(&self) -> Option<Timecode> {
+        if self.flags.contains(BufFlag::TIMECODE) {
+            Some(Timecode(self.timecode))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of planes in this buffer.
+    ///
+    /// This is always 1 for single-planar buffers.
+    #[inline]
+    pub fn num_planes(&self) -> usize {
+        self.planes.len
+    }
+
+    /// Returns the *used* portion of plane `index`.
+    ///
+    /// This excludes any unused space at the end of the plane, as well as any data before the
+    /// plane's data offset (which may contain a header for some formats).
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `index` is not less than [`ReadBufferView::num_planes`].
+    pub fn plane(&self, index: usize) -> &'a [u8] {
+        self.planes.planes[..self.planes.len][index].used()
+    }
+
+    /// Returns an iterator over the *used* portions of all planes in this buffer.
+    pub fn planes(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.planes.planes[..self.planes.len]
+            .iter()
+            .map(|plane| plane.used())
+    }
+
+    /// Returns a reference to the *entire* backing buffer of plane `index`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `index` is not less than [`ReadBufferView::num_planes`].
+    pub fn raw_plane(&self, index: usize) -> &'a [u8] {
+        self.planes.planes[..self.planes.len][index].data
+    }
+
+    /// Returns a reference to the *entire* backing buffer.
+    ///
+    /// [`ReadBufferView`] dereferences to the *used* portion of the buffer. For fixed-size
+    /// (uncompressed) image formats, the *used* portion is typically equal to the entire buffer,
+    /// but for compressed formats like MJPEG, the backing buffer, which can be access with this
+    /// method, is usually a lot larger than the actual data of interest.
+    ///
+    /// Normally, this method does not need to be used, as only the used portion of the buffer is
+    /// needed.
+    ///
+    /// For multi-planar buffers, this returns the first plane.
+    #[inline]
+    pub fn raw_buffer(&self) -> &'a [u8] {
+        self.raw_plane(0)
+    }
+}
+
+impl Deref for ReadBufferView<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.plane(0)
+    }
+}
+
+/// The clock used to timestamp buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimestampType {
+    /// The clock is unknown.
+    Unknown,
+    /// Timestamps are taken from `CLOCK_MONOTONIC`.
+    Monotonic,
+    /// The timestamp was copied from the corresponding output buffer (for memory-to-memory
+    /// devices).
+    Copy,
+}
+
+impl TimestampType {
+    fn from_flags(flags: BufFlag) -> Self {
+        let ty = flags & BufFlag::TIMESTAMP_MASK;
+        if ty == BufFlag::TIMESTAMP_MONOTONIC {
+            Self::Monotonic
+        } else if ty == BufFlag::TIMESTAMP_COPY {
+            Self::Copy
+        } else {
+            Self::Unknown
+        }
+    }
+}
+
+/// The event a buffer timestamp refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimestampSource {
+    /// The timestamp was taken when the last pixel of the frame was received (end of frame).
+    ///
+    /// This is also used when the driver doesn't report where the timestamp was taken.
+    EndOfFrame,
+    /// The timestamp was taken when the exposure of the frame started (start of exposure).
+    StartOfExposure,
+}
+
+impl TimestampSource {
+    fn from_flags(flags: BufFlag) -> Self {
+        if flags & BufFlag::TIMESTAMP_SRC_MASK == BufFlag::TIMESTAMP_SRC_SOE {
+            Self::StartOfExposure
+        } else {
+            Self::EndOfFrame
+        }
+    }
+}
+
+/// A SMPTE timecode attached to a buffer.
+///
+/// The [`fmt::Display`] implementation formats the timecode as `HH:MM:SS:FF` (or `HH:MM:SS;FF`
+/// for drop-frame timecodes).
+#[derive(Clone, Copy)]
+pub struct Timecode(raw::Timecode);
+
+impl Timecode {
+    /// Returns the frame rate the timecode is based on, in frames per second.
+    ///
+    /// Returns `None` if the driver reported an unknown timecode type.
+    pub fn frame_rate(&self) -> Option<u32> {
+        Some(match self.0.type_ {
+            TimecodeType::T_24FPS => 24,
+            TimecodeType::T_25FPS => 25,
+            TimecodeType::T_30FPS => 30,
+            TimecodeType::T_50FPS => 50,
+            TimecodeType::T_60FPS => 60,
+            _ => return None,
+        })
+    }
+
+    /// Returns the hours component of the timecode.
+    #[inline]
+    pub fn hours(&self) -> u8 {
+        self.0.hours
+    }
+
+    /// Returns the minutes component of the timecode.
+    #[inline]
+    pub fn minutes(&self) -> u8 {
+        self.0.minutes
+    }
+
+    /// Returns the seconds component of the timecode.
+    #[inline]
+    pub fn seconds(&self) -> u8 {
+        self.0.seconds
+    }
+
+    /// Returns the frame number within the current second.
+    #[inline]
+    pub fn frames(&self) -> u8 {
+        self.0.frames
+    }
+
+    /// Returns whether this is a drop-frame timecode.
+    ///
+    /// Drop-frame timecodes skip some frame numbers to compensate for NTSC frame rates (such as
+    /// 29.97 FPS) not being an integer.
+    #[inline]
+    pub fn is_drop_frame(&self) -> bool {
+        self.0.flags.contains(TimecodeFlags::DROPFRAME)
+    }
+
+    /// Returns whether the "color frame" flag is set.
+    #[inline]
+    pub fn is_color_frame(&self) -> bool {
+        self.0.flags.contains(TimecodeFlags::COLORFRAME)
+    }
+
+    /// Returns the 4 bytes of user-defined data embedded in the timecode.
+    #[inline]
+    pub fn userbits(&self) -> [u8; 4] {
+        self.0.userbits
+    }
 }
 
-impl<'a> ReadBufferView<'a> {
-    /// Returns whether the error flag for this buffer is set.
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sep = if self.is_drop_frame() { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours(),
+            self.minutes(),
+            self.seconds(),
+            sep,
+            self.frames(),
+        )
+    }
+}
+
+impl fmt::Debug for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timecode")
+            .field("type", &self.0.type_)
+            .field("flags", &self.0.flags)
+            .field("time", &format_args!("{}", self))
+            .field("userbits", &self.0.userbits)
+            .finish()
+    }
+}
+
+/// A stream that writes to a V4L2 device.
+///
+/// The lifetime `'a` is the lifetime of the application-provided buffer memory, if any. Streams
+/// using driver-allocated buffers are `WriteStream<'static>`.
+pub struct WriteStream<'a> {
+    file: File,
+    buffers: Buffers<'a>,
+    /// Indices of the buffers that are not queued, in the order they should be used in.
+    free_buffers: VecDeque<usize>,
+    /// The time each buffer was last enqueued at.
+    queued_at: Vec<Instant>,
+    /// Buffers returned by the driver that have not yet been reported to the application.
+    completed: VecDeque<CompletedBuffer>,
+    buf_type: BufType,
+    streaming: bool,
+}
+
+impl<'a> WriteStream<'a> {
+    pub(crate) fn new(
+        file: File,
+        buf_type: BufType,
+        builder: StreamBuilder<'a>,
+    ) -> io::Result<Self> {
+        let fd = file.as_raw_fd();
+        let buffers = Buffers::allocate(fd, buf_type, builder)?;
+
+        let mut this = Self {
+            file,
+            buffers,
+            free_buffers: VecDeque::new(),
+            queued_at: Vec::new(),
+            completed: VecDeque::new(),
+            buf_type,
+            streaming: false,
+        };
+        this.reset_buffer_state();
+        this.start()?;
+
+        Ok(this)
+    }
+
+    /// Resets the bookkeeping of buffer ownership after the driver released all buffers.
+    fn reset_buffer_state(&mut self) {
+        self.free_buffers.clear();
+        for (i, b) in self.buffers.iter_mut() {
+            *b.queued.get_mut() = false;
+            self.free_buffers.push_back(i as usize);
+        }
+        // Indexed by buffer index, so this includes the slots of removed buffers.
+        self.queued_at = vec![Instant::now(); self.buffers.buffers.len()];
+        self.completed.clear();
+    }
+
+    /// Starts (or resumes) streaming.
+    ///
+    /// Streams are started when they are created, so this only needs to be called after
+    /// [`WriteStream::stop`]. Does nothing if the stream is already running.
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.streaming {
+            return Ok(());
+        }
+        self.buffers.check_usable()?;
+
+        unsafe {
+            raw::streamon(self.file.as_raw_fd(), &self.buf_type)?;
+        }
+        self.streaming = true;
+
+        Ok(())
+    }
+
+    /// Stops (pauses) streaming.
+    ///
+    /// This makes the driver release all buffers, discarding any frames that were enqueued but
+    /// not yet output. Streaming can be resumed with [`WriteStream::start`].
+    pub fn stop(&mut self) -> io::Result<()> {
+        unsafe {
+            raw::streamoff(self.file.as_raw_fd(), &self.buf_type)?;
+        }
+
+        // `STREAMOFF` removes all buffers from the driver's queues.
+        self.reset_buffer_state();
+        self.streaming = false;
+
+        Ok(())
+    }
+
+    /// Returns whether the stream is currently running.
+    #[inline]
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Returns the number of buffers the driver allocated for this stream.
+    #[inline]
+    pub fn buffer_count(&self) -> u32 {
+        self.buffers.count() as u32
+    }
+
+    /// Returns the capabilities of the driver's buffer queue.
+    #[inline]
+    pub fn buffer_capabilities(&self) -> BufCap {
+        self.buffers.capabilities
+    }
+
+    /// Changes the pixel format of the stream without closing the device.
+    ///
+    /// This works like [`ReadStream::reconfigure`]. Any enqueued frames that were not yet output
+    /// are discarded.
+    pub fn reconfigure(&mut self, format: PixFormat) -> io::Result<PixFormat> {
+        let format = pix_format(self.buf_type, format)?;
+
+        let was_streaming = self.streaming;
+        self.stop()?;
+        let fd = self.file.as_raw_fd();
+        let format = self
+            .buffers
+            .reallocate(fd, || crate::set_format_raw(&fd, format));
+        // The number of buffers might have changed.
+        self.reset_buffer_state();
+        // The old buffers are kept if the format can't be changed, so streaming can continue.
+        if was_streaming && self.buffers.check_usable().is_ok() {
+            self.start()?;
+        }
+
+        Ok(negotiated_pix_format(format?))
+    }
+
+    fn enqueue_buffer(
+        &mut self,
+        index: u32,
+        meta: &OutputMeta,
+        request: Option<&Request>,
+    ) -> io::Result<()> {
+        let mut raw_buf = self.buffers.raw_buffer(index);
+        if let Some(request) = request {
+            raw_buf.set_request(request);
+        }
+        if self.buf_type.is_multiplanar() {
+            let num_planes = raw_buf.num_planes();
+            for (plane, &bytesused) in raw_buf.planes[..num_planes].iter_mut().zip(&meta.bytesused)
+            {
+                plane.bytesused = bytesused;
+            }
+        } else {
+            raw_buf.buf.bytesused = meta.bytesused[0];
+        }
+        raw_buf.buf.field = meta.field;
+        if let Some(timestamp) = meta.timestamp {
+            raw_buf.buf.timestamp = duration_to_timeval(timestamp);
+            raw_buf.buf.flags |= BufFlag::TIMESTAMP_COPY;
+        }
+
+        unsafe {
+            raw::qbuf(self.file.as_raw_fd(), raw_buf.get())?;
+        }
+
+        *self.buffers.buffer_mut(index).queued.get_mut() = true;
+        self.queued_at[index as usize] = Instant::now();
+
+        Ok(())
+    }
+
+    /// Passes a non-queued buffer to `cb` to fill it with data, then enqueues it for outputting.
+    ///
+    /// If no unqueued buffer is available, one is dequeued first (which may block until one is
+    /// available).
+    pub fn enqueue<T>(
+        &mut self,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let buf_index = self.next_free_buffer()?;
+        self.fill_and_enqueue(buf_index, None, cb)
+    }
+
+    /// Passes a non-queued buffer to `cb` to fill it with data, then adds it to `request`.
+    ///
+    /// The buffer is passed to the driver when the request is queued. Blocking works like in
+    /// [`WriteStream::enqueue`].
+    ///
+    /// Drivers that support requests do not allow mixing buffers enqueued with and without requests
+    /// while streaming, and stateless codecs require all of their output buffers to be part of a
+    /// request.
+    pub fn enqueue_in_request<T>(
+        &mut self,
+        request: &Request,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let buf_index = self.next_free_buffer()?;
+        self.fill_and_enqueue(buf_index, Some(request), cb)
+    }
+
+    /// Passes a non-queued buffer to `cb` to fill it with data, then enqueues it for outputting,
+    /// if a buffer is available without blocking.
+    ///
+    /// Returns `Ok(None)` without calling `cb` if all buffers are still in use by the driver.
+    pub fn try_enqueue<T>(
+        &mut self,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        self.enqueue_timeout(Duration::ZERO, cb)
+    }
+
+    /// Waits up to `timeout` for a non-queued buffer, passes it to `cb` to fill it with data, then
+    /// enqueues it for outputting.
     ///
-    /// If this returns `true`, the application should expect data corruption in the buffer data.
-    #[inline]
-    pub fn is_error(&self) -> bool {
-        self.flags.contains(BufFlag::ERROR)
+    /// Returns `Ok(None)` without calling `cb` if the driver did not release a buffer before the
+    /// timeout expired.
+    pub fn enqueue_timeout<T>(
+        &mut self,
+        timeout: Duration,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<Option<T>> {
+        if self.free_buffers.is_empty()
+            && !wait_ready(self.file.as_raw_fd(), PollFlags::POLLOUT, timeout)?
+        {
+            return Ok(None);
+        }
+
+        self.enqueue(cb).map(Some)
     }
 
-    /// Returns a reference to the *entire* backing buffer.
+    /// Returns information about the buffers the driver has finished outputting since the last
+    /// call.
     ///
-    /// [`ReadBufferView`] dereferences to the *used* portion of the buffer. For fixed-size
-    /// (uncompressed) image formats, the *used* portion is typically equal to the entire buffer,
-    /// but for compressed formats like MJPEG, the backing buffer, which can be access with this
-    /// method, is usually a lot larger than the actual data of interest.
+    /// This first reclaims all buffers the driver is done with (without blocking), so that they
+    /// are reported with an accurate [`CompletedBuffer::latency`]. Buffers that are reclaimed by
+    /// [`WriteStream::enqueue`] when it runs out of free buffers are reported too. At most one
+    /// entry per buffer of the stream is kept, so this should be called regularly when
+    /// measuring output latency.
+    pub fn completed_buffers(&mut self) -> io::Result<impl Iterator<Item = CompletedBuffer> + '_> {
+        while self.free_buffers.len() < self.buffers.count()
+            && wait_ready(self.file.as_raw_fd(), PollFlags::POLLOUT, Duration::ZERO)?
+        {
+            let index = self.dequeue_buffer()?;
+            self.free_buffers.push_back(index);
+        }
+
+        Ok(self.completed.drain(..))
+    }
+
+    /// Returns the index of a buffer that is not queued, dequeuing one if necessary.
+    fn next_free_buffer(&mut self) -> io::Result<usize> {
+        match self.free_buffers.pop_front() {
+            Some(i) => Ok(i),
+            // All buffers are enqueued with the driver. Dequeue one.
+            None => self.dequeue_buffer(),
+        }
+    }
+
+    /// Dequeues a buffer the driver is done with and records its completion.
+    fn dequeue_buffer(&mut self) -> io::Result<usize> {
+        let mut raw_buf = RawBuffer::new(self.buf_type, self.buffers.mem_type, 0);
+
+        unsafe {
+            raw::dqbuf(self.file.as_raw_fd(), raw_buf.get())?;
+        }
+
+        let index = raw_buf.buf.index as usize;
+        *self.buffers.buffer_mut(index as u32).queued.get_mut() = false;
+
+        if self.completed.len() >= self.buffers.count() {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(CompletedBuffer {
+            index: index as u32,
+            flags: raw_buf.buf.flags,
+            sequence: raw_buf.buf.sequence,
+            timestamp: timeval_to_duration(raw_buf.buf.timestamp),
+            latency: self.queued_at[index].elapsed(),
+        });
+
+        Ok(index)
+    }
+
+    /// Passes the unqueued buffer `buf_index` to `cb`, then enqueues it for outputting (as part of
+    /// `request`, if given).
+    fn fill_and_enqueue<T>(
+        &mut self,
+        buf_index: usize,
+        request: Option<&Request>,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let buffer = self.buffers.buffer_mut(buf_index as u32);
+        assert!(!*buffer.queued.get_mut());
+
+        let mut meta = OutputMeta {
+            sizes: [0; raw::VIDEO_MAX_PLANES],
+            bytesused: [0; raw::VIDEO_MAX_PLANES],
+            timestamp: None,
+            field: Field::ANY,
+        };
+        let mut planes: [&mut [u8]; raw::VIDEO_MAX_PLANES] = Default::default();
+        for (((view, size), bytesused), plane) in planes
+            .iter_mut()
+            .zip(&mut meta.sizes)
+            .zip(&mut meta.bytesused)
+            .zip(&buffer.planes)
+        {
+            *view = unsafe { plane.as_mut_slice() };
+            *size = plane.length;
+            // By default, the whole plane is used.
+            *bytesused = plane.length;
+        }
+        let view = WriteBufferView {
+            index: buf_index as u32,
+            planes,
+            num_planes: buffer.planes.len(),
+            meta: &mut meta,
+        };
+        let res = self
+            .buffers
+            .begin_cpu_access(buf_index as u32, true)
+            .and_then(|()| {
+                let res = cb(view);
+                self.buffers.end_cpu_access(buf_index as u32, true).and(res)
+            });
+        let res = res.and_then(|val| {
+            self.enqueue_buffer(buf_index as u32, &meta, request)?;
+            Ok(val)
+        });
+        if res.is_err() {
+            // `buf_index` is definitely unqueued now, so use it next.
+            self.free_buffers.push_front(buf_index);
+        }
+        res
+    }
+
+    /// Blocks until a buffer can be enqueued or a device event is pending.
     ///
-    /// Normally, this method does not need to be used, as only the used portion of the buffer is
-    /// needed.
-    #[inline]
-    pub fn raw_buffer(&self) -> &'a [u8] {
-        self.data
+    /// Events have to be subscribed to with [`Events::subscribe`] to be signaled.
+    pub fn wait(&self) -> io::Result<Readiness> {
+        self.wait_impl(None)
+    }
+
+    /// Waits up to `timeout` for a free buffer or a device event.
+    ///
+    /// Returns an empty [`Readiness`] if the timeout expired.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Readiness> {
+        self.wait_impl(Some(timeout))
+    }
+
+    fn wait_impl(&self, timeout: Option<Duration>) -> io::Result<Readiness> {
+        let fd = self.file.as_raw_fd();
+        if self.free_buffers.is_empty() {
+            wait_readiness(fd, PollFlags::POLLOUT, timeout)
+        } else {
+            // A buffer is available without waiting for the driver, so only check for events.
+            let readiness = wait_readiness(fd, PollFlags::empty(), Some(Duration::ZERO))?;
+            Ok(readiness | Readiness::BUFFER)
+        }
+    }
+
+    /// Returns the event queue of the device.
+    pub fn events(&self) -> Events<'_> {
+        Events::new(self.file.as_fd())
+    }
+
+    /// Allocates `count` additional buffers for `format`, and returns the range of their indices.
+    ///
+    /// `format` has to be the [`Format`] variant matching the stream, and only determines the size
+    /// of the new buffers. The new buffers are used by [`WriteStream::enqueue`] once the existing
+    /// free buffers are used up.
+    ///
+    /// The driver may allocate fewer buffers than requested. Only streams using driver-allocated
+    /// (`mmap`) buffers can create buffers, other streams will return an `EINVAL` error.
+    /// [`WriteStream::reconfigure`] replaces all buffers with buffers for the new format.
+    pub fn create_buffers(&mut self, count: u32, format: Format) -> io::Result<Range<u32>> {
+        let indices = self.buffers.create(self.file.as_raw_fd(), count, format)?;
+        self.free_buffers
+            .extend(indices.clone().map(|i| i as usize));
+        self.queued_at
+            .resize(self.buffers.buffers.len(), Instant::now());
+        Ok(indices)
+    }
+
+    /// Removes the buffers in the `indices` range from the stream, freeing their memory.
+    ///
+    /// Returns an `EBUSY` error if any of the buffers is enqueued. Buffers the driver is done with
+    /// can be reclaimed with [`WriteStream::completed_buffers`] first.
+    ///
+    /// This requires driver-allocated (`mmap`) buffers, and a queue with the
+    /// [`BufCap::SUPPORTS_REMOVE_BUFS`] capability (Linux 6.10 or newer).
+    pub fn remove_buffers(&mut self, indices: Range<u32>) -> io::Result<()> {
+        self.buffers
+            .remove(self.file.as_raw_fd(), indices.clone())?;
+        self.free_buffers
+            .retain(|&i| !indices.contains(&(i as u32)));
+        Ok(())
+    }
+
+    /// Returns an iterator over the buffers of this stream, in index order.
+    pub fn buffers(&self) -> impl Iterator<Item = BufferInfo<'_>> {
+        self.buffers.iter().map(BufferInfo::new)
+    }
+
+    /// Exports a buffer of this stream as a DMA-BUF file descriptor.
+    ///
+    /// `plane` selects the plane to export, and must be 0 for single-planar streams.
+    ///
+    /// The returned file descriptor refers to the same memory the driver outputs from, so it can be
+    /// passed to another process or device to fill the buffer without copying. Only streams using
+    /// driver-allocated (`mmap`) buffers can be exported, other streams will return an `EINVAL`
+    /// error.
+    pub fn export_buffer(&self, index: u32, plane: u32) -> io::Result<OwnedFd> {
+        self.buffers.export(self.file.as_raw_fd(), index, plane)
+    }
+
+    /// Exports every plane of every buffer of this stream as DMA-BUF file descriptors.
+    ///
+    /// The returned list is ordered by buffer index. See [`WriteStream::export_buffer`] for
+    /// details.
+    pub fn export_buffers(&self) -> io::Result<Vec<ExportedBuffer>> {
+        self.buffers.export_all(self.file.as_raw_fd())
+    }
+
+    /// Turns this stream into an [`AsyncWriteStream`] that can be used with any async executor.
+    ///
+    /// This puts the device file into non-blocking mode. Waiting tasks are woken by a background
+    /// thread shared by all asynchronous streams.
+    pub fn into_async(self) -> io::Result<AsyncWriteStream<'a>> {
+        AsyncWriteStream::new(self)
+    }
+
+    /// Turns this stream into a [`TokioWriteStream`] driven by the tokio reactor.
+    ///
+    /// This puts the device file into non-blocking mode. It must be called from within a tokio
+    /// runtime.
+    #[cfg(feature = "tokio")]
+    pub fn into_tokio(self) -> io::Result<TokioWriteStream<'a>> {
+        TokioWriteStream::new(self)
     }
 }
 
-impl Deref for ReadBufferView<'_> {
-    type Target = [u8];
+impl Drop for WriteStream<'_> {
+    fn drop(&mut self) {
+        // Turn off the stream to make the driver release all buffers.
+        // This must be done before `Buffers` can be dropped safely, at least for userptr I/O.
+        self.stop().ok();
+    }
+}
 
+impl AsRawFd for WriteStream<'_> {
     #[inline]
-    fn deref(&self) -> &Self::Target {
-        &self.data[..self.bytesused]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
     }
 }
 
-/// A stream that writes to a V4L2 device.
-pub struct WriteStream {
-    file: File,
-    buffers: Buffers,
-    next_unqueued_buffer: Option<usize>,
-    buf_type: BufType,
-    mem_type: Memory,
+/// A stream that passes frames through a memory-to-memory device.
+///
+/// Consists of a [`WriteStream`] that passes frames to the device, and a [`ReadStream`] that
+/// receives the processed frames. Both streams operate on the same open file description, which
+/// holds the processing context of the device. This also means that they share the file status
+/// flags, so turning one of them into an async stream affects the other.
+pub struct M2mStream<'a> {
+    output: WriteStream<'a>,
+    capture: ReadStream<'a>,
 }
 
-impl WriteStream {
+impl<'a> M2mStream<'a> {
     pub(crate) fn new(
         file: File,
-        buf_type: BufType,
-        mem_type: Memory,
-        buffer_count: u32,
+        output_type: BufType,
+        output: StreamBuilder<'a>,
+        capture_type: BufType,
+        capture: StreamBuilder<'a>,
     ) -> io::Result<Self> {
-        let fd = file.as_raw_fd();
-        let buffers = Buffers::allocate(fd, buf_type, mem_type, buffer_count)?;
-
-        Ok(Self {
-            file,
-            buffers,
-            next_unqueued_buffer: Some(0),
-            buf_type,
-            mem_type,
-        })
-    }
-
-    fn enqueue_buffer(&mut self, index: u32) -> io::Result<()> {
-        let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-        buf.type_ = self.buf_type;
-        buf.memory = self.mem_type;
-        buf.index = index;
-
-        unsafe {
-            raw::qbuf(self.file.as_raw_fd(), &mut buf)?;
-        }
-
-        self.buffers.buffers[index as usize].queued = true;
-
-        Ok(())
+        // `dup` the file descriptor, so that each stream can own one. The duplicate refers to the
+        // same open file description, and thus to the same processing context.
+        let capture_file = file.try_clone()?;
+        let output = WriteStream::new(file, output_type, output)?;
+        let capture = ReadStream::new(capture_file, capture_type, capture)?;
+        Ok(Self { output, capture })
     }
 
-    /// Passes a non-queued buffer to `cb` to fill it with data, then enqueues it for outputting.
+    /// Passes one frame through the device.
     ///
-    /// If no unqueued buffer is available, one is dequeued first (which may block until one is
-    /// available).
-    pub fn enqueue<T>(
+    /// This calls `fill` with a free output buffer to fill with the input frame, enqueues it, then
+    /// waits for the device to return the processed frame and passes it to `read`.
+    ///
+    /// This only works with devices that produce exactly one capture frame for every output
+    /// frame (like scalers and color converters). Use [`M2mStream::output`] and
+    /// [`M2mStream::capture`] to drive the queues independently.
+    pub fn process<T>(
         &mut self,
-        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+        fill: impl FnOnce(WriteBufferView<'_>) -> io::Result<()>,
+        read: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
     ) -> io::Result<T> {
-        let buf_index = match self.next_unqueued_buffer {
-            Some(i) => i,
-            None => {
-                // All buffers are enqueued with the driver. Dequeue one.
-                let mut buf: raw::Buffer = unsafe { mem::zeroed() };
-                buf.type_ = self.buf_type;
-                buf.memory = self.mem_type;
+        self.output.enqueue(fill)?;
+        self.capture.dequeue(read)
+    }
 
-                unsafe {
-                    raw::dqbuf(self.file.as_raw_fd(), &mut buf)?;
-                }
+    /// Returns the stream that passes frames to the device.
+    #[inline]
+    pub fn output(&mut self) -> &mut WriteStream<'a> {
+        &mut self.output
+    }
 
-                let buf_index = buf.index as usize;
-                self.buffers.buffers[buf_index].queued = false;
-                buf_index
-            }
-        };
+    /// Returns the stream that receives processed frames from the device.
+    #[inline]
+    pub fn capture(&mut self) -> &mut ReadStream<'a> {
+        &mut self.capture
+    }
 
-        let buffer = &mut self.buffers.buffers[buf_index];
-        assert!(!buffer.queued);
-
-        let data =
-            unsafe { slice::from_raw_parts_mut(buffer.ptr as *mut u8, buffer.length as usize) };
-        let view = WriteBufferView { data };
-        match cb(view) {
-            Ok(val) => match self.enqueue_buffer(buf_index as u32) {
-                Ok(()) => {
-                    match self.next_unqueued_buffer {
-                        Some(i) => {
-                            if i + 1 == self.buffers.buffers.len() {
-                                // Out of buffers we know are unqueued.
-                                self.next_unqueued_buffer = None;
-                            } else {
-                                // Next buffer was never enqueued, so use that next.
-                                self.next_unqueued_buffer = Some(i + 1);
-                            }
-                        }
-                        None => {
-                            // Do nothing, next call will dequeue.
-                        }
-                    }
-                    Ok(val)
-                }
-                Err(e) => {
-                    // `buf_index` is definitely unqueued now.
-                    self.next_unqueued_buffer = Some(buf_index);
-                    Err(e)
-                }
-            },
-            Err(e) => {
-                // `buf_index` is definitely unqueued now.
-                self.next_unqueued_buffer = Some(buf_index);
-                Err(e)
-            }
-        }
+    /// Returns the event queue of the device.
+    ///
+    /// Both streams share the same event queue.
+    pub fn events(&self) -> Events<'_> {
+        self.capture.events()
+    }
+
+    /// Splits this into the output and capture stream.
+    ///
+    /// This allows driving them from different threads.
+    pub fn into_parts(self) -> (WriteStream<'a>, ReadStream<'a>) {
+        (self.output, self.capture)
     }
 }
 
 /// Mutable view into an unqueued write buffer.
 ///
-/// Dereferences to a byte slice.
+/// Dereferences to a byte slice covering the first plane. Multi-planar buffers can be accessed
+/// with [`WriteBufferView::plane_mut`].
 pub struct WriteBufferView<'a> {
-    data: &'a mut [u8],
+    index: u32,
+    planes: [&'a mut [u8]; raw::VIDEO_MAX_PLANES],
+    num_planes: usize,
+    meta: &'a mut OutputMeta,
+}
+
+/// Buffer metadata set through a [`WriteBufferView`].
+struct OutputMeta {
+    /// The size of each plane, which can't be taken from the views of unmapped DMA-BUFs.
+    sizes: [u32; raw::VIDEO_MAX_PLANES],
+    bytesused: [u32; raw::VIDEO_MAX_PLANES],
+    timestamp: Option<Duration>,
+    field: Field,
+}
+
+impl WriteBufferView<'_> {
+    /// Returns the index of this buffer in the stream.
+    ///
+    /// For streams using application-provided buffers, this is the index of the buffer in the list
+    /// passed when creating the stream.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the number of planes in this buffer.
+    ///
+    /// This is always 1 for single-planar buffers.
+    #[inline]
+    pub fn num_planes(&self) -> usize {
+        self.num_planes
+    }
+
+    /// Returns a reference to plane `index`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `index` is not less than [`WriteBufferView::num_planes`].
+    pub fn plane(&self, index: usize) -> &[u8] {
+        self.planes[..self.num_planes][index]
+    }
+
+    /// Returns a mutable reference to plane `index`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `index` is not less than [`WriteBufferView::num_planes`].
+    pub fn plane_mut(&mut self, index: usize) -> &mut [u8] {
+        self.planes[..self.num_planes][index]
+    }
+
+    /// Sets the number of bytes at the start of the first plane that contain data.
+    ///
+    /// By default, the whole buffer is used. This has to be set when outputting variable-size
+    /// data, like compressed (MJPEG) frames.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `len` exceeds the size of the plane.
+    #[inline]
+    pub fn set_bytesused(&mut self, len: usize) {
+        self.set_plane_bytesused(0, len);
+    }
+
+    /// Sets the number of bytes at the start of plane `index` that contain data.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if `index` is not less than [`WriteBufferView::num_planes`], or if `len`
+    /// exceeds the size of the plane.
+    pub fn set_plane_bytesused(&mut self, index: usize, len: usize) {
+        let size = self.meta.sizes[..self.num_planes][index] as usize;
+        assert!(
+            len <= size,
+            "`bytesused` of {len} exceeds plane size of {size} bytes"
+        );
+        self.meta.bytesused[index] = len as u32;
+    }
+
+    /// Sets the timestamp of this buffer.
+    ///
+    /// The timestamp is passed on to the consumer of the output, for example the capture side of
+    /// a loopback or memory-to-memory device, where it is reported with
+    /// [`TimestampType::Copy`]. This can be used as a presentation timestamp.
+    #[inline]
+    pub fn set_timestamp(&mut self, timestamp: Duration) {
+        self.meta.timestamp = Some(timestamp);
+    }
+
+    /// Sets the field(s) contained in this buffer.
+    ///
+    /// Defaults to [`Field::ANY`], which lets the driver assume the field order of the format.
+    #[inline]
+    pub fn set_field(&mut self, field: Field) {
+        self.meta.field = field;
+    }
 }
 
 impl Deref for WriteBufferView<'_> {
@@ -442,14 +2724,165 @@ impl Deref for WriteBufferView<'_> {
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        self.data
+        self.plane(0)
     }
 }
 
 impl DerefMut for WriteBufferView<'_> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.data
+        self.plane_mut(0)
+    }
+}
+
+/// An output buffer that the driver has finished processing.
+///
+/// Returned by [`WriteStream::completed_buffers`].
+#[derive(Debug, Clone)]
+pub struct CompletedBuffer {
+    index: u32,
+    flags: BufFlag,
+    sequence: u32,
+    timestamp: Duration,
+    latency: Duration,
+}
+
+impl CompletedBuffer {
+    /// Returns the index of the buffer in the stream.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns whether the driver reported an error while outputting the buffer.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.flags.contains(BufFlag::ERROR)
+    }
+
+    /// Returns the sequence number the driver assigned to the buffer.
+    #[inline]
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the timestamp of the buffer.
+    ///
+    /// This is the timestamp set with [`WriteBufferView::set_timestamp`], unless the driver
+    /// overwrote it.
+    #[inline]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Returns the time between enqueuing the buffer and it being returned by the driver.
+    ///
+    /// Buffers are only dequeued when the stream needs a free buffer, or when
+    /// [`WriteStream::completed_buffers`] is called, so this is an upper bound of the actual
+    /// latency.
+    #[inline]
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+}
+
+/// Describes a buffer of a stream.
+///
+/// Returned by [`ReadStream::buffers`] and [`WriteStream::buffers`].
+pub struct BufferInfo<'a> {
+    index: u32,
+    buffer: &'a Buffer,
+}
+
+impl<'a> BufferInfo<'a> {
+    fn new((index, buffer): (u32, &'a Buffer)) -> Self {
+        Self { index, buffer }
+    }
+
+    /// Returns the index of the buffer in its stream.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the number of planes of the buffer.
+    ///
+    /// Single-planar streams have exactly one plane.
+    #[inline]
+    pub fn num_planes(&self) -> usize {
+        self.buffer.planes.len()
+    }
+
+    /// Returns the size of a plane of the buffer in bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not smaller than [`BufferInfo::num_planes`].
+    pub fn plane_size(&self, index: usize) -> usize {
+        self.buffer.planes[index].length as usize
+    }
+
+    /// Returns the total size of the buffer's planes in bytes.
+    pub fn size(&self) -> usize {
+        self.buffer
+            .planes
+            .iter()
+            .map(|plane| plane.length as usize)
+            .sum()
+    }
+
+    /// Returns the format the buffer was allocated for.
+    ///
+    /// This is the stream's format, unless the buffer was added with a different format by
+    /// [`ReadStream::create_buffers`] or [`WriteStream::create_buffers`].
+    pub fn format(&self) -> Format {
+        unsafe { Format::from_raw(self.buffer.format).unwrap() }
+    }
+}
+
+impl fmt::Debug for BufferInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plane_sizes = (0..self.num_planes())
+            .map(|i| self.plane_size(i))
+            .collect::<Vec<_>>();
+        f.debug_struct("BufferInfo")
+            .field("index", &self.index())
+            .field("plane_sizes", &plane_sizes)
+            .field("format", &self.format())
+            .finish()
+    }
+}
+
+/// A stream buffer exported as one DMA-BUF file descriptor per plane.
+///
+/// Returned by [`ReadStream::export_buffers`] and [`WriteStream::export_buffers`].
+#[derive(Debug)]
+pub struct ExportedBuffer {
+    index: u32,
+    planes: Vec<OwnedFd>,
+}
+
+impl ExportedBuffer {
+    /// Returns the index of the exported buffer in its stream.
+    ///
+    /// This matches the index reported by the buffer views of the stream.
+    #[inline]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the DMA-BUF file descriptors of the buffer's planes.
+    ///
+    /// Single-planar streams have exactly one plane.
+    #[inline]
+    pub fn planes(&self) -> &[OwnedFd] {
+        &self.planes
+    }
+
+    /// Returns the owned DMA-BUF file descriptors of the buffer's planes.
+    #[inline]
+    pub fn into_planes(self) -> Vec<OwnedFd> {
+        self.planes
     }
 }
 
@@ -461,9 +2894,98 @@ mod tests {
     fn stream_types_are_send_sync() {
         fn assert<T: Send + Sync>() {}
 
-        assert::<WriteStream>();
-        assert::<ReadStream>();
+        assert::<WriteStream<'_>>();
+        assert::<ReadStream<'_>>();
         assert::<WriteBufferView<'_>>();
         assert::<ReadBufferView<'_>>();
+        assert::<CompletedBuffer>();
+        assert::<M2mStream<'_>>();
+        assert::<HeldBuffer<'_, '_>>();
+        assert::<AsyncReadStream<'_>>();
+        assert::<AsyncWriteStream<'_>>();
+        #[cfg(feature = "tokio")]
+        {
+            assert::<TokioReadStream<'_>>();
+            assert::<TokioWriteStream<'_>>();
+        }
+    }
+
+    #[test]
+    fn wait_ready_times_out() {
+        let (rx, tx) = nix::unistd::pipe().unwrap();
+        let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(rx), OwnedFd::from_raw_fd(tx)) };
+
+        let timeout = Duration::from_millis(20);
+        let start = Instant::now();
+        assert!(!wait_ready(rx.as_raw_fd(), PollFlags::POLLIN, timeout).unwrap());
+        assert!(start.elapsed() >= timeout);
+        assert!(!wait_ready(rx.as_raw_fd(), PollFlags::POLLIN, Duration::ZERO).unwrap());
+
+        nix::unistd::write(tx.as_raw_fd(), &[0]).unwrap();
+        assert!(wait_ready(rx.as_raw_fd(), PollFlags::POLLIN, Duration::ZERO).unwrap());
+    }
+
+    #[test]
+    fn removed_buffers_leave_gaps() {
+        let mut buffers = Buffers::empty(
+            AllocType::Mmap,
+            BufType::VIDEO_CAPTURE,
+            Memory::MMAP,
+            MemoryFlags::empty(),
+        );
+        for i in 0..4 {
+            buffers.buffers.push((i != 1).then(|| Buffer {
+                planes: Vec::new(),
+                queued: AtomicBool::new(false),
+                format: unsafe { mem::zeroed() },
+                requeue: true,
+            }));
+        }
+
+        assert_eq!(buffers.count(), 3);
+        let indices = buffers.iter().map(|(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(indices, [0, 2, 3]);
+        assert!(buffers.get(1).is_err());
+        assert!(buffers.get(4).is_err());
+        assert!(buffers.get(3).is_ok());
+    }
+
+    #[test]
+    fn timestamp_flags() {
+        let flags = BufFlag::DONE | BufFlag::TIMESTAMP_MONOTONIC | BufFlag::TIMESTAMP_SRC_SOE;
+        assert_eq!(TimestampType::from_flags(flags), TimestampType::Monotonic);
+        assert_eq!(
+            TimestampSource::from_flags(flags),
+            TimestampSource::StartOfExposure
+        );
+
+        let flags = BufFlag::TIMESTAMP_COPY;
+        assert_eq!(TimestampType::from_flags(flags), TimestampType::Copy);
+        assert_eq!(
+            TimestampSource::from_flags(flags),
+            TimestampSource::EndOfFrame
+        );
+        assert_eq!(
+            TimestampType::from_flags(BufFlag::empty()),
+            TimestampType::Unknown
+        );
+    }
+
+    #[test]
+    fn timecode_display() {
+        let mut tc = Timecode(raw::Timecode {
+            type_: TimecodeType::T_30FPS,
+            flags: TimecodeFlags::empty(),
+            frames: 7,
+            seconds: 59,
+            minutes: 3,
+            hours: 1,
+            userbits: [0; 4],
+        });
+        assert_eq!(tc.frame_rate(), Some(30));
+        assert_eq!(tc.to_string(), "01:03:59:07");
+
+        tc.0.flags = TimecodeFlags::DROPFRAME;
+        assert_eq!(tc.to_string(), "01:03:59;07");
     }
 }
@@ -0,0 +1,397 @@
+//! Executor-agnostic asynchronous streams.
+//!
+//! The streams in this module put the device file into non-blocking mode. When a buffer isn't
+//! ready yet, the task is registered with a small reactor: a single background thread, shared by
+//! all streams in the process, that waits for the registered file descriptors with `poll(2)` and
+//! wakes the tasks waiting on them. This works with any executor.
+
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd::{pipe2, read, write};
+
+use super::{set_nonblocking, ReadBufferView, ReadStream, WriteBufferView, WriteStream};
+
+/// An asynchronous stream that reads data from a V4L2 device.
+///
+/// Created by [`ReadStream::into_async`].
+pub struct AsyncReadStream<'a> {
+    // NB: the registration has to be dropped before the stream closes the file descriptor.
+    registration: Registration,
+    stream: ReadStream<'a>,
+}
+
+impl<'a> AsyncReadStream<'a> {
+    pub(super) fn new(stream: ReadStream<'a>) -> io::Result<Self> {
+        set_nonblocking(stream.as_raw_fd(), true)?;
+        let registration = Registration::new(stream.as_raw_fd(), PollFlags::POLLIN)?;
+        Ok(Self {
+            registration,
+            stream,
+        })
+    }
+
+    /// Waits for a filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// This behaves like [`ReadStream::dequeue`], except that waiting for a buffer does not block
+    /// the calling thread.
+    pub async fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let (stream, registration) = (&self.stream, &self.registration);
+        let view = poll_fn(|cx| registration.poll_io(cx, || stream.dequeue_view())).await?;
+        stream.finish_dequeue(view, cb)
+    }
+
+    /// Waits for a filled buffer, then passes the most recently filled buffer to `cb`.
+    ///
+    /// This behaves like [`ReadStream::dequeue_latest`], except that waiting for a buffer does not
+    /// block the calling thread.
+    pub async fn dequeue_latest<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<(T, u32)> {
+        let (stream, registration) = (&self.stream, &self.registration);
+        let view = poll_fn(|cx| registration.poll_io(cx, || stream.dequeue_view())).await?;
+        let (view, skipped) = stream.skip_stale(view)?;
+        stream.finish_dequeue(view, cb).map(|val| (val, skipped))
+    }
+
+    /// Attempts to dequeue a filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// If no buffer is ready yet, this returns [`Poll::Pending`] without calling `cb`, and the task
+    /// in `cx` is woken once the device becomes readable.
+    pub fn poll_dequeue<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        let stream = &self.stream;
+        self.registration
+            .poll_io(cx, || stream.dequeue_view())
+            .map(|res| res.and_then(|view| stream.finish_dequeue(view, cb)))
+    }
+
+    /// Returns a reference to the underlying [`ReadStream`].
+    ///
+    /// The stream is in non-blocking mode, so its methods will return a
+    /// [`WouldBlock`][io::ErrorKind::WouldBlock] error instead of blocking.
+    #[inline]
+    pub fn get_ref(&self) -> &ReadStream<'a> {
+        &self.stream
+    }
+
+    /// Turns this back into a blocking [`ReadStream`].
+    pub fn into_inner(self) -> io::Result<ReadStream<'a>> {
+        let Self {
+            registration,
+            stream,
+        } = self;
+        drop(registration);
+        set_nonblocking(stream.as_raw_fd(), false)?;
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for AsyncReadStream<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// An asynchronous stream that writes data to a V4L2 device.
+///
+/// Created by [`WriteStream::into_async`].
+pub struct AsyncWriteStream<'a> {
+    // NB: the registration has to be dropped before the stream closes the file descriptor.
+    registration: Registration,
+    stream: WriteStream<'a>,
+}
+
+impl<'a> AsyncWriteStream<'a> {
+    pub(super) fn new(stream: WriteStream<'a>) -> io::Result<Self> {
+        set_nonblocking(stream.as_raw_fd(), true)?;
+        let registration = Registration::new(stream.as_raw_fd(), PollFlags::POLLOUT)?;
+        Ok(Self {
+            registration,
+            stream,
+        })
+    }
+
+    /// Waits for a free buffer, passes it to `cb` to fill it with data, then enqueues it for
+    /// outputting.
+    ///
+    /// This behaves like [`WriteStream::enqueue`], except that waiting for a buffer does not block
+    /// the calling thread.
+    pub async fn enqueue<T>(
+        &mut self,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let (stream, registration) = (&mut self.stream, &self.registration);
+        let index = poll_fn(|cx| registration.poll_io(cx, || stream.next_free_buffer())).await?;
+        stream.fill_and_enqueue(index, None, cb)
+    }
+
+    /// Attempts to get a free buffer, passes it to `cb` to fill it with data, then enqueues it
+    /// for outputting.
+    ///
+    /// If all buffers are still in use by the driver, this returns [`Poll::Pending`] without
+    /// calling `cb`, and the task in `cx` is woken once the device becomes writable.
+    pub fn poll_enqueue<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        let stream = &mut self.stream;
+        match self.registration.poll_io(cx, || stream.next_free_buffer()) {
+            Poll::Ready(Ok(index)) => Poll::Ready(stream.fill_and_enqueue(index, None, cb)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Returns a reference to the underlying [`WriteStream`].
+    #[inline]
+    pub fn get_ref(&self) -> &WriteStream<'a> {
+        &self.stream
+    }
+
+    /// Turns this back into a blocking [`WriteStream`].
+    pub fn into_inner(self) -> io::Result<WriteStream<'a>> {
+        let Self {
+            registration,
+            stream,
+        } = self;
+        drop(registration);
+        set_nonblocking(stream.as_raw_fd(), false)?;
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for AsyncWriteStream<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// Interest of a stream in one kind of readiness of its file descriptor.
+///
+/// Removes any pending interest from the reactor when dropped.
+struct Registration {
+    reactor: &'static Reactor,
+    fd: RawFd,
+    events: PollFlags,
+}
+
+impl Registration {
+    fn new(fd: RawFd, events: PollFlags) -> io::Result<Self> {
+        Ok(Self {
+            reactor: Reactor::get()?,
+            fd,
+            events,
+        })
+    }
+
+    /// Calls `io`, and registers the task in `cx` to be woken when the file descriptor becomes
+    /// ready if it fails with `EAGAIN`.
+    fn poll_io<T>(
+        &self,
+        cx: &mut Context<'_>,
+        io: impl FnOnce() -> io::Result<T>,
+    ) -> Poll<io::Result<T>> {
+        match io() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // The reactor polls level-triggered, so if the fd became ready between the call to
+                // `io` and now, the task will still be woken.
+                self.reactor.register(self.fd, self.events, cx.waker());
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.reactor.deregister(self.fd, self.events);
+    }
+}
+
+/// Background thread that waits for file descriptors to become ready and wakes the tasks
+/// interested in them.
+struct Reactor {
+    /// Tasks to wake, by file descriptor and the events they wait for.
+    ///
+    /// Every entry is removed when its task is woken, so the task has to register again if the
+    /// file descriptor still isn't ready.
+    interests: Mutex<HashMap<(RawFd, PollFlags), Waker>>,
+    /// Write end of a pipe used to make the thread pick up changes to `interests`.
+    interrupt: OwnedFd,
+}
+
+impl Reactor {
+    /// Returns the process-wide reactor, starting its thread if necessary.
+    fn get() -> io::Result<&'static Self> {
+        static REACTOR: Mutex<Option<&'static Reactor>> = Mutex::new(None);
+
+        let mut reactor = REACTOR.lock().unwrap();
+        if let Some(reactor) = *reactor {
+            return Ok(reactor);
+        }
+
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(rx), OwnedFd::from_raw_fd(tx)) };
+        let new: &'static Self = Box::leak(Box::new(Self {
+            interests: Mutex::new(HashMap::new()),
+            interrupt: tx,
+        }));
+        thread::Builder::new()
+            .name("linuxvideo-reactor".into())
+            .spawn(move || new.run(rx))?;
+
+        *reactor = Some(new);
+        Ok(new)
+    }
+
+    fn register(&self, fd: RawFd, events: PollFlags, waker: &Waker) {
+        let mut interests = self.interests.lock().unwrap();
+        match interests.get_mut(&(fd, events)) {
+            // The thread already polls for this, no need to interrupt it.
+            Some(old) if old.will_wake(waker) => return,
+            Some(old) => *old = waker.clone(),
+            None => {
+                interests.insert((fd, events), waker.clone());
+            }
+        }
+        drop(interests);
+        self.interrupt();
+    }
+
+    fn deregister(&self, fd: RawFd, events: PollFlags) {
+        if self
+            .interests
+            .lock()
+            .unwrap()
+            .remove(&(fd, events))
+            .is_some()
+        {
+            // Make the thread stop polling the fd before it gets closed.
+            self.interrupt();
+        }
+    }
+
+    fn interrupt(&self) {
+        // If the pipe is full, the thread is already going to wake up.
+        write(self.interrupt.as_raw_fd(), &[0]).ok();
+    }
+
+    fn run(&self, interrupt: OwnedFd) {
+        let mut keys = Vec::new();
+        let mut fds = Vec::new();
+        loop {
+            keys.clear();
+            keys.extend(self.interests.lock().unwrap().keys().copied());
+            fds.clear();
+            fds.push(PollFd::new(interrupt.as_raw_fd(), PollFlags::POLLIN));
+            fds.extend(keys.iter().map(|&(fd, events)| PollFd::new(fd, events)));
+
+            let res = poll(&mut fds, -1);
+            let ready = |fd: &PollFd| fd.revents().is_some_and(|ev| !ev.is_empty());
+            if ready(&fds[0]) {
+                // Drain the pipe. Changed interests are picked up at the start of the loop.
+                let mut buf = [0; 16];
+                while let Ok(1..) = read(interrupt.as_raw_fd(), &mut buf) {}
+            }
+
+            let mut interests = self.interests.lock().unwrap();
+            match res {
+                Ok(_) => {
+                    for (key, fd) in keys.iter().zip(&fds[1..]) {
+                        if ready(fd) {
+                            if let Some(waker) = interests.remove(key) {
+                                waker.wake();
+                            }
+                        }
+                    }
+                }
+                Err(Errno::EINTR) => {}
+                Err(e) => {
+                    // Wake all tasks, they will observe any error when retrying the operation.
+                    log::error!("failed to poll devices: {}", e);
+                    interests.drain().for_each(|(_, waker)| waker.wake());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(fut);
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn reactor_wakes_when_ready() {
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK).unwrap();
+        let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(rx), OwnedFd::from_raw_fd(tx)) };
+        let registration = Registration::new(rx.as_raw_fd(), PollFlags::POLLIN).unwrap();
+
+        let writer = thread::spawn(move || {
+            for byte in [1, 2] {
+                thread::sleep(Duration::from_millis(50));
+                write(tx.as_raw_fd(), &[byte]).unwrap();
+            }
+        });
+
+        for expected in [1, 2] {
+            let byte = block_on(poll_fn(|cx| {
+                registration.poll_io(cx, || {
+                    let mut buf = [0];
+                    read(rx.as_raw_fd(), &mut buf)?;
+                    Ok(buf[0])
+                })
+            }))
+            .unwrap();
+            assert_eq!(byte, expected);
+        }
+
+        writer.join().unwrap();
+    }
+}
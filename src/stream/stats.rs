@@ -0,0 +1,260 @@
+//! Capture statistics derived from dequeued buffer metadata.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use nix::time::{clock_gettime, ClockId};
+
+use super::{timeval_to_duration, TimestampType};
+use crate::raw;
+use crate::shared::BufFlag;
+
+/// Number of most recent frames that frame rate, jitter and latency are computed from.
+const WINDOW: usize = 60;
+
+/// A snapshot of the statistics of a capture stream, returned by [`ReadStream::stats`].
+///
+/// Frame rate, jitter and latency are computed from the most recently dequeued frames, while the
+/// counters cover every frame dequeued since the stream was created or the statistics were reset.
+///
+/// Frame timing is based on the buffer timestamps when the driver uses
+/// [`TimestampType::Monotonic`] timestamps, and on the time the buffers were dequeued otherwise.
+///
+/// [`ReadStream::stats`]: super::ReadStream::stats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaptureStats {
+    frames: u64,
+    dropped: u64,
+    errors: u64,
+    frame_interval: Option<Duration>,
+    jitter: Option<Duration>,
+    latency: Option<Duration>,
+}
+
+impl CaptureStats {
+    /// Returns the number of frames that were dequeued.
+    #[inline]
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the number of frames the driver dropped, as indicated by gaps in the buffer
+    /// sequence numbers.
+    ///
+    /// For [`Field::ALTERNATE`][crate::shared::Field::ALTERNATE] streams, this counts fields
+    /// rather than frames.
+    #[inline]
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns the number of dequeued buffers that the driver flagged as possibly corrupted.
+    #[inline]
+    pub fn error_frames(&self) -> u64 {
+        self.errors
+    }
+
+    /// Returns the mean interval between recent frames.
+    ///
+    /// Returns `None` until at least two frames have been dequeued. This can be compared against
+    /// the frame interval configured with
+    /// [`VideoCaptureDevice::set_frame_interval`][crate::VideoCaptureDevice::set_frame_interval].
+    #[inline]
+    pub fn frame_interval(&self) -> Option<Duration> {
+        self.frame_interval
+    }
+
+    /// Returns the measured frame rate in frames per second.
+    ///
+    /// This is the reciprocal of [`CaptureStats::frame_interval`].
+    pub fn fps(&self) -> Option<f64> {
+        self.frame_interval
+            .filter(|interval| !interval.is_zero())
+            .map(|interval| 1.0 / interval.as_secs_f64())
+    }
+
+    /// Returns the standard deviation of the intervals between recent frames.
+    ///
+    /// Returns `None` until at least two frames have been dequeued.
+    #[inline]
+    pub fn jitter(&self) -> Option<Duration> {
+        self.jitter
+    }
+
+    /// Returns the mean time between the buffer timestamp and the time the buffer was dequeued,
+    /// for recent frames.
+    ///
+    /// This includes the time a filled buffer waited for the application to dequeue it. Returns
+    /// `None` if the driver does not use [`TimestampType::Monotonic`] timestamps.
+    #[inline]
+    pub fn mean_latency(&self) -> Option<Duration> {
+        self.latency
+    }
+}
+
+struct Sample {
+    time: Duration,
+    latency: Option<Duration>,
+}
+
+/// Accumulates statistics for [`CaptureStats`].
+pub(crate) struct StatsTracker {
+    frames: u64,
+    dropped: u64,
+    errors: u64,
+    last_sequence: Option<u32>,
+    window: VecDeque<Sample>,
+}
+
+impl StatsTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            frames: 0,
+            dropped: 0,
+            errors: 0,
+            last_sequence: None,
+            window: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    /// Records a buffer that was just dequeued.
+    pub(super) fn record_dequeue(&mut self, buf: &raw::Buffer) {
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC).expect("failed to read monotonic clock");
+        self.record(
+            buf.sequence,
+            buf.flags,
+            timeval_to_duration(buf.timestamp),
+            now.into(),
+        );
+    }
+
+    /// Records a buffer that was dequeued at `now`, on the `CLOCK_MONOTONIC` clock.
+    pub(crate) fn record(
+        &mut self,
+        sequence: u32,
+        flags: BufFlag,
+        timestamp: Duration,
+        now: Duration,
+    ) {
+        self.frames += 1;
+        if flags.contains(BufFlag::ERROR) {
+            self.errors += 1;
+        }
+
+        if let Some(last) = self.last_sequence {
+            // Only count forward jumps, in case a driver resets the sequence number.
+            let gap = sequence.wrapping_sub(last);
+            if gap > 1 && gap <= u32::MAX / 2 {
+                self.dropped += u64::from(gap - 1);
+            }
+        }
+        self.last_sequence = Some(sequence);
+
+        let sample = if TimestampType::from_flags(flags) == TimestampType::Monotonic {
+            Sample {
+                time: timestamp,
+                latency: Some(now.saturating_sub(timestamp)),
+            }
+        } else {
+            Sample {
+                time: now,
+                latency: None,
+            }
+        };
+        if self.window.len() == WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(sample);
+    }
+
+    /// Forgets the previous frames when streaming restarts, since drivers reset the sequence
+    /// number and the pause would distort the frame timing.
+    pub(super) fn restart(&mut self) {
+        self.last_sequence = None;
+        self.window.clear();
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn snapshot(&self) -> CaptureStats {
+        let intervals = || {
+            self.window
+                .iter()
+                .zip(self.window.iter().skip(1))
+                .map(|(a, b)| b.time.saturating_sub(a.time).as_secs_f64())
+        };
+        let count = self.window.len().saturating_sub(1);
+        let (frame_interval, jitter) = if count == 0 {
+            (None, None)
+        } else {
+            let mean = intervals().sum::<f64>() / count as f64;
+            let variance = intervals().map(|i| (i - mean).powi(2)).sum::<f64>() / count as f64;
+            (
+                Some(Duration::from_secs_f64(mean)),
+                Some(Duration::from_secs_f64(variance.sqrt())),
+            )
+        };
+
+        let latencies = self.window.iter().filter_map(|s| s.latency);
+        let latency_count = latencies.clone().count();
+        let latency =
+            (latency_count != 0).then(|| latencies.sum::<Duration>() / latency_count as u32);
+
+        CaptureStats {
+            frames: self.frames,
+            dropped: self.dropped,
+            errors: self.errors,
+            frame_interval,
+            jitter,
+            latency,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timing_and_drops() {
+        let mut tracker = StatsTracker::new();
+        let stats = tracker.snapshot();
+        assert_eq!(stats.frames(), 0);
+        assert_eq!(stats.fps(), None);
+        assert_eq!(stats.jitter(), None);
+
+        // 25 fps with alternating 38/42 ms intervals, frame 3 dropped by the driver, frame 4
+        // flagged as corrupted.
+        let flags = BufFlag::TIMESTAMP_MONOTONIC;
+        let ms = Duration::from_millis;
+        let mut time = ms(1000);
+        for (i, seq) in [0, 1, 2, 4, 5].into_iter().enumerate() {
+            let flags = if seq == 4 {
+                flags | BufFlag::ERROR
+            } else {
+                flags
+            };
+            tracker.record(seq, flags, time, time + ms(5));
+            time += if i % 2 == 0 { ms(38) } else { ms(42) };
+        }
+
+        let stats = tracker.snapshot();
+        assert_eq!(stats.frames(), 5);
+        assert_eq!(stats.dropped_frames(), 1);
+        assert_eq!(stats.error_frames(), 1);
+        assert_eq!(stats.frame_interval(), Some(ms(40)));
+        assert!((stats.fps().unwrap() - 25.0).abs() < 1e-9);
+        assert_eq!(stats.jitter(), Some(ms(2)));
+        assert_eq!(stats.mean_latency(), Some(ms(5)));
+
+        // The sequence number restarts after streaming is restarted.
+        tracker.restart();
+        tracker.record(0, flags, time, time);
+        let stats = tracker.snapshot();
+        assert_eq!(stats.frames(), 6);
+        assert_eq!(stats.dropped_frames(), 1);
+        assert_eq!(stats.frame_interval(), None);
+    }
+}
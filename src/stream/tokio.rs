@@ -0,0 +1,144 @@
+//! Asynchronous streams integrated with the tokio reactor.
+
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use super::{set_nonblocking, ReadBufferView, ReadStream, WriteBufferView, WriteStream};
+
+/// An asynchronous stream that reads data from a V4L2 device, driven by the tokio reactor.
+///
+/// Created by [`ReadStream::into_tokio`]. Must be created and used within a tokio runtime.
+pub struct TokioReadStream<'a> {
+    inner: AsyncFd<ReadStream<'a>>,
+}
+
+impl<'a> TokioReadStream<'a> {
+    pub(super) fn new(stream: ReadStream<'a>) -> io::Result<Self> {
+        set_nonblocking(stream.as_raw_fd(), true)?;
+        Ok(Self {
+            inner: AsyncFd::with_interest(stream, Interest::READABLE)?,
+        })
+    }
+
+    /// Waits for a filled buffer, passes it to `cb`, then enqueues it again.
+    ///
+    /// This behaves like [`ReadStream::dequeue`], except that waiting for a buffer does not block
+    /// the calling thread.
+    pub async fn dequeue<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            match self.inner.get_ref().dequeue_view() {
+                Ok(view) => return self.inner.get_ref().finish_dequeue(view, cb),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits for a filled buffer, then passes the most recently filled buffer to `cb`.
+    ///
+    /// This behaves like [`ReadStream::dequeue_latest`], except that waiting for a buffer does not
+    /// block the calling thread.
+    pub async fn dequeue_latest<T>(
+        &mut self,
+        cb: impl FnOnce(ReadBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<(T, u32)> {
+        loop {
+            let mut guard = self.inner.readable().await?;
+            let stream = self.inner.get_ref();
+            match stream.dequeue_view() {
+                Ok(view) => {
+                    let (view, skipped) = stream.skip_stale(view)?;
+                    return stream.finish_dequeue(view, cb).map(|val| (val, skipped));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns a reference to the underlying [`ReadStream`].
+    ///
+    /// The stream is in non-blocking mode, so its methods will return a
+    /// [`WouldBlock`][io::ErrorKind::WouldBlock] error instead of blocking.
+    #[inline]
+    pub fn get_ref(&self) -> &ReadStream<'a> {
+        self.inner.get_ref()
+    }
+
+    /// Turns this back into a blocking [`ReadStream`].
+    pub fn into_inner(self) -> io::Result<ReadStream<'a>> {
+        let stream = self.inner.into_inner();
+        set_nonblocking(stream.as_raw_fd(), false)?;
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for TokioReadStream<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// An asynchronous stream that writes data to a V4L2 device, driven by the tokio reactor.
+///
+/// Created by [`WriteStream::into_tokio`]. Must be created and used within a tokio runtime.
+pub struct TokioWriteStream<'a> {
+    inner: AsyncFd<WriteStream<'a>>,
+}
+
+impl<'a> TokioWriteStream<'a> {
+    pub(super) fn new(stream: WriteStream<'a>) -> io::Result<Self> {
+        set_nonblocking(stream.as_raw_fd(), true)?;
+        Ok(Self {
+            inner: AsyncFd::with_interest(stream, Interest::WRITABLE)?,
+        })
+    }
+
+    /// Waits for a free buffer, passes it to `cb` to fill it with data, then enqueues it for
+    /// outputting.
+    ///
+    /// This behaves like [`WriteStream::enqueue`], except that waiting for a buffer does not block
+    /// the calling thread.
+    pub async fn enqueue<T>(
+        &mut self,
+        cb: impl FnOnce(WriteBufferView<'_>) -> io::Result<T>,
+    ) -> io::Result<T> {
+        loop {
+            let mut guard = self.inner.writable_mut().await?;
+            let stream = guard.get_inner_mut();
+            match stream.next_free_buffer() {
+                Ok(index) => return stream.fill_and_enqueue(index, None, cb),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns a reference to the underlying [`WriteStream`].
+    #[inline]
+    pub fn get_ref(&self) -> &WriteStream<'a> {
+        self.inner.get_ref()
+    }
+
+    /// Turns this back into a blocking [`WriteStream`].
+    pub fn into_inner(self) -> io::Result<WriteStream<'a>> {
+        let stream = self.inner.into_inner();
+        set_nonblocking(stream.as_raw_fd(), false)?;
+        Ok(stream)
+    }
+}
+
+impl AsRawFd for TokioWriteStream<'_> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
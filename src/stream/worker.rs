@@ -0,0 +1,577 @@
+//! Background capture thread that delivers owned frames through a bounded channel.
+
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{fmt, io, mem};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::unistd::{pipe2, write};
+
+use super::{ReadBufferView, ReadStream};
+use crate::format::Format;
+use crate::raw;
+use crate::shared::Field;
+
+/// What a [`CaptureWorker`] does with a captured frame when its channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest frame in the channel to make room for the new one.
+    ///
+    /// This keeps latency low when the consumer cannot keep up.
+    DropOldest,
+    /// Discard the newly captured frame.
+    DropNewest,
+    /// Wait until the consumer has received a frame.
+    ///
+    /// While the worker waits, the driver keeps capturing into its remaining buffers, and drops
+    /// frames itself once it runs out of them.
+    Block,
+}
+
+/// A thread that captures frames from a [`ReadStream`] and sends copies of them to the owner.
+///
+/// Created by [`ReadStream::into_worker`].
+///
+/// Frames are copied into buffers taken from a pool owned by the worker. Dropping a [`Frame`]
+/// returns its buffer to the pool, so once the pool is warmed up, capturing does not allocate as
+/// long as the consumer does not hold on to more frames than the channel can contain.
+///
+/// Errors that occur on the capture thread stop the worker and are returned by the next call to
+/// one of the `recv` methods, after all frames captured before the error have been received.
+///
+/// Dropping the worker stops the capture thread and drops the stream. [`CaptureWorker::stop`] can
+/// be used to get the stream back instead.
+pub struct CaptureWorker {
+    shared: Arc<Shared>,
+    /// Write end of a pipe used to interrupt the `poll` call of the thread.
+    interrupt: OwnedFd,
+    thread: Option<JoinHandle<ReadStream<'static>>>,
+}
+
+impl CaptureWorker {
+    pub(super) fn new(
+        stream: ReadStream<'static>,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> io::Result<Self> {
+        if capacity == 0 {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let (rx, tx) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(rx), OwnedFd::from_raw_fd(tx)) };
+
+        let shared = Arc::new(Shared::new(capacity, policy));
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("linuxvideo-capture".into())
+                .spawn(move || capture_loop(stream, rx, &shared))?
+        };
+
+        Ok(Self {
+            shared,
+            interrupt: tx,
+            thread: Some(thread),
+        })
+    }
+
+    /// Blocks until a frame is available and returns it.
+    ///
+    /// If the capture thread has stopped because of an error, that error is returned once all
+    /// remaining frames have been received. After that, this returns a `BrokenPipe` error.
+    pub fn recv(&self) -> io::Result<Frame> {
+        self.shared
+            .recv(None)
+            .map(|frame| frame.expect("no timeout"))
+    }
+
+    /// Returns a frame if one is available without blocking.
+    ///
+    /// Errors are returned like in [`CaptureWorker::recv`].
+    pub fn try_recv(&self) -> io::Result<Option<Frame>> {
+        self.shared.recv(Some(Duration::ZERO))
+    }
+
+    /// Waits up to `timeout` for a frame to become available.
+    ///
+    /// Returns `Ok(None)` if no frame was captured before the timeout expired. Errors are returned
+    /// like in [`CaptureWorker::recv`].
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<Frame>> {
+        self.shared.recv(Some(timeout))
+    }
+
+    /// Returns the number of frames that were discarded because the channel was full.
+    ///
+    /// This does not include frames dropped by the driver, which can be detected from gaps in
+    /// [`Frame::sequence`].
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Returns whether the capture thread is still running.
+    pub fn is_running(&self) -> bool {
+        self.shared.lock().running
+    }
+
+    /// Stops the capture thread and returns the stream.
+    ///
+    /// Frames that have not been received yet are discarded. The stream is still streaming, and
+    /// can be used directly or turned into a new worker.
+    pub fn stop(mut self) -> io::Result<ReadStream<'static>> {
+        self.shutdown()
+            .expect("capture thread already joined")
+            .map_err(|_| io::Error::other("capture thread panicked"))
+    }
+
+    fn shutdown(&mut self) -> Option<thread::Result<ReadStream<'static>>> {
+        {
+            let mut state = self.shared.lock();
+            state.shutdown = true;
+            state.frames.clear();
+        }
+        self.shared.space_available.notify_all();
+        write(self.interrupt.as_raw_fd(), &[0]).ok();
+        self.thread.take().map(JoinHandle::join)
+    }
+}
+
+impl Drop for CaptureWorker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for CaptureWorker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.lock();
+        f.debug_struct("CaptureWorker")
+            .field("capacity", &self.shared.capacity)
+            .field("policy", &self.shared.policy)
+            .field("queued", &state.frames.len())
+            .field("dropped", &state.dropped)
+            .field("running", &state.running)
+            .finish()
+    }
+}
+
+/// An owned copy of a captured frame, received from a [`CaptureWorker`].
+///
+/// Dereferences to the contents of the first plane, like [`ReadBufferView`].
+pub struct Frame {
+    data: Vec<u8>,
+    /// End offset of each plane in `data`.
+    plane_ends: [usize; raw::VIDEO_MAX_PLANES],
+    num_planes: usize,
+    format: raw::Format,
+    timestamp: Duration,
+    sequence: u32,
+    field: Field,
+    is_error: bool,
+    pool: Arc<Pool>,
+}
+
+// The raw format only contains pointers for overlay formats (clip lists), and they are never
+// dereferenced.
+unsafe impl Send for Frame {}
+unsafe impl Sync for Frame {}
+
+impl Frame {
+    fn copy_from(view: &ReadBufferView<'_>, format: raw::Format, pool: &Arc<Pool>) -> Self {
+        let mut data = pool.take();
+        let mut plane_ends = [0; raw::VIDEO_MAX_PLANES];
+        for (end, plane) in plane_ends.iter_mut().zip(view.planes()) {
+            data.extend_from_slice(plane);
+            *end = data.len();
+        }
+
+        Self {
+            data,
+            plane_ends,
+            num_planes: view.num_planes(),
+            format,
+            timestamp: view.timestamp(),
+            sequence: view.sequence(),
+            field: view.field(),
+            is_error: view.is_error(),
+            pool: pool.clone(),
+        }
+    }
+
+    /// Returns the format of the stream the frame was captured from.
+    ///
+    /// This is the format the capture buffer was allocated for, which can differ from the stream's
+    /// current format if buffers were added with [`ReadStream::create_buffers`].
+    pub fn format(&self) -> Format {
+        unsafe { Format::from_raw(self.format).unwrap() }
+    }
+
+    /// Returns the time at which the frame was captured, as reported by the driver.
+    ///
+    /// See [`ReadBufferView::timestamp`].
+    #[inline]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Returns the frame's sequence number, as assigned by the driver.
+    ///
+    /// Gaps in the sequence numbers indicate that the driver dropped frames.
+    #[inline]
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Returns the field order of the frame.
+    #[inline]
+    pub fn field(&self) -> Field {
+        self.field
+    }
+
+    /// Returns whether the driver flagged the frame as possibly corrupted.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.is_error
+    }
+
+    /// Returns the number of planes of the frame.
+    #[inline]
+    pub fn num_planes(&self) -> usize {
+        self.num_planes
+    }
+
+    /// Returns the contents of a plane.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not smaller than [`Frame::num_planes`].
+    pub fn plane(&self, index: usize) -> &[u8] {
+        assert!(index < self.num_planes, "plane index out of range");
+        let start = match index {
+            0 => 0,
+            _ => self.plane_ends[index - 1],
+        };
+        &self.data[start..self.plane_ends[index]]
+    }
+
+    /// Returns an iterator over the contents of all planes.
+    pub fn planes(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.num_planes).map(|i| self.plane(i))
+    }
+
+    /// Returns the contents of all planes, concatenated.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        self.plane(0)
+    }
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        self.pool.put(mem::take(&mut self.data));
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("sequence", &self.sequence)
+            .field("timestamp", &self.timestamp)
+            .field("field", &self.field)
+            .field("is_error", &self.is_error)
+            .field("num_planes", &self.num_planes)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+/// Recycles frame buffers, so that their allocations are reused.
+struct Pool {
+    free: Mutex<Vec<Vec<u8>>>,
+    /// Maximum number of buffers kept in the pool. Buffers returned beyond that are freed.
+    capacity: usize,
+}
+
+impl Pool {
+    fn new(capacity: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    fn take(&self) -> Vec<u8> {
+        self.free.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    fn put(&self, mut buf: Vec<u8>) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.capacity {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+struct State {
+    frames: VecDeque<Frame>,
+    /// The error that stopped the capture thread, until it is received.
+    error: Option<io::Error>,
+    dropped: u64,
+    running: bool,
+    shutdown: bool,
+}
+
+/// The channel between the capture thread and the [`CaptureWorker`].
+struct Shared {
+    state: Mutex<State>,
+    /// Signaled when a frame is sent or the capture thread exits.
+    frame_available: Condvar,
+    /// Signaled when a frame is received or shutdown is requested.
+    space_available: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Shared {
+    fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                frames: VecDeque::with_capacity(capacity),
+                error: None,
+                dropped: 0,
+                running: true,
+                shutdown: false,
+            }),
+            frame_available: Condvar::new(),
+            space_available: Condvar::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Sends a frame to the receiver, applying the overflow policy if the channel is full.
+    ///
+    /// Returns the frame that was discarded, if any. It is returned rather than dropped, so that
+    /// it is returned to the pool after the channel is unlocked.
+    fn send(&self, frame: Frame) -> Option<Frame> {
+        let mut state = self.lock();
+        let mut discarded = None;
+        if state.frames.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    discarded = state.frames.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return Some(frame);
+                }
+                OverflowPolicy::Block => {
+                    while state.frames.len() >= self.capacity && !state.shutdown {
+                        state = self.space_available.wait(state).unwrap();
+                    }
+                }
+            }
+        }
+        if state.shutdown {
+            return Some(frame);
+        }
+
+        state.frames.push_back(frame);
+        self.frame_available.notify_one();
+        discarded
+    }
+
+    fn recv(&self, timeout: Option<Duration>) -> io::Result<Option<Frame>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.lock();
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                self.space_available.notify_one();
+                return Ok(Some(frame));
+            }
+            if let Some(e) = state.error.take() {
+                return Err(e);
+            }
+            if !state.running {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "capture thread has stopped",
+                ));
+            }
+
+            match deadline {
+                None => state = self.frame_available.wait(state).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+                    state = self
+                        .frame_available
+                        .wait_timeout(state, remaining)
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+
+    fn fail(&self, error: io::Error) {
+        let mut state = self.lock();
+        if !state.shutdown {
+            state.error = Some(error);
+        }
+    }
+}
+
+/// Marks the capture thread as stopped when dropped, even if it panics.
+struct ExitGuard<'a>(&'a Shared);
+
+impl Drop for ExitGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock().running = false;
+        self.0.frame_available.notify_all();
+    }
+}
+
+fn capture_loop(
+    stream: ReadStream<'static>,
+    interrupt: OwnedFd,
+    shared: &Shared,
+) -> ReadStream<'static> {
+    let _guard = ExitGuard(shared);
+
+    // Enough buffers for a full channel, plus the frame being captured and one held by the
+    // consumer.
+    let pool = Arc::new(Pool::new(shared.capacity + 2));
+
+    loop {
+        let mut fds = [
+            PollFd::new(stream.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(interrupt.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        match poll(&mut fds, -1) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => {
+                shared.fail(e.into());
+                break;
+            }
+        }
+
+        if fds[1].revents().is_some_and(|ev| !ev.is_empty()) {
+            // Only written to on shutdown.
+            break;
+        }
+
+        // If the stream signals an error condition, the next dequeue will report it.
+        let frame = stream.dequeue_view().and_then(|view| {
+            let format = stream.buffers.buffer(view.index).format;
+            stream.finish_dequeue(view, |view| Ok(Frame::copy_from(&view, format, &pool)))
+        });
+        match frame {
+            Ok(frame) => drop(shared.send(frame)),
+            Err(e) => {
+                shared.fail(e);
+                break;
+            }
+        }
+
+        if shared.lock().shutdown {
+            break;
+        }
+    }
+
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pool: &Arc<Pool>, sequence: u32) -> Frame {
+        let mut data = pool.take();
+        data.extend_from_slice(&sequence.to_ne_bytes());
+        let mut plane_ends = [0; raw::VIDEO_MAX_PLANES];
+        plane_ends[0] = data.len();
+        Frame {
+            data,
+            plane_ends,
+            num_planes: 1,
+            format: unsafe { mem::zeroed() },
+            timestamp: Duration::ZERO,
+            sequence,
+            field: Field::NONE,
+            is_error: false,
+            pool: pool.clone(),
+        }
+    }
+
+    fn received(shared: &Shared) -> Vec<u32> {
+        let mut sequences = Vec::new();
+        while let Ok(Some(frame)) = shared.recv(Some(Duration::ZERO)) {
+            sequences.push(frame.sequence());
+        }
+        sequences
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let pool = Arc::new(Pool::new(4));
+
+        let shared = Shared::new(2, OverflowPolicy::DropOldest);
+        for seq in 0..4 {
+            drop(shared.send(frame(&pool, seq)));
+        }
+        assert_eq!(shared.lock().dropped, 2);
+        assert_eq!(received(&shared), [2, 3]);
+
+        let shared = Shared::new(2, OverflowPolicy::DropNewest);
+        for seq in 0..4 {
+            drop(shared.send(frame(&pool, seq)));
+        }
+        assert_eq!(shared.lock().dropped, 2);
+        assert_eq!(received(&shared), [0, 1]);
+
+        // Buffers are returned to the pool and reused, so only as many buffers were allocated as
+        // frames were alive at the same time: a full channel, plus the frame being sent.
+        assert_eq!(pool.free.lock().unwrap().len(), 3);
+        assert!(pool.free.lock().unwrap().iter().all(|buf| buf.is_empty()));
+    }
+
+    #[test]
+    fn error_after_frames() {
+        let pool = Arc::new(Pool::new(4));
+        let shared = Shared::new(2, OverflowPolicy::Block);
+        drop(shared.send(frame(&pool, 0)));
+        shared.fail(Errno::ENODEV.into());
+        drop(ExitGuard(&shared));
+
+        assert_eq!(shared.recv(None).unwrap().unwrap().sequence(), 0);
+        let e = shared.recv(None).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(Errno::ENODEV as i32));
+        let e = shared.recv(None).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,741 @@
+//! Device enumeration through sysfs.
+//!
+//! Unlike [`crate::list`], this does not open any device nodes. It only reads
+//! `/sys/class/video4linux`, so it doesn't wake up suspended devices and works for nodes that are
+//! busy in other processes. The returned [`NodeDesc`]s can be opened later with
+//! [`NodeDesc::open`].
+//!
+//! [`physical_devices`] groups the nodes by the hardware device they belong to, so that, for
+//! example, the metadata node of a UVC camera can be found from its video node. Since sysfs doesn't
+//! say what a video node is used for, this does open the video nodes.
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use nix::errno::Errno;
+
+use crate::media::MediaDevice;
+use crate::{Capabilities, CapabilityFlags, Device};
+
+/// Lists the V4L2 device nodes registered in `/sys/class/video4linux`.
+///
+/// The nodes are ordered by their device number.
+pub fn list() -> io::Result<Vec<NodeDesc>> {
+    Enumerator::new().nodes()
+}
+
+/// Lists the physical devices that have V4L2 or media controller nodes.
+///
+/// See [`Enumerator::physical_devices`] for details.
+pub fn physical_devices() -> io::Result<Vec<PhysicalDevice>> {
+    Enumerator::new().physical_devices()
+}
+
+/// Enumerates V4L2 device nodes through sysfs.
+///
+/// By default, sysfs is expected to be mounted at `/sys`, and device nodes to be in `/dev`. Both
+/// can be changed, for example to read a copy of a sysfs tree in tests.
+#[derive(Debug, Clone)]
+pub struct Enumerator {
+    sysfs: PathBuf,
+    dev: PathBuf,
+    /// Queries the capabilities of a video node, to determine its [`NodeRole`].
+    probe: fn(&Path) -> io::Result<Capabilities>,
+}
+
+impl Enumerator {
+    /// Creates an enumerator that reads `/sys` and `/dev`.
+    pub fn new() -> Self {
+        Self {
+            sysfs: PathBuf::from("/sys"),
+            dev: PathBuf::from("/dev"),
+            probe: |path| Device::open(path)?.capabilities(),
+        }
+    }
+
+    /// Sets the directory sysfs is mounted at.
+    pub fn sysfs_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.sysfs = root.into();
+        self
+    }
+
+    /// Sets the directory containing the device nodes.
+    pub fn dev_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.dev = root.into();
+        self
+    }
+
+    /// Lists the V4L2 device nodes, ordered by their device number.
+    ///
+    /// Nodes that disappear while they are being enumerated are skipped.
+    pub fn nodes(&self) -> io::Result<Vec<NodeDesc>> {
+        self.read_nodes(&self.sysfs.join("class/video4linux"))
+    }
+
+    /// Lists the media controller nodes (`/dev/mediaN`), ordered by their device number.
+    pub fn media_nodes(&self) -> io::Result<Vec<NodeDesc>> {
+        self.read_nodes(&self.sysfs.join("bus/media/devices"))
+    }
+
+    /// Groups the V4L2 and media controller nodes by the physical device they belong to.
+    ///
+    /// Nodes are grouped by their parent devices in sysfs: all nodes below the same USB device, or
+    /// below the same parent device (for example the sensor sub-devices of a capture card), form
+    /// one [`PhysicalDevice`]. Nodes that have no parent device, like those of `v4l2loopback`, are
+    /// grouped by their [`Capabilities::bus_info`].
+    ///
+    /// Unlike [`Enumerator::nodes`], this opens every video node to query its capabilities, which
+    /// determine the node's [`NodeRole`]. Nodes that can't be opened get the role
+    /// [`NodeRole::Other`].
+    ///
+    /// The devices are ordered by the device number of their first node.
+    pub fn physical_devices(&self) -> io::Result<Vec<PhysicalDevice>> {
+        let mut entries = Vec::new();
+        for node in self.nodes()? {
+            let (role, bus_info) = self.probe_role(&node);
+            entries.push((node, role, bus_info));
+        }
+        for node in self.media_nodes()? {
+            entries.push((node, NodeRole::Media, None));
+        }
+
+        // Parents come first, so that nodes of child devices join the group of their parent.
+        entries.sort_by_key(|(node, ..)| {
+            let depth = node.physical_path().map(|path| path.components().count());
+            (depth, node.dev)
+        });
+
+        let mut devices: Vec<PhysicalDevice> = Vec::new();
+        for (node, role, bus_info) in entries {
+            let sys_path = node.physical_path().map(Path::to_path_buf);
+            let existing = devices.iter_mut().find(|device| {
+                let below = match (&device.sys_path, &sys_path) {
+                    (Some(parent), Some(path)) => path.starts_with(parent),
+                    _ => false,
+                };
+                below || (bus_info.is_some() && device.bus_info == bus_info)
+            });
+            match existing {
+                Some(device) => {
+                    if device.bus_info.is_none() {
+                        device.bus_info = bus_info;
+                    }
+                    device.nodes.push((node, role));
+                }
+                None => devices.push(PhysicalDevice {
+                    sys_path,
+                    bus_info,
+                    usb: node.usb.clone(),
+                    nodes: vec![(node, role)],
+                }),
+            }
+        }
+
+        for device in &mut devices {
+            device.nodes.sort_by_key(|(node, _)| node.dev);
+        }
+        devices.sort_by_key(|device| device.nodes[0].0.dev);
+        Ok(devices)
+    }
+
+    /// Determines the role of a V4L2 node, and its bus info if it had to be opened for that.
+    fn probe_role(&self, node: &NodeDesc) -> (NodeRole, Option<String>) {
+        if node.name.as_bytes().starts_with(b"v4l-subdev") {
+            return (NodeRole::Subdev, None);
+        }
+
+        match (self.probe)(&node.dev_path) {
+            Ok(caps) => {
+                let bus_info = Some(caps.bus_info())
+                    .filter(|bus_info| !bus_info.is_empty())
+                    .map(String::from);
+                (
+                    NodeRole::from_capabilities(caps.device_capabilities()),
+                    bus_info,
+                )
+            }
+            Err(e) => {
+                log::debug!("cannot query {}: {}", node.dev_path.display(), e);
+                (NodeRole::Other, None)
+            }
+        }
+    }
+
+    fn read_nodes(&self, dir: &Path) -> io::Result<Vec<NodeDesc>> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            // No devices of this kind were ever registered, so the directory doesn't exist.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut nodes = Vec::new();
+        for entry in entries {
+            match self.read_node(&entry?.path()) {
+                Ok(node) => nodes.push(node),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    log::debug!("skipping node that disappeared: {}", e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        nodes.sort_by_key(|node| node.dev);
+        Ok(nodes)
+    }
+
+    fn read_node(&self, class_path: &Path) -> io::Result<NodeDesc> {
+        let sys_path = fs::canonicalize(class_path)?;
+        let name = class_path.file_name().unwrap_or_default().to_os_string();
+        // Media controller nodes have a `model` instead of a `name`.
+        let card =
+            read_attr(&sys_path.join("name")).or_else(|_| read_attr(&sys_path.join("model")))?;
+        let index = read_attr(&sys_path.join("index"))
+            .ok()
+            .and_then(|index| index.parse().ok());
+        let dev = parse_dev(&read_attr(&sys_path.join("dev"))?)?;
+        let dev_name = read_uevent_var(&sys_path.join("uevent"), "DEVNAME")
+            .map(OsString::from)
+            .unwrap_or_else(|| name.clone());
+
+        let device_path = fs::canonicalize(sys_path.join("device")).ok();
+        let (driver, module) = match &device_path {
+            Some(device) => (
+                link_name(&device.join("driver")),
+                link_name(&device.join("driver/module")),
+            ),
+            None => (None, None),
+        };
+        let usb = device_path
+            .as_deref()
+            .and_then(|device| self.find_usb_device(device));
+
+        Ok(NodeDesc {
+            dev_path: self.dev.join(dev_name),
+            name,
+            card,
+            index,
+            dev,
+            sys_path,
+            device_path,
+            driver,
+            module,
+            usb,
+        })
+    }
+
+    /// Finds the USB device a node's device belongs to, by walking up the device hierarchy.
+    fn find_usb_device(&self, device: &Path) -> Option<UsbDevice> {
+        // `device` is canonical, so the root has to be too.
+        let devices = fs::canonicalize(self.sysfs.join("devices")).ok()?;
+        device
+            .ancestors()
+            .take_while(|path| path.starts_with(&devices) && *path != devices)
+            .find(|path| path.join("idVendor").exists())
+            .and_then(|path| UsbDevice::read(path).ok())
+    }
+}
+
+impl Default for Enumerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describes a V4L2 device node found in sysfs.
+#[derive(Debug, Clone)]
+pub struct NodeDesc {
+    name: OsString,
+    card: String,
+    index: Option<u32>,
+    dev: (u32, u32),
+    dev_path: PathBuf,
+    sys_path: PathBuf,
+    device_path: Option<PathBuf>,
+    driver: Option<String>,
+    module: Option<String>,
+    usb: Option<UsbDevice>,
+}
+
+impl NodeDesc {
+    /// Returns the name of the node, like `video0` or `v4l-subdev2`.
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns the name of the device the node belongs to.
+    ///
+    /// This is usually the same as [`Capabilities::card`][crate::Capabilities::card].
+    pub fn card(&self) -> &str {
+        &self.card
+    }
+
+    /// Returns the index of the node among the nodes of the same type created by its driver.
+    ///
+    /// For example, UVC cameras typically create a video node with index 0 for the video stream,
+    /// and one with index 1 for metadata.
+    pub fn index(&self) -> Option<u32> {
+        self.index
+    }
+
+    /// Returns the major and minor device number of the node.
+    pub fn dev(&self) -> (u32, u32) {
+        self.dev
+    }
+
+    /// Returns the path of the device node, like `/dev/video0`.
+    pub fn dev_path(&self) -> &Path {
+        &self.dev_path
+    }
+
+    /// Returns the path of the node in sysfs.
+    pub fn sys_path(&self) -> &Path {
+        &self.sys_path
+    }
+
+    /// Returns the sysfs path of the device that created the node.
+    ///
+    /// For USB cameras, this is the USB interface the node belongs to.
+    pub fn device_path(&self) -> Option<&Path> {
+        self.device_path.as_deref()
+    }
+
+    /// Returns the name of the driver bound to the device, like `uvcvideo`.
+    pub fn driver(&self) -> Option<&str> {
+        self.driver.as_deref()
+    }
+
+    /// Returns the name of the kernel module providing the driver.
+    ///
+    /// This is `None` for drivers that are built into the kernel.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    /// Returns the USB device the node belongs to, if any.
+    pub fn usb_device(&self) -> Option<&UsbDevice> {
+        self.usb.as_ref()
+    }
+
+    /// Opens the device node.
+    ///
+    /// Media controller nodes have to be opened with [`NodeDesc::open_media`] instead.
+    pub fn open(&self) -> io::Result<Device> {
+        Device::open(&self.dev_path)
+    }
+
+    /// Opens the node as a media controller device.
+    pub fn open_media(&self) -> io::Result<MediaDevice> {
+        MediaDevice::open(&self.dev_path)
+    }
+
+    /// Returns the sysfs path of the physical device the node belongs to.
+    fn physical_path(&self) -> Option<&Path> {
+        match &self.usb {
+            Some(usb) => Some(&usb.sys_path),
+            None => self.device_path.as_deref(),
+        }
+    }
+}
+
+/// What a device node is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NodeRole {
+    /// A node for capturing video, with single- or multi-planar buffers.
+    VideoCapture,
+    /// A node for outputting video.
+    VideoOutput,
+    /// A memory-to-memory node, like a codec or scaler, with both a capture and an output queue.
+    Mem2Mem,
+    /// A node for capturing metadata, like the per-frame metadata of UVC cameras.
+    MetaCapture,
+    /// A node for outputting metadata, like the parameters of an ISP.
+    MetaOutput,
+    /// A V4L2 sub-device node (`/dev/v4l-subdevN`).
+    Subdev,
+    /// A media controller node (`/dev/mediaN`).
+    Media,
+    /// A radio, VBI, SDR or touch node, or a node whose capabilities could not be queried.
+    Other,
+}
+
+impl NodeRole {
+    fn from_capabilities(caps: CapabilityFlags) -> Self {
+        if caps.intersects(CapabilityFlags::VIDEO_M2M | CapabilityFlags::VIDEO_M2M_MPLANE) {
+            Self::Mem2Mem
+        } else if caps
+            .intersects(CapabilityFlags::VIDEO_CAPTURE | CapabilityFlags::VIDEO_CAPTURE_MPLANE)
+        {
+            Self::VideoCapture
+        } else if caps
+            .intersects(CapabilityFlags::VIDEO_OUTPUT | CapabilityFlags::VIDEO_OUTPUT_MPLANE)
+        {
+            Self::VideoOutput
+        } else if caps.contains(CapabilityFlags::META_CAPTURE) {
+            Self::MetaCapture
+        } else if caps.contains(CapabilityFlags::META_OUTPUT) {
+            Self::MetaOutput
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// A hardware device and the device nodes that belong to it.
+///
+/// Returned by [`physical_devices`] and [`Enumerator::physical_devices`].
+#[derive(Debug, Clone)]
+pub struct PhysicalDevice {
+    sys_path: Option<PathBuf>,
+    bus_info: Option<String>,
+    usb: Option<UsbDevice>,
+    nodes: Vec<(NodeDesc, NodeRole)>,
+}
+
+impl PhysicalDevice {
+    /// Returns the sysfs path of the device.
+    ///
+    /// This is the USB device for USB cameras, and `None` for virtual devices like
+    /// `v4l2loopback`.
+    pub fn sys_path(&self) -> Option<&Path> {
+        self.sys_path.as_deref()
+    }
+
+    /// Returns the bus info reported by the device's video nodes.
+    ///
+    /// This is `None` if none of the nodes could be opened.
+    pub fn bus_info(&self) -> Option<&str> {
+        self.bus_info.as_deref()
+    }
+
+    /// Returns the USB device, if this is a USB device.
+    pub fn usb_device(&self) -> Option<&UsbDevice> {
+        self.usb.as_ref()
+    }
+
+    /// Returns the card name of the device.
+    ///
+    /// This is taken from the first video node, or from the first node if there is none.
+    pub fn card(&self) -> &str {
+        let is_video = |role: &NodeRole| !matches!(role, NodeRole::Subdev | NodeRole::Media);
+        self.nodes
+            .iter()
+            .find(|(_, role)| is_video(role))
+            .unwrap_or(&self.nodes[0])
+            .0
+            .card()
+    }
+
+    /// Returns the nodes of the device and their roles, ordered by device number.
+    pub fn nodes(&self) -> &[(NodeDesc, NodeRole)] {
+        &self.nodes
+    }
+
+    /// Returns the first node with the given role.
+    pub fn node(&self, role: NodeRole) -> Option<&NodeDesc> {
+        self.nodes
+            .iter()
+            .find(|(_, r)| *r == role)
+            .map(|(node, _)| node)
+    }
+
+    /// Opens the first node with the given role.
+    ///
+    /// Returns an `ENODEV` error if the device has no such node. Media controller nodes can't be
+    /// opened as a [`Device`]; use [`NodeDesc::open_media`] on the node returned by
+    /// [`PhysicalDevice::node`] instead.
+    pub fn open(&self, role: NodeRole) -> io::Result<Device> {
+        self.node(role).ok_or(Errno::ENODEV)?.open()
+    }
+}
+
+/// Information about a USB device, read from sysfs.
+#[derive(Debug, Clone)]
+pub struct UsbDevice {
+    sys_path: PathBuf,
+    vendor_id: u16,
+    product_id: u16,
+    manufacturer: Option<String>,
+    product: Option<String>,
+    serial: Option<String>,
+    bus_num: u32,
+    dev_num: u32,
+}
+
+impl UsbDevice {
+    fn read(path: &Path) -> io::Result<Self> {
+        let hex = |attr| {
+            u16::from_str_radix(&read_attr(&path.join(attr))?, 16)
+                .map_err(|_| io::Error::from(Errno::EINVAL))
+        };
+        let dec = |attr| {
+            read_attr(&path.join(attr))?
+                .parse()
+                .map_err(|_| io::Error::from(Errno::EINVAL))
+        };
+        Ok(Self {
+            sys_path: path.to_path_buf(),
+            vendor_id: hex("idVendor")?,
+            product_id: hex("idProduct")?,
+            manufacturer: read_attr(&path.join("manufacturer")).ok(),
+            product: read_attr(&path.join("product")).ok(),
+            serial: read_attr(&path.join("serial")).ok(),
+            bus_num: dec("busnum")?,
+            dev_num: dec("devnum")?,
+        })
+    }
+
+    /// Returns the sysfs path of the USB device.
+    pub fn sys_path(&self) -> &Path {
+        &self.sys_path
+    }
+
+    /// Returns the USB vendor ID (`idVendor`).
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// Returns the USB product ID (`idProduct`).
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// Returns the manufacturer string reported by the device.
+    pub fn manufacturer(&self) -> Option<&str> {
+        self.manufacturer.as_deref()
+    }
+
+    /// Returns the product string reported by the device.
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    /// Returns the serial number reported by the device.
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// Returns the number of the USB bus the device is connected to.
+    pub fn bus_num(&self) -> u32 {
+        self.bus_num
+    }
+
+    /// Returns the address of the device on its USB bus.
+    pub fn dev_num(&self) -> u32 {
+        self.dev_num
+    }
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor_id, self.product_id)?;
+        if let Some(product) = &self.product {
+            write!(f, " {}", product)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a sysfs attribute, without the trailing newline.
+fn read_attr(path: &Path) -> io::Result<String> {
+    let mut value = fs::read_to_string(path)?;
+    value.truncate(value.trim_end().len());
+    Ok(value)
+}
+
+/// Reads a variable from a `uevent` file.
+fn read_uevent_var(path: &Path, var: &str) -> Option<String> {
+    let uevent = fs::read_to_string(path).ok()?;
+    uevent.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key == var).then(|| value.to_string())
+    })
+}
+
+/// Returns the file name of a symlink's target.
+fn link_name(path: &Path) -> Option<String> {
+    let target = fs::read_link(path).ok()?;
+    Some(target.file_name()?.to_str()?.to_string())
+}
+
+/// Parses a `major:minor` device number.
+fn parse_dev(dev: &str) -> io::Result<(u32, u32)> {
+    let parse = || {
+        let (major, minor) = dev.split_once(':')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    };
+    parse().ok_or_else(|| Errno::EINVAL.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::mock::MockDevice;
+    use crate::test_util::TempDir;
+
+    /// Creates a sysfs tree with a UVC camera (video, metadata and media node), a platform device
+    /// and a `v4l2loopback` device.
+    fn fake_sysfs(root: &Path) -> io::Result<()> {
+        let usb = root.join("devices/pci0000:00/0000:00:14.0/usb1/1-2");
+        let intf = usb.join("1-2:1.0");
+        let attrs: &[(&Path, &str, &str)] = &[
+            (&usb, "idVendor", "046d\n"),
+            (&usb, "idProduct", "0825\n"),
+            (&usb, "product", "Webcam C270\n"),
+            (&usb, "busnum", "1\n"),
+            (&usb, "devnum", "4\n"),
+        ];
+        for (dir, attr, value) in attrs {
+            fs::create_dir_all(dir)?;
+            fs::write(dir.join(attr), value)?;
+        }
+        fs::create_dir_all(root.join("bus/usb/drivers/uvcvideo"))?;
+        fs::create_dir_all(root.join("module/uvcvideo"))?;
+        fs::create_dir_all(&intf)?;
+        symlink(
+            "../../../../../../bus/usb/drivers/uvcvideo",
+            intf.join("driver"),
+        )?;
+        symlink(
+            "../../../../module/uvcvideo",
+            root.join("bus/usb/drivers/uvcvideo/module"),
+        )?;
+
+        let platform = root.join("devices/platform/vivid.0");
+        fs::create_dir_all(&platform)?;
+
+        let class = root.join("class/video4linux");
+        fs::create_dir_all(&class)?;
+        let virtual_ = root.join("devices/virtual");
+        let nodes = [
+            (&intf, "video0", "C270 HD WEBCAM", 0, "81:0"),
+            (&intf, "video1", "C270 HD WEBCAM", 1, "81:1"),
+            (&platform, "v4l-subdev0", "vivid", 0, "81:12"),
+            (&virtual_, "video5", "Dummy video device", 0, "81:5"),
+        ];
+        for (device, name, card, index, dev) in nodes {
+            let node = device.join("video4linux").join(name);
+            fs::create_dir_all(&node)?;
+            fs::write(node.join("name"), format!("{card}\n"))?;
+            fs::write(node.join("index"), format!("{index}\n"))?;
+            fs::write(node.join("dev"), format!("{dev}\n"))?;
+            fs::write(node.join("uevent"), format!("MAJOR=81\nDEVNAME={name}\n"))?;
+            if *device != virtual_ {
+                symlink("../..", node.join("device"))?;
+            }
+            let target = Path::new("../../").join(node.strip_prefix(root).unwrap());
+            symlink(target, class.join(name))?;
+        }
+
+        let media = intf.join("media0");
+        let bus = root.join("bus/media/devices");
+        fs::create_dir_all(&media)?;
+        fs::create_dir_all(&bus)?;
+        fs::write(media.join("model"), "C270 HD WEBCAM\n")?;
+        fs::write(media.join("dev"), "239:0\n")?;
+        fs::write(media.join("uevent"), "MAJOR=239\nDEVNAME=media0\n")?;
+        symlink("..", media.join("device"))?;
+        let target = Path::new("../../../").join(media.strip_prefix(root).unwrap());
+        symlink(target, bus.join("media0"))?;
+        Ok(())
+    }
+
+    /// Answers for the video nodes of [`fake_sysfs`] without opening them.
+    fn probe(path: &Path) -> io::Result<Capabilities> {
+        let (caps, bus_info) = match path.file_name().unwrap().to_str().unwrap() {
+            "video0" => (CapabilityFlags::VIDEO_CAPTURE, "usb-0000:00:14.0-2"),
+            "video1" => (CapabilityFlags::META_CAPTURE, "usb-0000:00:14.0-2"),
+            "video5" => (CapabilityFlags::VIDEO_OUTPUT, "platform:v4l2loopback-000"),
+            _ => return Err(Errno::ENOENT.into()),
+        };
+        MockDevice::new("mock", "mock", caps)
+            .with_bus_info(bus_info)
+            .into_device()?
+            .capabilities()
+    }
+
+    #[test]
+    fn enumerate() -> io::Result<()> {
+        let tmp = TempDir::new("sysfs")?;
+        let root = tmp.path();
+        fake_sysfs(root)?;
+
+        let nodes = Enumerator::new()
+            .sysfs_root(root)
+            .dev_root("/fake/dev")
+            .nodes()?;
+        let names = nodes.iter().map(|node| node.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["video0", "video1", "video5", "v4l-subdev0"]);
+
+        let meta = &nodes[1];
+        assert_eq!(meta.card(), "C270 HD WEBCAM");
+        assert_eq!(meta.index(), Some(1));
+        assert_eq!(meta.dev(), (81, 1));
+        assert_eq!(meta.dev_path(), Path::new("/fake/dev/video1"));
+        assert_eq!(meta.driver(), Some("uvcvideo"));
+        assert_eq!(meta.module(), Some("uvcvideo"));
+        let usb = meta.usb_device().unwrap();
+        assert_eq!((usb.vendor_id(), usb.product_id()), (0x046d, 0x0825));
+        assert_eq!((usb.bus_num(), usb.dev_num()), (1, 4));
+        assert_eq!(usb.to_string(), "046d:0825 Webcam C270");
+
+        let subdev = &nodes[3];
+        assert!(subdev.usb_device().is_none());
+        assert!(subdev.driver().is_none());
+        assert!(subdev.device_path().unwrap().ends_with("platform/vivid.0"));
+        assert!(nodes[2].device_path().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn physical_devices() -> io::Result<()> {
+        let tmp = TempDir::new("sysfs-group")?;
+        let root = tmp.path();
+        fake_sysfs(root)?;
+
+        let enumerator = Enumerator {
+            probe,
+            ..Enumerator::new().sysfs_root(root).dev_root("/fake/dev")
+        };
+        let devices = enumerator.physical_devices()?;
+        assert_eq!(devices.len(), 3);
+
+        let camera = &devices[0];
+        let roles = camera
+            .nodes()
+            .iter()
+            .map(|(node, role)| (node.name().to_str().unwrap(), *role))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            roles,
+            [
+                ("video0", NodeRole::VideoCapture),
+                ("video1", NodeRole::MetaCapture),
+                ("media0", NodeRole::Media),
+            ]
+        );
+        assert_eq!(camera.card(), "C270 HD WEBCAM");
+        assert_eq!(camera.bus_info(), Some("usb-0000:00:14.0-2"));
+        assert_eq!(camera.usb_device().unwrap().product_id(), 0x0825);
+        let meta = camera.node(NodeRole::MetaCapture).unwrap();
+        assert_eq!(meta.dev_path(), Path::new("/fake/dev/video1"));
+        assert!(camera.node(NodeRole::VideoOutput).is_none());
+        let err = camera.open(NodeRole::VideoOutput).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::ENODEV as i32));
+
+        let loopback = &devices[1];
+        assert!(loopback.sys_path().is_none());
+        assert_eq!(loopback.bus_info(), Some("platform:v4l2loopback-000"));
+        assert_eq!(loopback.nodes()[0].1, NodeRole::VideoOutput);
+
+        let vivid = &devices[2];
+        assert!(vivid.sys_path().unwrap().ends_with("platform/vivid.0"));
+        assert_eq!(vivid.nodes()[0].1, NodeRole::Subdev);
+        assert_eq!(vivid.card(), "vivid");
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A fresh, empty temporary directory that is removed (with its contents) when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a temporary directory whose name starts with `linuxvideo-{name}`.
+    ///
+    /// The name also contains the process ID and a counter, so that concurrently running tests
+    /// (and test processes) get distinct directories.
+    pub fn new(name: &str) -> io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("linuxvideo-{name}-{}-{n}", process::id()));
+        // Remove leftovers of a previous process that had the same ID.
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
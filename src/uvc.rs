@@ -89,7 +89,7 @@ pub struct ExtensionUnit<'a> {
 
 impl<'a> ExtensionUnit<'a> {
     fn fd(&self) -> RawFd {
-        self.device.file.as_raw_fd()
+        self.device.as_raw_fd()
     }
 
     pub fn control_info(&self, selector: u8) -> io::Result<ControlInfo> {
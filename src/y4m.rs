@@ -0,0 +1,628 @@
+//! Reading and writing raw video in the YUV4MPEG2 (Y4M) format.
+//!
+//! Y4M files consist of a header line describing the video, followed by frames of planar YUV data.
+//! They can be played back and converted by most video tools (like `ffmpeg` and `mpv`), which
+//! makes them useful for recording frames captured from a [`ReadStream`] for offline analysis,
+//! and for feeding recorded clips to a [`WriteStream`] (for example, of a `v4l2loopback` device).
+//!
+//! [`Y4mWriter`] converts frames from a V4L2 pixel format to the planar Y4M layout, and
+//! [`Y4mReader`] does the reverse. Both take the line padding of the V4L2 format into account.
+//! The following pixel formats are supported:
+//!
+//! | V4L2 pixel format                                        | Y4M colorspace |
+//! |----------------------------------------------------------|----------------|
+//! | [`YUYV`][PixelFormat::YUYV], [`UYVY`][PixelFormat::UYVY] | `C422`         |
+//! | [`YU12`][PixelFormat::YU12], [`NV12`][PixelFormat::NV12] | `C420jpeg`     |
+//! | [`GREY`][PixelFormat::GREY]                              | `Cmono`        |
+//!
+//! [`ReadStream`]: crate::stream::ReadStream
+//! [`WriteStream`]: crate::stream::WriteStream
+
+use std::io::{self, BufRead, Read, Write};
+use std::str;
+
+use nix::errno::Errno;
+
+use crate::format::{PixFormat, PixelFormat, Quantization};
+use crate::{Field, Fract};
+
+const MAGIC: &str = "YUV4MPEG2";
+const FRAME_MAGIC: &[u8] = b"FRAME";
+
+/// Maximum length of header lines, to avoid reading unbounded amounts of data from corrupt files.
+const MAX_LINE_LEN: u64 = 1024;
+
+/// Writes frames to a Y4M file.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    layout: Layout,
+    /// Reused buffer holding the planar data of a frame.
+    planar: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Creates a writer for frames of the given format, and writes the file header.
+    ///
+    /// `format` is typically the format negotiated with the capture device, and `frame_interval`
+    /// the interval returned by [`ReadStream::frame_interval`]. The field order and quantization
+    /// range of `format` are recorded in the header.
+    ///
+    /// Returns an `EINVAL` error if the pixel format or field order cannot be represented in a Y4M
+    /// file.
+    ///
+    /// [`ReadStream::frame_interval`]: crate::stream::ReadStream::frame_interval
+    pub fn new(mut writer: W, format: &PixFormat, frame_interval: Fract) -> io::Result<Self> {
+        let layout = Layout::new(format)?;
+        let interlacing = match format.field() {
+            Field::ANY | Field::NONE => 'p',
+            Field::INTERLACED_TB => 't',
+            Field::INTERLACED_BT => 'b',
+            // The field order depends on the video standard.
+            Field::INTERLACED => '?',
+            _ => return Err(Errno::EINVAL.into()),
+        };
+
+        // The frame rate is the inverse of the frame interval.
+        write!(
+            writer,
+            "{MAGIC} W{} H{} F{}:{} I{interlacing} C{}",
+            layout.width,
+            layout.height,
+            frame_interval.denominator(),
+            frame_interval.numerator(),
+            layout.chroma.tag(),
+        )?;
+        match format.quantization() {
+            Quantization::FULL_RANGE => write!(writer, " XCOLORRANGE=FULL")?,
+            Quantization::LIM_RANGE => write!(writer, " XCOLORRANGE=LIMITED")?,
+            _ => {}
+        }
+        writeln!(writer)?;
+
+        Ok(Self {
+            writer,
+            layout,
+            planar: Vec::new(),
+        })
+    }
+
+    /// Writes a frame.
+    ///
+    /// `data` is the contents of a buffer using the format passed to [`Y4mWriter::new`], like a
+    /// dequeued [`ReadBufferView`][crate::stream::ReadBufferView]. Returns an `EINVAL` error if it
+    /// is too small to contain a frame.
+    pub fn write_frame(&mut self, data: &[u8]) -> io::Result<()> {
+        self.layout.copy_to_planar(data, &mut self.planar)?;
+        self.writer.write_all(FRAME_MAGIC)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.write_all(&self.planar)
+    }
+
+    /// Returns a reference to the underlying writer.
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying writer.
+    ///
+    /// Buffered writers should be flushed after the last frame was written.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads frames from a Y4M file.
+///
+/// Files should be wrapped in a [`BufReader`][io::BufReader].
+pub struct Y4mReader<R: BufRead> {
+    reader: R,
+    width: u32,
+    height: u32,
+    frame_interval: Option<Fract>,
+    field: Field,
+    quantization: Quantization,
+    chroma: Chroma,
+    /// Reused buffer holding the planar data of a frame.
+    planar: Vec<u8>,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    /// Reads and parses the file header.
+    ///
+    /// Returns an `InvalidData` error if the header is malformed, and an `EINVAL` error if it uses
+    /// a colorspace that is not supported.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let line = read_line(&mut reader)?.ok_or_else(|| invalid_data("missing Y4M header"))?;
+        let line = str::from_utf8(&line).map_err(|_| invalid_data("invalid Y4M header"))?;
+
+        let mut params = line.split(' ');
+        if params.next() != Some(MAGIC) {
+            return Err(invalid_data("not a Y4M file"));
+        }
+
+        let mut width = None;
+        let mut height = None;
+        let mut frame_interval = None;
+        let mut field = Field::NONE;
+        let mut quantization = Quantization::DEFAULT;
+        let mut chroma = Chroma::C420;
+        for param in params {
+            let Some(tag) = param.chars().next() else {
+                continue;
+            };
+            let value = &param[tag.len_utf8()..];
+            match tag {
+                'W' => width = value.parse().ok(),
+                'H' => height = value.parse().ok(),
+                'F' => {
+                    let (num, den) = parse_ratio(value)?;
+                    // A frame rate of 0 means it is unknown.
+                    frame_interval = (num != 0 && den != 0).then(|| Fract::new(den, num));
+                }
+                'I' => {
+                    field = match value {
+                        "p" => Field::NONE,
+                        "t" => Field::INTERLACED_TB,
+                        "b" => Field::INTERLACED_BT,
+                        _ => Field::INTERLACED,
+                    }
+                }
+                'C' => chroma = Chroma::parse(value).ok_or(Errno::EINVAL)?,
+                'X' => match value {
+                    "COLORRANGE=FULL" => quantization = Quantization::FULL_RANGE,
+                    "COLORRANGE=LIMITED" => quantization = Quantization::LIM_RANGE,
+                    _ => {}
+                },
+                // The pixel aspect ratio is not needed.
+                _ => {}
+            }
+        }
+
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(invalid_data("missing Y4M frame size"));
+        };
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            frame_interval,
+            field,
+            quantization,
+            chroma,
+            planar: Vec::new(),
+        })
+    }
+
+    /// Returns the width of the frames in pixels.
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the frames in pixels.
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the interval between frames, or `None` if the file does not specify a frame rate.
+    #[inline]
+    pub fn frame_interval(&self) -> Option<Fract> {
+        self.frame_interval
+    }
+
+    /// Returns the field order of the frames.
+    #[inline]
+    pub fn field(&self) -> Field {
+        self.field
+    }
+
+    /// Returns the quantization range of the frames.
+    ///
+    /// Returns [`Quantization::DEFAULT`] if the file does not specify it.
+    #[inline]
+    pub fn quantization(&self) -> Quantization {
+        self.quantization
+    }
+
+    /// Returns a format suitable for configuring a [`WriteStream`] to play back this file.
+    ///
+    /// This uses [`PixelFormat::YUYV`] for 4:2:2 files, [`PixelFormat::YU12`] for 4:2:0 files and
+    /// [`PixelFormat::GREY`] for greyscale files. Any other pixel format with the same chroma
+    /// subsampling can be passed to [`Y4mReader::read_frame`] as well.
+    ///
+    /// [`WriteStream`]: crate::stream::WriteStream
+    pub fn pix_format(&self) -> PixFormat {
+        let mut format = PixFormat::new(self.width, self.height, self.chroma.pixel_format());
+        format.set_field(self.field);
+        if self.quantization != Quantization::DEFAULT {
+            format.set_quantization(self.quantization);
+        }
+        format
+    }
+
+    /// Reads the next frame into `buf`, converting it to `format`.
+    ///
+    /// `format` is typically the format negotiated with the output device, and `buf` a
+    /// [`WriteBufferView`][crate::stream::WriteBufferView] of it. Line padding in `buf` is left
+    /// untouched.
+    ///
+    /// Returns the number of bytes of `buf` that contain the frame, or `None` at the end of the
+    /// file. Returns an `EINVAL` error if `format` has a different size or chroma subsampling than
+    /// the file, or if `buf` is too small to contain a frame.
+    pub fn read_frame(&mut self, format: &PixFormat, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let layout = Layout::new(format)?;
+        if layout.chroma != self.chroma
+            || layout.width != self.width as usize
+            || layout.height != self.height as usize
+            || buf.len() < layout.image_size()
+        {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let Some(line) = read_line(&mut self.reader)? else {
+            return Ok(None);
+        };
+        if !line.starts_with(FRAME_MAGIC) {
+            return Err(invalid_data("missing Y4M frame header"));
+        }
+
+        self.planar.resize(layout.planar_size(), 0);
+        self.reader.read_exact(&mut self.planar)?;
+        layout.copy_from_planar(&self.planar, buf);
+
+        Ok(Some(layout.image_size()))
+    }
+
+    /// Returns the underlying reader.
+    #[inline]
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a header line, without the terminating newline.
+///
+/// Returns `None` at the end of the file.
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    reader.take(MAX_LINE_LEN).read_until(b'\n', &mut line)?;
+    match line.pop() {
+        None => Ok(None),
+        Some(b'\n') => Ok(Some(line)),
+        Some(_) => Err(invalid_data("unterminated Y4M header")),
+    }
+}
+
+fn parse_ratio(s: &str) -> io::Result<(u32, u32)> {
+    s.split_once(':')
+        .and_then(|(num, den)| Some((num.parse().ok()?, den.parse().ok()?)))
+        .ok_or_else(|| invalid_data("invalid Y4M ratio"))
+}
+
+/// Chroma subsampling of a Y4M file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chroma {
+    C420,
+    C422,
+    Mono,
+}
+
+impl Chroma {
+    fn of(pixel_format: PixelFormat) -> io::Result<Self> {
+        match pixel_format {
+            PixelFormat::YU12 | PixelFormat::NV12 => Ok(Self::C420),
+            PixelFormat::YUYV | PixelFormat::UYVY => Ok(Self::C422),
+            PixelFormat::GREY => Ok(Self::Mono),
+            _ => Err(Errno::EINVAL.into()),
+        }
+    }
+
+    fn parse(tag: &str) -> Option<Self> {
+        // The 4:2:0 variants only differ in chroma siting, which V4L2 does not describe.
+        match tag {
+            "420" | "420jpeg" | "420mpeg2" | "420paldv" => Some(Self::C420),
+            "422" => Some(Self::C422),
+            "mono" => Some(Self::Mono),
+            _ => None,
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Self::C420 => "420jpeg",
+            Self::C422 => "422",
+            Self::Mono => "mono",
+        }
+    }
+
+    fn pixel_format(self) -> PixelFormat {
+        match self {
+            Self::C420 => PixelFormat::YU12,
+            Self::C422 => PixelFormat::YUYV,
+            Self::Mono => PixelFormat::GREY,
+        }
+    }
+}
+
+/// Location of the samples of one Y4M plane in a V4L2 buffer.
+///
+/// Each of the `rows` lines starts `stride` bytes after the previous one, and contains a sample
+/// every `step` bytes, starting at `offset`, until `len` bytes into the line.
+#[derive(Clone, Copy)]
+struct Plane {
+    start: usize,
+    stride: usize,
+    rows: usize,
+    len: usize,
+    offset: usize,
+    step: usize,
+}
+
+impl Plane {
+    fn lines<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let Self {
+            start, stride, len, ..
+        } = *self;
+        (0..self.rows).map(move |row| &data[start + row * stride..][..len])
+    }
+
+    fn lines_mut<'a>(&self, data: &'a mut [u8]) -> impl Iterator<Item = &'a mut [u8]> + 'a {
+        let Self { stride, len, .. } = *self;
+        data[self.start..]
+            .chunks_mut(stride)
+            .take(self.rows)
+            .map(move |line| &mut line[..len])
+    }
+}
+
+/// Memory layout of a frame in a V4L2 buffer.
+struct Layout {
+    pixel_format: PixelFormat,
+    chroma: Chroma,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl Layout {
+    fn new(format: &PixFormat) -> io::Result<Self> {
+        let pixel_format = format.pixel_format();
+        let chroma = Chroma::of(pixel_format)?;
+        let width = format.width() as usize;
+        let height = format.height() as usize;
+
+        // Subsampled formats are only defined for even sizes.
+        let odd_height = chroma == Chroma::C420 && height % 2 == 1;
+        if chroma != Chroma::Mono && width % 2 == 1 || odd_height {
+            return Err(Errno::EINVAL.into());
+        }
+
+        let min_stride = match chroma {
+            Chroma::C422 => width * 2,
+            _ => width,
+        };
+        let stride = match format.bytes_per_line() as usize {
+            0 => min_stride,
+            stride if stride < min_stride => return Err(Errno::EINVAL.into()),
+            stride => stride,
+        };
+
+        Ok(Self {
+            pixel_format,
+            chroma,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    /// Returns the size of a frame in the V4L2 buffer, including line padding.
+    fn image_size(&self) -> usize {
+        let luma = self.stride * self.height;
+        match self.pixel_format {
+            // The chroma planes use half the stride of the luma plane.
+            PixelFormat::YU12 => luma * 3 / 2,
+            PixelFormat::NV12 => luma + self.stride * self.height / 2,
+            _ => luma,
+        }
+    }
+
+    /// Returns the size of a frame in the Y4M file.
+    fn planar_size(&self) -> usize {
+        let luma = self.width * self.height;
+        match self.chroma {
+            Chroma::C420 => luma * 3 / 2,
+            Chroma::C422 => luma * 2,
+            Chroma::Mono => luma,
+        }
+    }
+
+    /// Returns the locations of the Y, U and V planes, in that order.
+    ///
+    /// The chroma planes of greyscale formats are empty.
+    fn planes(&self) -> [Plane; 3] {
+        let (width, height, stride) = (self.width, self.height, self.stride);
+        let plane = |start, stride, rows, len, offset, step| Plane {
+            start,
+            stride,
+            rows,
+            len,
+            offset,
+            step,
+        };
+        let luma = plane(0, stride, height, width, 0, 1);
+        let empty = plane(0, 1, 0, 0, 0, 1);
+        let chroma_start = stride * height;
+
+        match self.pixel_format {
+            PixelFormat::YUYV => [
+                plane(0, stride, height, width * 2, 0, 2),
+                plane(0, stride, height, width * 2, 1, 4),
+                plane(0, stride, height, width * 2, 3, 4),
+            ],
+            PixelFormat::UYVY => [
+                plane(0, stride, height, width * 2, 1, 2),
+                plane(0, stride, height, width * 2, 0, 4),
+                plane(0, stride, height, width * 2, 2, 4),
+            ],
+            PixelFormat::YU12 => {
+                let chroma_size = stride / 2 * height / 2;
+                [
+                    luma,
+                    plane(chroma_start, stride / 2, height / 2, width / 2, 0, 1),
+                    plane(
+                        chroma_start + chroma_size,
+                        stride / 2,
+                        height / 2,
+                        width / 2,
+                        0,
+                        1,
+                    ),
+                ]
+            }
+            PixelFormat::NV12 => [
+                luma,
+                plane(chroma_start, stride, height / 2, width, 0, 2),
+                plane(chroma_start, stride, height / 2, width, 1, 2),
+            ],
+            _ => [luma, empty, empty],
+        }
+    }
+
+    /// Copies the samples of a frame in `data` to `planar`, one plane after the other.
+    fn copy_to_planar(&self, data: &[u8], planar: &mut Vec<u8>) -> io::Result<()> {
+        if data.len() < self.image_size() {
+            return Err(Errno::EINVAL.into());
+        }
+
+        planar.clear();
+        for plane in self.planes() {
+            for line in plane.lines(data) {
+                planar.extend(line.iter().skip(plane.offset).step_by(plane.step));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the planar samples in `planar` to their locations in `data`.
+    fn copy_from_planar(&self, planar: &[u8], data: &mut [u8]) {
+        let mut samples = planar.iter();
+        for plane in self.planes() {
+            for line in plane.lines_mut(data) {
+                for (dest, src) in line
+                    .iter_mut()
+                    .skip(plane.offset)
+                    .step_by(plane.step)
+                    .zip(&mut samples)
+                {
+                    *dest = *src;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn format(pixel_format: PixelFormat, bytes_per_line: u32) -> PixFormat {
+        let mut format = PixFormat::new(4, 2, pixel_format);
+        format.set_bytes_per_line(bytes_per_line);
+        format
+    }
+
+    #[test]
+    fn round_trip() {
+        // 4x2 YUYV frame with 2 bytes of padding per line.
+        #[rustfmt::skip]
+        let yuyv = [
+            0x10, 0x80, 0x11, 0x81, 0x12, 0x82, 0x13, 0x83, 0xee, 0xee,
+            0x20, 0x90, 0x21, 0x91, 0x22, 0x92, 0x23, 0x93, 0xee, 0xee,
+        ];
+        let mut yuyv_format = format(PixelFormat::YUYV, 10);
+        yuyv_format.set_field(Field::INTERLACED_TB);
+        yuyv_format.set_quantization(Quantization::FULL_RANGE);
+
+        let mut writer = Y4mWriter::new(Vec::new(), &yuyv_format, Fract::new(1, 30)).unwrap();
+        writer.write_frame(&yuyv).unwrap();
+        let file = writer.into_inner();
+
+        #[rustfmt::skip]
+        let expected = [
+            // Y
+            0x10, 0x11, 0x12, 0x13,
+            0x20, 0x21, 0x22, 0x23,
+            // U
+            0x80, 0x82,
+            0x90, 0x92,
+            // V
+            0x81, 0x83,
+            0x91, 0x93,
+        ];
+        let header = b"YUV4MPEG2 W4 H2 F30:1 It C422 XCOLORRANGE=FULL\nFRAME\n";
+        assert_eq!(&file[..header.len()], header);
+        assert_eq!(&file[header.len()..], expected);
+
+        let mut reader = Y4mReader::new(Cursor::new(&file)).unwrap();
+        assert_eq!((reader.width(), reader.height()), (4, 2));
+        assert_eq!(reader.frame_interval().unwrap().denominator(), 30);
+        assert_eq!(reader.field(), Field::INTERLACED_TB);
+        assert_eq!(reader.quantization(), Quantization::FULL_RANGE);
+        assert_eq!(reader.pix_format().pixel_format(), PixelFormat::YUYV);
+
+        let mut buf = [0xee; 20];
+        assert_eq!(reader.read_frame(&yuyv_format, &mut buf).unwrap(), Some(20));
+        assert_eq!(buf, yuyv);
+        assert_eq!(reader.read_frame(&yuyv_format, &mut buf).unwrap(), None);
+
+        // Converting between 4:2:0 formats, from NV12 without padding to YU12 with padding.
+        #[rustfmt::skip]
+        let nv12 = [
+            0x10, 0x11, 0x12, 0x13,
+            0x20, 0x21, 0x22, 0x23,
+            0x80, 0x81, 0x82, 0x83,
+        ];
+        let mut writer = Y4mWriter::new(
+            Vec::new(),
+            &format(PixelFormat::NV12, 0),
+            Fract::new(1001, 30000),
+        )
+        .unwrap();
+        writer.write_frame(&nv12).unwrap();
+        let file = writer.into_inner();
+
+        let mut reader = Y4mReader::new(Cursor::new(&file)).unwrap();
+        assert_eq!(reader.frame_interval().unwrap().numerator(), 1001);
+        let mut buf = [0xee; 18];
+        assert_eq!(
+            reader
+                .read_frame(&format(PixelFormat::YU12, 6), &mut buf)
+                .unwrap(),
+            Some(18)
+        );
+        #[rustfmt::skip]
+        assert_eq!(buf, [
+            0x10, 0x11, 0x12, 0x13, 0xee, 0xee,
+            0x20, 0x21, 0x22, 0x23, 0xee, 0xee,
+            0x80, 0x82, 0xee,
+            0x81, 0x83, 0xee,
+        ]);
+    }
+}